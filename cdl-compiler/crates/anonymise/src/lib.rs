@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use lexer::{lex_borrowed, BorrowedToken, TokenKind};
+use serde::Serialize;
+
+/// A name, string or hub number and what it was replaced with.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rename {
+  pub original: String,
+  pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Anonymised {
+  pub text: String,
+  /// In order of first use. Keep it private, it maps the script back to the original.
+  pub renames: Vec<Rename>,
+}
+
+/// Replaces everything customer specific in `text` while keeping its structure, so the result
+/// parses and processes the same way.
+///
+/// Entity idents, references, table aliases, table names and variables get generated names, the
+/// same name is always replaced the same way. Strings become placeholders, `hub:` numbers are
+/// scrambled and comments are emptied. Entity types, property names and functions are kept, as is
+/// all whitespace, so line numbers in errors still line up.
+pub fn anonymise(text: &str) -> Result<Anonymised> {
+  let tokens = lex_borrowed(text)?;
+  let mut anonymiser = Anonymiser::default();
+  let mut replacements = vec![];
+  for token in &tokens {
+    match token.kind {
+      TokenKind::LineComment => replacements.push((token.pos.clone(), "//".to_string())),
+      TokenKind::MultiLineComment => {
+        let lines = "\n".repeat(token.text.unwrap_or_default().matches('\n').count());
+        replacements.push((token.pos.clone(), format!("/*{}*/", lines)))
+      }
+      _ => {}
+    }
+  }
+  let tokens = tokens
+    .into_iter()
+    .filter(|t| !matches!(t.kind, TokenKind::LineComment | TokenKind::MultiLineComment))
+    .collect::<Vec<_>>();
+  let property_colons = property_colons(&tokens);
+  for (index, token) in tokens.iter().enumerate() {
+    let text = token.text.unwrap_or_default();
+    if property_colons.get(index + 1) == Some(&true) {
+      anonymiser.properties.insert(text.to_string());
+    } else if is_ident(&tokens, index) {
+      anonymiser.idents.insert(text.to_string());
+    }
+  }
+  for (index, token) in tokens.iter().enumerate() {
+    let text = token.text.unwrap_or_default();
+    let before = |n: usize| index.checked_sub(n).map(|i| &tokens[i]);
+    let kind_before = |n: usize| before(n).map(|t| &t.kind);
+    let after = tokens.get(index + 1).map(|t| &t.kind);
+    let vpath_colon = kind_before(1) == Some(&TokenKind::Colon) && !property_colons[index - 1];
+    let replacement = match token.kind {
+      TokenKind::Identifier if is_ident(&tokens, index) => Some(anonymiser.ident(text)),
+      TokenKind::Identifier if is_text(before(1), "table") && after == Some(&TokenKind::Equal) => {
+        Some(anonymiser.name(text, "t"))
+      }
+      TokenKind::Identifier
+        if kind_before(1) == Some(&TokenKind::Equal)
+          && kind_before(2) == Some(&TokenKind::Identifier)
+          && is_text(before(3), "table") =>
+      {
+        Some(anonymiser.dotted(text, "t"))
+      }
+      TokenKind::Identifier if after == Some(&TokenKind::Colon) && !property_colons[index + 1] => {
+        Some(anonymiser.dotted(text, "t"))
+      }
+      TokenKind::Identifier if vpath_colon && after != Some(&TokenKind::ParenOpen) => {
+        Some(anonymiser.name(text, "v"))
+      }
+      TokenKind::Reference => Some(format!("@{}", anonymiser.reference(text))),
+      TokenKind::HierarchyReference => Some(format!("^{}", anonymiser.reference(text))),
+      TokenKind::String => anonymiser.string(text),
+      TokenKind::Number(_)
+        if kind_before(1) == Some(&TokenKind::Colon)
+          && property_colons[index - 1]
+          && is_text(before(2), "hub") =>
+      {
+        Some(anonymiser.hub(text))
+      }
+      _ => None,
+    };
+    if let Some(replacement) = replacement {
+      replacements.push((token.pos.clone(), replacement));
+    }
+  }
+
+  replacements.sort_by_key(|(range, _)| range.start);
+  let mut anonymised = text.to_string();
+  for (range, replacement) in replacements.into_iter().rev() {
+    anonymised.replace_range(range, &replacement);
+  }
+  Ok(Anonymised {
+    text: anonymised,
+    renames: anonymiser.renames,
+  })
+}
+
+/// `#ident` and `const ident =`, the names references point to.
+fn is_ident(tokens: &[BorrowedToken], index: usize) -> bool {
+  let before = |n: usize| index.checked_sub(n).map(|i| &tokens[i]);
+  tokens[index].kind == TokenKind::Identifier
+    && (matches!(before(1), Some(t) if t.kind == TokenKind::Hash)
+      || (is_text(before(1), "const")
+        && matches!(tokens.get(index + 1), Some(t) if t.kind == TokenKind::Equal)))
+}
+
+fn is_text(token: Option<&BorrowedToken>, text: &str) -> bool {
+  matches!(token, Some(t) if t.kind == TokenKind::Identifier && t.text == Some(text))
+}
+
+/// Marks the colons ending a property name, the other colons belong to vpaths. Property names
+/// start a line in a block, lists and arguments can only hold values.
+fn property_colons(tokens: &[BorrowedToken]) -> Vec<bool> {
+  let mut colons = vec![false; tokens.len()];
+  let mut nesting = 0usize;
+  for (index, token) in tokens.iter().enumerate() {
+    match token.kind {
+      TokenKind::BracketOpen | TokenKind::ParenOpen => nesting += 1,
+      TokenKind::BracketClose | TokenKind::ParenClose => nesting = nesting.saturating_sub(1),
+      TokenKind::Colon if nesting == 0 && index > 0 => {
+        let starts_line = index == 1
+          || matches!(
+            tokens[index - 2].kind,
+            TokenKind::EOL | TokenKind::BraceOpen
+          );
+        colons[index] = starts_line && tokens[index - 1].kind == TokenKind::Identifier;
+      }
+      _ => {}
+    }
+  }
+  colons
+}
+
+#[derive(Default)]
+struct Anonymiser {
+  /// Declared idents and property names, references can go through both.
+  idents: HashSet<String>,
+  properties: HashSet<String>,
+  names: HashMap<String, String>,
+  counters: HashMap<&'static str, usize>,
+  renames: Vec<Rename>,
+}
+
+impl Anonymiser {
+  /// Names share one mapping whatever their kind, a table alias used as a hierarchy or an ident
+  /// used as a table stays consistent. `prefix` is only used for new names.
+  fn name(&mut self, original: &str, prefix: &'static str) -> String {
+    if original.is_empty() {
+      return String::new();
+    }
+    if let Some(name) = self.names.get(original) {
+      return name.clone();
+    }
+    let counter = self.counters.entry(prefix).or_default();
+    *counter += 1;
+    let name = format!("{}{}", prefix, counter);
+    self.add(original, &name);
+    name
+  }
+
+  fn add(&mut self, original: &str, replacement: &str) {
+    self
+      .names
+      .insert(original.to_string(), replacement.to_string());
+    self.renames.push(Rename {
+      original: original.to_string(),
+      replacement: replacement.to_string(),
+    });
+  }
+
+  /// `custom.Account_2`, every part is renamed on its own.
+  fn dotted(&mut self, original: &str, prefix: &'static str) -> String {
+    original
+      .split('.')
+      .map(|part| self.name(part, prefix))
+      .collect::<Vec<_>>()
+      .join(".")
+  }
+
+  /// Idents that are also used as property names are kept, a reference can't tell them apart.
+  fn ident(&mut self, original: &str) -> String {
+    if self.properties.contains(original) {
+      return original.to_string();
+    }
+    self.name(original, "id")
+  }
+
+  /// `ident.ident.property`, the first part and the parts naming declared idents are renamed.
+  fn reference(&mut self, original: &str) -> String {
+    original
+      .split('.')
+      .enumerate()
+      .map(
+        |(index, part)| match index == 0 || self.idents.contains(part) {
+          true => self.ident(part),
+          false => part.to_string(),
+        },
+      )
+      .collect::<Vec<_>>()
+      .join(".")
+  }
+
+  /// Keeps the quotes of the literal, and keeps empty strings empty.
+  fn string(&mut self, literal: &str) -> Option<String> {
+    let end = literal.len() - 1;
+    let (open, close) = if literal.len() >= 6 && literal.starts_with("\"\"\"") {
+      (&literal[..3], &literal[end - 2..])
+    } else if literal.starts_with('r') {
+      (&literal[..2], &literal[end..])
+    } else {
+      (&literal[..1], &literal[end..])
+    };
+    if literal.len() == open.len() + close.len() {
+      return None;
+    }
+    // multiline strings keep their line breaks, for the line numbers
+    let lines = "\n".repeat(literal.matches('\n').count());
+    let placeholder = self.name(literal, "s");
+    Some(format!("{}{}{}{}", open, placeholder, lines, close))
+  }
+
+  /// Another number with the same count of digits.
+  fn hub(&mut self, original: &str) -> String {
+    let key = format!("hub:{}", original);
+    if let Some(hub) = self.names.get(&key) {
+      return hub.clone();
+    }
+    let counter = self.counters.entry("hub").or_default();
+    *counter += 1;
+    let digits = original.trim_start_matches('-').len().max(1) as u32;
+    let hub = (10u64.saturating_pow(digits - 1) + *counter as u64).to_string();
+    self.names.insert(key, hub.clone());
+    self.renames.push(Rename {
+      original: original.to_string(),
+      replacement: hub.clone(),
+    });
+    hub
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use node_processing::NodeProcessor;
+
+  #[test]
+  fn renames_consistently() {
+    let text = r#"title "Acme sales"
+config hub {
+  hub: 14900
+  table accounts = custom.Account_2 // secret
+  relation oneToMany #rel {
+    primaryKey: accounts:AccountID
+    foreignKey: p185.response:accountID
+  }
+}
+page #sales @rel {
+  label: "Acme"
+  widget kpi #total {
+    /* about
+       acme */
+    value: max(accounts:AccountID) + @sales.label
+    filter: :region = "North" AND accounts:^regions
+    label: ""
+  }
+  widget kpi #label {
+    value: @sales.total.value * @target
+  }
+}
+const target = 5
+"#;
+    let anonymised = anonymise(text).unwrap();
+    assert_eq!(
+      r#"title "s1"
+config hub {
+  hub: 10001
+  table t1 = t2.t3 //
+  relation oneToMany #id1 {
+    primaryKey: t1:v1
+    foreignKey: t4.t5:v2
+  }
+}
+page #id2 @id1 {
+  label: "s2"
+  widget kpi #id3 {
+    /*
+*/
+    value: max(t1:v1) + @id2.label
+    filter: :v3 = "s3" AND t1:^id4
+    label: ""
+  }
+  widget kpi #label {
+    value: @id2.id3.value * @id5
+  }
+}
+const id5 = 5
+"#,
+      anonymised.text
+    );
+    assert!(anonymised.renames.contains(&Rename {
+      original: "custom".to_string(),
+      replacement: "t2".to_string()
+    }));
+  }
+
+  /// Parse errors, node count and processing errors.
+  fn behaviour(text: &str) -> (Option<usize>, usize) {
+    match parser::parse_text(text) {
+      Ok(ast) => {
+        let nodes = ast.nodes.borrow().len();
+        let (_, error) = NodeProcessor::new(ast).process_partial();
+        (Some(nodes), error.map(|e| e.diagnostics.len()).unwrap_or(0))
+      }
+      Err(_) => (None, 0),
+    }
+  }
+
+  #[test]
+  fn keeps_behaviour_of_test_scripts() {
+    for script in [
+      include_str!("../../../test_script/test.cdl"),
+      include_str!("../../../test_script/workforce.cdl"),
+      include_str!("../../../test_script/canvas_example.cdl"),
+      include_str!("../../../test_script/large.cdl"),
+    ] {
+      let anonymised = anonymise(script).unwrap();
+      assert_eq!(script.lines().count(), anonymised.text.lines().count());
+      assert_eq!(behaviour(script), behaviour(&anonymised.text));
+    }
+  }
+}
@@ -0,0 +1,269 @@
+//! The compiler API shared by the wasm and C bindings. Failures are returned as diagnostics that
+//! carry the location of the problem. Input comes from browsers and other programs, so it is
+//! parsed with the limits of [`ParseOptions::untrusted`].
+
+use std::ops::Range;
+
+use ast::{select_property, Ast, Comments, NodeRef, DEFAULT_WIDTH};
+use codegen::{lower, typescript, References};
+use lexer::{LexError, Location};
+use node_processing::{PassManager, ProcessingReport};
+use parser::{parse_cst_with_options, parse_text_with_options, ParseError, ParseOptions};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub pos: Option<Range<usize>>,
+  pub location: Option<Location>,
+}
+
+impl Diagnostic {
+  pub fn error(message: String) -> Diagnostic {
+    Diagnostic {
+      severity: Severity::Error,
+      message,
+      pos: None,
+      location: None,
+    }
+  }
+
+  fn from_processing(ast: &Ast, diagnostic: &node_processing::Diagnostic) -> Diagnostic {
+    Diagnostic {
+      severity: match diagnostic.severity {
+        node_processing::Severity::Error => Severity::Error,
+        node_processing::Severity::Warning => Severity::Warning,
+      },
+      message: diagnostic.message.clone(),
+      pos: diagnostic.node_ref.map(|n| ast.get_pos_for_node(n)),
+      location: diagnostic
+        .node_ref
+        .and_then(|n| ast.get_location_for_node(n)),
+    }
+  }
+
+  fn from_anyhow(error: &anyhow::Error) -> Diagnostic {
+    let mut diagnostic = Diagnostic::error(format!("{:#}", error));
+    if let Some(lex_error) = error.downcast_ref::<LexError>() {
+      diagnostic.message = lex_error.message.clone();
+      diagnostic.pos = Some(lex_error.pos.clone());
+      diagnostic.location = Some(lex_error.location.clone());
+    } else if let Some(parse_error) = error.downcast_ref::<ParseError>() {
+      diagnostic.message = format!("{:#}", error.root_cause());
+      diagnostic.pos = Some(parse_error.pos.clone());
+      diagnostic.location = Some(parse_error.location.clone());
+    }
+    diagnostic
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Selection {
+  pub node_ref: NodeRef,
+  pub pos: Range<usize>,
+  pub location: Option<Location>,
+  pub cdl: String,
+}
+
+pub fn parse(text: &str) -> Result<Ast, Vec<Diagnostic>> {
+  parse_text_with_options(text, &ParseOptions::untrusted())
+    .map_err(|error| vec![Diagnostic::from_anyhow(&error)])
+}
+
+fn run(text: &str, manager: PassManager) -> Result<(Ast, ProcessingReport), Vec<Diagnostic>> {
+  Ok(manager.run(parse(text)?))
+}
+
+fn report_diagnostics(ast: &Ast, report: &ProcessingReport) -> Vec<Diagnostic> {
+  report
+    .diagnostics()
+    .map(|diagnostic| Diagnostic::from_processing(ast, diagnostic))
+    .collect()
+}
+
+pub fn process(text: &str) -> Result<Ast, Vec<Diagnostic>> {
+  let (ast, report) = run(text, PassManager::new())?;
+  if report.has_errors() {
+    return Err(report_diagnostics(&ast, &report));
+  }
+  Ok(ast)
+}
+
+pub fn diagnostics(text: &str) -> Vec<Diagnostic> {
+  match run(text, PassManager::new()) {
+    Ok((ast, report)) => report_diagnostics(&ast, &report),
+    Err(diagnostics) => diagnostics,
+  }
+}
+
+/// Like [`parse`], with the comments of the script for [`Ast::to_cdl_with_comments`].
+pub fn parse_with_comments(text: &str) -> Result<(Ast, Comments), Vec<Diagnostic>> {
+  let cst = parse_cst_with_options(text, &ParseOptions::untrusted())
+    .map_err(|error| vec![Diagnostic::from_anyhow(&error)])?;
+  let comments = cst.comments();
+  Ok((cst.into_ast(), comments))
+}
+
+/// Formats the script, keeping its comments.
+pub fn format(text: &str) -> Result<String, Vec<Diagnostic>> {
+  let (ast, comments) = parse_with_comments(text)?;
+  ast
+    .to_cdl_with_comments(DEFAULT_WIDTH, &comments)
+    .map_err(|error| vec![Diagnostic::from_anyhow(&error)])
+}
+
+pub fn select(text: &str, property: &str) -> Result<Vec<Selection>, Vec<Diagnostic>> {
+  select_in(&parse(text)?, property)
+}
+
+pub fn select_in(ast: &Ast, property: &str) -> Result<Vec<Selection>, Vec<Diagnostic>> {
+  select_property(ast, property)
+    .into_iter()
+    .map(|node_ref| {
+      let cdl = ast
+        .node_to_cdl(node_ref)
+        .map_err(|error| vec![Diagnostic::from_anyhow(&error)])?;
+      Ok(Selection {
+        node_ref,
+        pos: ast.get_pos_for_node(node_ref),
+        location: ast.get_location_for_node(node_ref),
+        cdl: cdl.trim().to_string(),
+      })
+    })
+    .collect()
+}
+
+pub fn to_json(text: &str) -> Result<String, Vec<Diagnostic>> {
+  let ast = process(text)?;
+  serde_json::to_string(&ast).map_err(|error| vec![Diagnostic::error(error.to_string())])
+}
+
+/// Nested, resolved dashboard model, see [`codegen::lower`].
+pub fn to_model(text: &str, link_references: bool) -> Result<serde_json::Value, Vec<Diagnostic>> {
+  let ast = process(text)?;
+  let references = if link_references {
+    References::Link
+  } else {
+    References::Inline
+  };
+  lower(&ast, references).map_err(|error| vec![Diagnostic::from_anyhow(&error)])
+}
+
+pub fn to_typescript(text: &str) -> Result<String, Vec<Diagnostic>> {
+  Ok(typescript(&to_model(text, false)?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn valid_script_has_no_diagnostics() {
+    assert!(diagnostics("widget kpi #a {\n  label: \"foo\"\n}\n").is_empty());
+  }
+
+  #[test]
+  fn lex_error_has_location() {
+    let diagnostics = diagnostics("widget kpi {\n  label: &\n}\n");
+    assert_eq!(1, diagnostics.len());
+    assert_eq!("Unknown token \"&\"", diagnostics[0].message);
+    assert_eq!(Some(22..23), diagnostics[0].pos);
+    let location = diagnostics[0].location.as_ref().unwrap();
+    assert_eq!((2, 10), (location.start_line, location.start_pos));
+  }
+
+  #[test]
+  fn parse_error_has_location() {
+    let diagnostics = diagnostics("widget kpi {\n  label: \"foo\"\n  : bar\n}");
+    assert_eq!(1, diagnostics.len());
+    let location = diagnostics[0].location.as_ref().unwrap();
+    assert_eq!((3, 3), (location.start_line, location.start_pos));
+  }
+
+  #[test]
+  fn too_deep_nesting_is_a_diagnostic() {
+    let text = format!("widget kpi {{\n  value: {}1\n}}\n", "(".repeat(1000));
+    let diagnostics = diagnostics(&text);
+    assert_eq!(1, diagnostics.len());
+    assert_eq!(
+      "Input is nested deeper than 64 levels",
+      diagnostics[0].message
+    );
+    assert_eq!(2, diagnostics[0].location.as_ref().unwrap().start_line);
+  }
+
+  #[test]
+  fn input_over_the_untrusted_size_is_a_diagnostic() {
+    let max_size = ParseOptions::untrusted().max_size;
+    let diagnostics = diagnostics(&" ".repeat(max_size + 1));
+    assert_eq!(
+      format!("Input is larger than {} bytes", max_size),
+      diagnostics[0].message
+    );
+    assert!(format(&" ".repeat(max_size + 1)).is_err());
+  }
+
+  #[test]
+  fn processing_errors_are_reported() {
+    let diagnostics = diagnostics("widget kpi #first @second {\n}\n");
+    assert_eq!(1, diagnostics.len());
+    assert_eq!(Severity::Error, diagnostics[0].severity);
+    assert_eq!(
+      "Did not find reference @second target for entity #first",
+      diagnostics[0].message
+    );
+    assert_eq!(1, diagnostics[0].location.as_ref().unwrap().start_line);
+  }
+
+  #[test]
+  fn unused_consts_are_warnings() {
+    let diagnostics = diagnostics("const unused = 1\nwidget kpi {\n  label: \"foo\"\n}\n");
+    assert_eq!(1, diagnostics.len());
+    assert_eq!(Severity::Warning, diagnostics[0].severity);
+    assert_eq!("Constant unused is never used", diagnostics[0].message);
+    assert_eq!(Some(0..16), diagnostics[0].pos);
+  }
+
+  #[test]
+  fn can_format() {
+    let cdl = format("title \"t\"\nwidget   kpi {\nlabel:\"foo\"\n}").unwrap();
+    assert_eq!("title \"t\"\nwidget kpi {\n  label: \"foo\"\n}\n", cdl);
+  }
+
+  #[test]
+  fn format_keeps_comments() {
+    assert_eq!("// c\npage {\n}\n", format("// c\npage {\n}\n").unwrap());
+  }
+
+  #[test]
+  fn can_select_properties() {
+    let selections = select("widget kpi {\n  label: \"foo\"\n  size: small\n}\n", "size").unwrap();
+    assert_eq!(1, selections.len());
+    assert_eq!("size: small", selections[0].cdl);
+    assert_eq!(3, selections[0].location.as_ref().unwrap().start_line);
+  }
+
+  #[test]
+  fn can_convert_to_json() {
+    let json = to_json("widget kpi {\n  label: \"foo\"\n}\n").unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!("label", value["nodes"][2]["node_data"]["Property"]["name"]);
+    assert!(to_json("widget kpi {\n  label: &\n}\n").is_err());
+  }
+
+  #[test]
+  fn can_convert_to_model() {
+    let model = to_model("widget kpi #a {\n  label: \"foo\"\n}\n", false).unwrap();
+    assert_eq!("foo", model["entities"][0]["properties"]["label"]);
+    let ts = to_typescript("widget kpi #a {\n  label: \"foo\"\n}\n").unwrap();
+    assert!(ts.contains("export interface WidgetKpi {"));
+  }
+}
@@ -1,20 +1,49 @@
 use anyhow::Result;
-use serde::Serialize;
-use std::{cell::RefCell, fmt::Write, ops::Range, rc::Rc};
+use lexer::{LineIndex, Location};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{cell::RefCell, collections::HashMap, ops::Range, rc::Rc};
 
 use crate::{
-  ast_nodes::Operator, AstBooleanNode, AstColorNode, AstEntityNode, AstFormulaNode,
-  AstFunctionNode, AstIdentifierNode, AstNode, AstNumberNode, AstOperatorNode, AstPropertyNode,
-  AstReferenceNode, AstScriptNode, AstStringNode, AstTableAliasNode, AstTitleNode, AstVPathNode,
-  Node, NodeRef,
+  printer::CdlPrinter, walk, walk_mut, AstNode, Comments, Node, NodeRef, Visitor, VisitorMut, Walk,
 };
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Clone)]
 pub struct Ast {
   pub nodes: RefCell<Vec<Rc<AstNode>>>,
   pub locations: RefCell<Vec<Range<usize>>>,
   pub script_entity: NodeRef,
   processed: RefCell<Vec<bool>>,
+  line_index: Option<Rc<LineIndex>>,
+  expanded_from: RefCell<HashMap<NodeRef, NodeRef>>,
+}
+
+#[derive(Serialize)]
+struct SerializedLocation {
+  start: usize,
+  end: usize,
+  #[serde(flatten)]
+  location: Option<Location>,
+}
+
+impl Serialize for Ast {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let locations = self
+      .locations
+      .borrow()
+      .iter()
+      .map(|range| SerializedLocation {
+        start: range.start,
+        end: range.end,
+        location: self.line_index.as_ref().map(|index| index.location(range)),
+      })
+      .collect::<Vec<_>>();
+    let mut state = serializer.serialize_struct("Ast", 4)?;
+    state.serialize_field("nodes", &self.nodes)?;
+    state.serialize_field("locations", &locations)?;
+    state.serialize_field("script_entity", &self.script_entity)?;
+    state.serialize_field("processed", &self.processed)?;
+    state.end()
+  }
 }
 
 impl Default for Ast {
@@ -30,8 +59,36 @@ impl Ast {
       locations: RefCell::new(Vec::new()),
       script_entity: NodeRef(0),
       processed: RefCell::new(Vec::new()),
+      line_index: None,
+      expanded_from: RefCell::new(HashMap::new()),
     }
   }
+
+  pub fn set_line_index(&mut self, line_index: Rc<LineIndex>) {
+    self.line_index = Some(line_index);
+  }
+
+  pub fn get_line_index(&self) -> Option<Rc<LineIndex>> {
+    self.line_index.clone()
+  }
+
+  pub fn get_location_for_node(&self, node_ref: NodeRef) -> Option<Location> {
+    let line_index = self.line_index.as_ref()?;
+    let locations = self.locations.borrow();
+    let range = locations.get(node_ref.0 as usize)?;
+    Some(line_index.location(range))
+  }
+
+  /// Generated nodes keep the location of the node they were copied from, this records the
+  /// node that caused the copy, like the template instantiation.
+  pub fn set_expanded_from(&self, node_ref: NodeRef, site: NodeRef) {
+    self.expanded_from.borrow_mut().insert(node_ref, site);
+  }
+
+  pub fn get_expanded_from(&self, node_ref: NodeRef) -> Option<NodeRef> {
+    self.expanded_from.borrow().get(&node_ref).copied()
+  }
+
   pub fn get_parent(&self, node_ref: NodeRef) -> Vec<NodeRef> {
     if node_ref == NodeRef(0) {
       vec![]
@@ -58,6 +115,10 @@ impl Ast {
     (nodes.len() - 1).into()
   }
 
+  pub fn replace_node(&self, node_ref: NodeRef, node: AstNode) {
+    self.nodes.borrow_mut()[node_ref.0 as usize] = Rc::new(node);
+  }
+
   pub fn add_child_to_node(&self, parent: NodeRef, child: NodeRef) {
     let nodes = self.nodes.borrow();
     let node = &nodes[parent.0 as usize];
@@ -90,6 +151,11 @@ impl Ast {
     }
   }
 
+  pub fn set_parent_of_node(&self, node_ref: NodeRef, parent: NodeRef) {
+    let nodes = self.nodes.borrow();
+    nodes[node_ref.0 as usize].parent.replace(vec![parent]);
+  }
+
   fn add_parent_to_node(&self, new_child: NodeRef, target_node_ref: NodeRef) {
     let nodes = self.nodes.borrow();
     nodes[new_child.0 as usize]
@@ -114,264 +180,60 @@ impl Ast {
 
   pub fn to_cdl(&self) -> Result<String> {
     let mut cdl = String::new();
-    self.print_node(&mut cdl, self.script_entity, 0)?;
+    CdlPrinter::new(&mut cdl).print(self, self.script_entity)?;
     Ok(cdl)
   }
 
-  fn print_node(
-    &self,
-    cdl: &mut dyn std::fmt::Write,
-    node_ref: NodeRef,
-    indent: usize,
-  ) -> Result<()> {
-    let nodes = self.nodes.borrow();
-    let node_data = &nodes[node_ref.0 as usize].node_data;
-    match node_data {
-      Node::Title(title) => self.title_to_cdl(cdl, title, indent)?,
-      Node::Entity(entity) => self.entity_to_cdl(cdl, entity, node_ref, indent)?,
-      Node::Property(prop) => self.property_to_cdl(cdl, prop, indent)?,
-      Node::Identifier(identifier) => self.identifier_to_cdl(cdl, identifier, indent)?,
-      Node::Script(script) => self.script_to_cdl(cdl, script, indent)?,
-      Node::String(string) => self.string_to_cdl(cdl, string, indent)?,
-      Node::Number(number) => self.number_to_cdl(cdl, number, indent)?,
-      Node::Boolean(boolean) => self.boolean_to_cdl(cdl, boolean, indent)?,
-      Node::VPath(vpath) => self.vpath_to_cdl(cdl, vpath, indent)?,
-      Node::Color(color) => self.color_to_cdl(cdl, color, indent)?,
-      Node::Reference(r) => self.reference_to_cdl(cdl, r, indent)?,
-      Node::Function(func) => self.func_to_cdl(cdl, func, indent)?,
-      Node::Operator(op) => self.op_to_cdl(cdl, op, indent)?,
-      Node::TableAlias(alias) => self.alias_to_cdl(cdl, alias, indent)?,
-      Node::Formula(formula) => self.formula_to_cdl(cdl, formula, indent)?,
-    }
-    Ok(())
-  }
-
-  fn script_to_cdl(
-    &self,
-    cdl: &mut dyn std::fmt::Write,
-    s: &AstScriptNode,
-    indent: usize,
-  ) -> Result<()> {
-    for child in s.children.borrow().iter() {
-      self.print_node(cdl, *child, indent)?;
-    }
-    Ok(())
-  }
-
-  fn title_to_cdl(
-    &self,
-    cdl: &mut dyn std::fmt::Write,
-    title: &AstTitleNode,
-    _indent: usize,
-  ) -> Result<()> {
-    writeln!(cdl, "title: {}", title.title.as_str())?;
-    Ok(())
-  }
-
-  fn entity_to_cdl(
-    &self,
-    cdl: &mut dyn std::fmt::Write,
-    entity: &AstEntityNode,
-    parent_ref: NodeRef,
-    indent: usize,
-  ) -> Result<()> {
-    // let parent_ref = self.get_parent(node_ref);
-    let parent_node = self.get_node(parent_ref).unwrap();
-    let indent_str = if let Node::Property(_) = parent_node.node_data {
-      "".to_string()
-    } else {
-      create_indent(indent)
-    };
-    write!(
-      cdl,
-      "{}{}",
-      indent_str,
-      entity
-        .terms
-        .iter()
-        .map(|t| t.0.clone())
-        .collect::<Vec<Rc<str>>>()
-        .join(" ")
-    )?;
-    if let Some(label) = &entity.label {
-      write!(cdl, " {}", label)?;
-    }
-    for r in &entity.refs {
-      write!(cdl, " @{}", r)?;
-    }
-    if let Some(id) = &entity.ident {
-      write!(cdl, " #{}", id)?;
-    }
-    if let Some(num) = &entity.entity_number {
-      write!(cdl, " {}", num)?;
-    }
-    writeln!(cdl, " {{")?;
-    for child in entity.children.borrow().iter() {
-      self.print_node(cdl, *child, indent + 1)?;
-    }
-    writeln!(cdl, "{}}}", indent_str)?;
-    Ok(())
-  }
-
-  fn property_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    prop: &AstPropertyNode,
-    indent: usize,
-  ) -> Result<()> {
-    let indent_str = create_indent(indent);
-    write!(cdl, "{}{}: ", indent_str, prop.name)?;
-    for child in prop.children.borrow().iter() {
-      self.print_node(cdl, *child, indent)?;
-    }
-    writeln!(cdl)?;
-    Ok(())
-  }
-
-  fn identifier_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    identifier: &AstIdentifierNode,
-    _indent: usize,
-  ) -> Result<()> {
-    write!(cdl, "{}", identifier.identifier)?;
-    Ok(())
-  }
-
-  fn string_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    string: &AstStringNode,
-    _indent: usize,
-  ) -> Result<()> {
-    write!(cdl, "{}", string.text)?;
-    Ok(())
+  pub fn node_to_cdl(&self, node_ref: NodeRef) -> Result<String> {
+    let mut cdl = String::new();
+    CdlPrinter::new(&mut cdl).print(self, node_ref)?;
+    Ok(cdl)
   }
 
-  fn number_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    number: &AstNumberNode,
-    _indent: usize,
-  ) -> Result<()> {
-    write!(cdl, "{}", number.value)?;
-    Ok(())
+  /// Like `to_cdl`, with expressions longer than `width` wrapped over several lines.
+  pub fn to_cdl_with_width(&self, width: usize) -> Result<String> {
+    self.node_to_cdl_with_width(self.script_entity, width)
   }
 
-  fn boolean_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    boolean: &AstBooleanNode,
-    _indent: usize,
-  ) -> Result<()> {
-    write!(cdl, "{}", boolean.get())?;
-    Ok(())
+  pub fn node_to_cdl_with_width(&self, node_ref: NodeRef, width: usize) -> Result<String> {
+    let mut cdl = String::new();
+    CdlPrinter::with_width(&mut cdl, width).print(self, node_ref)?;
+    Ok(cdl)
   }
 
-  fn color_to_cdl(&self, cdl: &mut dyn Write, color: &AstColorNode, _indent: usize) -> Result<()> {
-    write!(cdl, "#{}", color.color)?;
-    Ok(())
+  /// Like `to_cdl_with_width`, with `comments` printed around the statements they belong to.
+  pub fn to_cdl_with_comments(&self, width: usize, comments: &Comments) -> Result<String> {
+    self.node_to_cdl_with_comments(self.script_entity, width, comments)
   }
 
-  fn reference_to_cdl(
+  pub fn node_to_cdl_with_comments(
     &self,
-    cdl: &mut dyn Write,
-    r: &AstReferenceNode,
-    indent: usize,
-  ) -> Result<()> {
-    if r.resolved_node.get() == NodeRef(-1) {
-      write!(cdl, "@{}", r.ident.as_str())?;
-    } else {
-      self.print_node(cdl, r.resolved_node.get(), indent)?;
-    }
-    Ok(())
-  }
-
-  fn vpath_to_cdl(&self, cdl: &mut dyn Write, vpath: &AstVPathNode, _indent: usize) -> Result<()> {
-    if let Some(table) = &vpath.table {
-      write!(cdl, "{}", table)?;
-    }
-    write!(cdl, ":")?;
-
-    if vpath.is_hierarchy {
-      write!(cdl, "^")?;
-    }
-    if let Some(variable) = &vpath.variable {
-      write!(cdl, "{}", variable)?;
-    }
-    if let Some(func) = &vpath.function {
-      write!(cdl, "{}()", func)?;
-    }
-    Ok(())
-  }
-
-  fn func_to_cdl(&self, cdl: &mut dyn Write, func: &AstFunctionNode, indent: usize) -> Result<()> {
-    write!(cdl, "{}(", func.name)?;
-    for child in func.children.borrow().iter() {
-      self.print_node(cdl, *child, indent)?;
-      write!(cdl, ", ")?;
-    }
-    write!(cdl, ")")?;
-    Ok(())
-  }
-
-  fn op_to_cdl(&self, cdl: &mut dyn Write, op: &AstOperatorNode, indent: usize) -> Result<()> {
-    self.print_node(cdl, op.left.get(), indent)?;
-    match op.operator {
-      Operator::Plus => write!(cdl, " + ")?,
-      Operator::Minus => write!(cdl, " - ")?,
-      Operator::Mul => write!(cdl, " * ")?,
-      Operator::Div => write!(cdl, " / ")?,
-      Operator::Equal => write!(cdl, " = ")?,
-      Operator::And => write!(cdl, " AND ")?,
-      Operator::Or => write!(cdl, " OR ")?,
-      Operator::NotEqual => write!(cdl, " != ")?,
-      Operator::LessThan => write!(cdl, " < ")?,
-      Operator::LessThanOrEqual => write!(cdl, " <= ")?,
-      Operator::MoreThan => write!(cdl, " > ")?,
-      Operator::MoreThanOrEqual => write!(cdl, " >= ")?,
-    }
-    self.print_node(cdl, op.right.get(), indent)?;
-    Ok(())
+    node_ref: NodeRef,
+    width: usize,
+    comments: &Comments,
+  ) -> Result<String> {
+    let mut cdl = String::new();
+    CdlPrinter::with_comments(&mut cdl, width, comments).print(self, node_ref)?;
+    Ok(cdl)
   }
 
-  fn alias_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    alias: &AstTableAliasNode,
-    indent: usize,
-  ) -> Result<()> {
-    let indent_str = create_indent(indent);
-    writeln!(cdl, "{}table {} = {}", indent_str, alias.alias, alias.table)?;
-    Ok(())
+  pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) -> Walk {
+    walk(visitor, self, self.script_entity)
   }
 
-  fn formula_to_cdl(
-    &self,
-    cdl: &mut dyn Write,
-    formula: &AstFormulaNode,
-    indent: usize,
-  ) -> Result<()> {
-    write!(cdl, "[")?;
-    for child in &formula.children {
-      self.print_node(cdl, *child, indent)?;
-      write!(cdl, ", ")?;
-    }
-    write!(cdl, "]")?;
-    Ok(())
+  pub fn walk_mut<V: VisitorMut + ?Sized>(&mut self, visitor: &mut V) -> Walk {
+    let script_entity = self.script_entity;
+    walk_mut(visitor, self, script_entity)
   }
 }
 
-fn create_indent(indent_size: usize) -> String {
-  "  ".repeat(indent_size)
-}
-
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::{AstEntityNode, AstNumberNode, AstPropertyNode, AstScriptNode};
 
   #[test]
-  fn can_print_entity_as_prop() {  
+  fn can_print_entity_as_prop() {
     let ast = Ast::new();
     let script = AstNode::new(
       Node::Script(AstScriptNode {
@@ -383,7 +245,7 @@ mod tests {
     let entity = AstNode::new(
       Node::Entity(AstEntityNode {
         children: vec![].into(),
-        terms: vec!["entity".into()].into(),
+        terms: vec!["entity".into()],
         label: None,
         refs: vec![],
         ident: None,
@@ -405,7 +267,7 @@ mod tests {
     let entity_anon = AstNode::new(
       Node::Entity(AstEntityNode {
         children: vec![].into(),
-        terms: vec![].into(),
+        terms: vec![],
         label: None,
         refs: vec![],
         ident: None,
@@ -416,7 +278,13 @@ mod tests {
     let entity_anon_ref = ast.add_node(entity_anon, 0..1);
     ast.add_child_to_node(prop_ref, entity_anon_ref);
 
-    let number = AstNode::new(Node::Number(AstNumberNode { value: 10.0 }), entity_anon_ref);
+    let number = AstNode::new(
+      Node::Number(AstNumberNode {
+        value: 10.0,
+        raw: None,
+      }),
+      entity_anon_ref,
+    );
     let number_ref = ast.add_node(number, 0..1);
     ast.add_child_to_node(entity_anon_ref, number_ref);
     let cdl = ast.to_cdl().unwrap();
@@ -8,8 +8,6 @@ pub struct AstColorNode {
 
 impl AstColorNode {
   pub fn new(color: LexedStr) -> Self {
-    Self {
-      color,
-    }
+    Self { color }
   }
 }
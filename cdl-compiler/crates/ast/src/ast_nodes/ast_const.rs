@@ -0,0 +1,24 @@
+use std::cell::Cell;
+
+use lexer::LexedStr;
+use serde::Serialize;
+
+use crate::NodeRef;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AstConstNode {
+  pub name: LexedStr,
+  pub value: Cell<NodeRef>,
+}
+
+impl AstConstNode {
+  pub fn new(name: LexedStr) -> Self {
+    AstConstNode {
+      name,
+      value: Cell::new(NodeRef(-1)),
+    }
+  }
+  pub(crate) fn add_value(&self, child: NodeRef) {
+    self.value.set(child);
+  }
+}
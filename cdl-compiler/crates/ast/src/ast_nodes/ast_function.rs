@@ -11,7 +11,7 @@ pub struct AstFunctionNode {
   pub children: RefCell<Vec<NodeRef>>,
 }
 impl AstFunctionNode {
-    pub(crate) fn add_argument(&self, child: NodeRef) {
-      self.children.borrow_mut().push(child)
-    }
+  pub(crate) fn add_argument(&self, child: NodeRef) {
+    self.children.borrow_mut().push(child)
+  }
 }
@@ -1,6 +1,8 @@
+use lexer::LexedStr;
 use serde::Serialize;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct AstNumberNode {
   pub value: f64,
+  pub raw: Option<LexedStr>,
 }
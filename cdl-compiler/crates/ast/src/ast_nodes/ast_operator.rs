@@ -4,12 +4,14 @@ use serde::Serialize;
 
 use crate::NodeRef;
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
 pub enum Operator {
   Plus,
   Minus,
   Mul,
   Div,
+  /// The remainder, written `a % b`. It needs a space before the `%`, `10%` is a percentage.
+  Mod,
   Equal,
   And,
   Or,
@@ -20,6 +22,42 @@ pub enum Operator {
   MoreThanOrEqual,
 }
 
+impl Operator {
+  /// Binding power, higher binds tighter. All binary operators are left associative.
+  pub fn precedence(&self) -> u8 {
+    match self {
+      Operator::Or => 1,
+      Operator::And => 2,
+      Operator::Equal
+      | Operator::NotEqual
+      | Operator::LessThan
+      | Operator::LessThanOrEqual
+      | Operator::MoreThan
+      | Operator::MoreThanOrEqual => 4,
+      Operator::Plus | Operator::Minus => 5,
+      Operator::Mul | Operator::Div | Operator::Mod => 6,
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      Operator::Plus => "+",
+      Operator::Minus => "-",
+      Operator::Mul => "*",
+      Operator::Div => "/",
+      Operator::Mod => "%",
+      Operator::Equal => "=",
+      Operator::And => "AND",
+      Operator::Or => "OR",
+      Operator::NotEqual => "!=",
+      Operator::LessThan => "<",
+      Operator::LessThanOrEqual => "<=",
+      Operator::MoreThan => ">",
+      Operator::MoreThanOrEqual => ">=",
+    }
+  }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct AstOperatorNode {
   pub operator: Operator,
@@ -13,4 +13,4 @@ impl AstScriptNode {
   pub fn add_child(&self, child: NodeRef) {
     self.children.borrow_mut().push(child)
   }
-}
\ No newline at end of file
+}
@@ -5,10 +5,13 @@ use serde::Serialize;
 pub enum QuoteKind {
   SingleQuote,
   DoubleQuote,
+  TripleQuote,
+  Raw,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct AstStringNode {
   pub text: LexedStr,
+  pub value: LexedStr,
   pub quote_kind: QuoteKind,
 }
@@ -0,0 +1,45 @@
+use std::cell::Cell;
+
+use serde::Serialize;
+
+use crate::NodeRef;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+  Minus,
+  Not,
+}
+
+impl UnaryOperator {
+  pub fn precedence(&self) -> u8 {
+    match self {
+      UnaryOperator::Not => 3,
+      UnaryOperator::Minus => 7,
+    }
+  }
+
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      UnaryOperator::Minus => "-",
+      UnaryOperator::Not => "NOT",
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AstUnaryOperatorNode {
+  pub operator: UnaryOperator,
+  pub expr: Cell<NodeRef>,
+}
+
+impl AstUnaryOperatorNode {
+  pub(crate) fn add_expr(&self, child: NodeRef) {
+    self.expr.set(child);
+  }
+  pub fn new(operator: UnaryOperator, expr: NodeRef) -> Self {
+    AstUnaryOperatorNode {
+      operator,
+      expr: Cell::new(expr),
+    }
+  }
+}
@@ -1,5 +1,8 @@
 use lexer::LexedStr;
 use serde::Serialize;
+use std::cell::Cell;
+
+use crate::NodeRef;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct AstVPathNode {
@@ -7,4 +10,13 @@ pub struct AstVPathNode {
   pub variable: Option<LexedStr>,
   pub function: Option<LexedStr>,
   pub is_hierarchy: bool,
+  /// The `reportingHierarchy` entity a `table:^hierarchy` path binds to, `NodeRef(-1)` until
+  /// resolved.
+  pub hierarchy: Cell<NodeRef>,
+}
+
+impl AstVPathNode {
+  pub fn set_hierarchy(&self, node_ref: NodeRef) {
+    self.hierarchy.set(node_ref)
+  }
 }
@@ -1,5 +1,6 @@
 mod ast_boolean;
 mod ast_color;
+mod ast_const;
 mod ast_entity;
 mod ast_formula;
 mod ast_function;
@@ -12,10 +13,12 @@ mod ast_script;
 mod ast_string;
 mod ast_table_alias;
 mod ast_title;
+mod ast_unary_operator;
 mod ast_vpath;
 
 pub use ast_boolean::AstBooleanNode;
 pub use ast_color::AstColorNode;
+pub use ast_const::AstConstNode;
 pub use ast_entity::AstEntityNode;
 pub use ast_formula::AstFormulaNode;
 pub use ast_function::AstFunctionNode;
@@ -30,4 +33,6 @@ pub use ast_string::AstStringNode;
 pub use ast_string::QuoteKind;
 pub use ast_table_alias::AstTableAliasNode;
 pub use ast_title::AstTitleNode;
+pub use ast_unary_operator::AstUnaryOperatorNode;
+pub use ast_unary_operator::UnaryOperator;
 pub use ast_vpath::AstVPathNode;
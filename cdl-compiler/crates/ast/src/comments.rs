@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use crate::NodeRef;
+
+/// Where a comment is printed, relative to the statement it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommentPlace {
+  /// On its own lines, before the statement.
+  Before,
+  /// At the end of the statement's line. For entities, after the opening brace.
+  EndOfLine,
+  /// On its own lines, before the closing brace of an entity, or at the end of the script.
+  BeforeClose,
+  /// After the closing brace of an entity.
+  AfterClose,
+}
+
+/// Comments to print with an `Ast`, keyed by the statement they belong to. Statements are
+/// titles, entities, properties, table aliases and consts.
+#[derive(Debug, Clone, Default)]
+pub struct Comments {
+  comments: HashMap<(NodeRef, CommentPlace), Vec<String>>,
+}
+
+impl Comments {
+  pub fn new() -> Comments {
+    Comments::default()
+  }
+
+  pub fn add(&mut self, node_ref: NodeRef, place: CommentPlace, text: impl Into<String>) {
+    self
+      .comments
+      .entry((node_ref, place))
+      .or_default()
+      .push(text.into());
+  }
+
+  pub fn get(&self, node_ref: NodeRef, place: CommentPlace) -> &[String] {
+    self
+      .comments
+      .get(&(node_ref, place))
+      .map(|comments| comments.as_slice())
+      .unwrap_or_default()
+  }
+}
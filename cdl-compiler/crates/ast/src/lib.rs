@@ -1,6 +1,10 @@
 mod ast;
 mod ast_nodes;
+mod comments;
+mod pretty;
+mod printer;
 mod select;
+mod visitor;
 
 use serde::Serialize;
 use std::cell::RefCell;
@@ -8,6 +12,7 @@ use std::fmt::Debug;
 
 pub use ast_nodes::AstBooleanNode;
 pub use ast_nodes::AstColorNode;
+pub use ast_nodes::AstConstNode;
 pub use ast_nodes::AstEntityNode;
 pub use ast_nodes::AstFormulaNode;
 pub use ast_nodes::AstFunctionNode;
@@ -20,12 +25,17 @@ pub use ast_nodes::AstScriptNode;
 pub use ast_nodes::AstStringNode;
 pub use ast_nodes::AstTableAliasNode;
 pub use ast_nodes::AstTitleNode;
+pub use ast_nodes::AstUnaryOperatorNode;
 pub use ast_nodes::AstVPathNode;
 pub use ast_nodes::Operator;
 pub use ast_nodes::QuoteKind;
+pub use ast_nodes::UnaryOperator;
 
 pub use ast::Ast;
+pub use comments::{CommentPlace, Comments};
+pub use pretty::{Doc, DEFAULT_WIDTH};
 pub use select::*;
+pub use visitor::*;
 
 #[derive(Debug, Serialize, Clone)]
 pub enum Node {
@@ -42,17 +52,51 @@ pub enum Node {
   Reference(AstReferenceNode),
   Function(AstFunctionNode),
   Operator(AstOperatorNode),
+  UnaryOperator(AstUnaryOperatorNode),
   TableAlias(AstTableAliasNode),
   Formula(AstFormulaNode),
+  Const(AstConstNode),
 }
 
 impl Node {
   pub fn is_reference(&self) -> bool {
     matches!(self, Node::Reference(_))
   }
+
+  pub fn children(&self) -> Vec<NodeRef> {
+    match self {
+      Node::Script(script) => script.children.borrow().clone(),
+      Node::Entity(entity) => entity.children.borrow().clone(),
+      Node::Property(prop) => prop.children.borrow().clone(),
+      Node::Function(func) => func.children.borrow().clone(),
+      Node::Operator(op) => vec![op.left.get(), op.right.get()],
+      Node::UnaryOperator(op) => vec![op.expr.get()],
+      Node::Const(c) => vec![c.value.get()],
+      Node::Formula(formula) => formula.children.clone(),
+      _ => vec![],
+    }
+  }
+
+  /// Replaces the children, in the same order as returned by `children`.
+  pub fn set_children(&mut self, children: Vec<NodeRef>) {
+    match self {
+      Node::Script(script) => *script.children.get_mut() = children,
+      Node::Entity(entity) => *entity.children.get_mut() = children,
+      Node::Property(prop) => *prop.children.get_mut() = children,
+      Node::Function(func) => *func.children.get_mut() = children,
+      Node::Operator(op) => {
+        op.left.set(children[0]);
+        op.right.set(children[1]);
+      }
+      Node::UnaryOperator(op) => op.expr.set(children[0]),
+      Node::Const(c) => c.value.set(children[0]),
+      Node::Formula(formula) => formula.children = children,
+      _ => {}
+    }
+  }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub struct NodeRef(pub isize);
 
 impl From<usize> for NodeRef {
@@ -89,6 +133,8 @@ impl AstNode {
       Node::Property(prop) => prop.add_property(child),
       Node::Function(func) => func.add_argument(child),
       Node::Operator(op) => op.add_right(child),
+      Node::UnaryOperator(op) => op.add_expr(child),
+      Node::Const(c) => c.add_value(child),
       _ => panic!("Unknown type to set as parent {:?}", node_data),
     };
   }
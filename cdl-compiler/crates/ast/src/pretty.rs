@@ -0,0 +1,242 @@
+use crate::{printer::precedence_of, Ast, Node, NodeRef, UnaryOperator};
+
+/// Line width the formatter wraps expressions at.
+pub const DEFAULT_WIDTH: usize = 100;
+
+/// A document for Wadler style pretty printing. A group is printed on one line when it fits in
+/// the width, otherwise the lines directly in it break and its inner groups get the same choice.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Doc {
+  Text(String),
+  /// A space, or a new line when the group breaks.
+  Line,
+  /// Nothing, or a new line when the group breaks.
+  SoftLine,
+  /// Lines inside are indented this much more when they break.
+  Nest(usize, Box<Doc>),
+  Group(Box<Doc>),
+  Concat(Vec<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+  Flat,
+  Break,
+}
+
+impl Doc {
+  pub fn text(text: impl Into<String>) -> Doc {
+    Doc::Text(text.into())
+  }
+
+  pub fn nest(indent: usize, doc: Doc) -> Doc {
+    Doc::Nest(indent, Box::new(doc))
+  }
+
+  pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+  }
+
+  /// Renders the document starting at `column` of a line indented by `indent`.
+  pub fn render(&self, width: usize, indent: usize, column: usize) -> String {
+    let mut out = String::new();
+    let mut column = column;
+    let mut stack = vec![(indent, Mode::Break, self)];
+    while let Some((indent, mode, doc)) = stack.pop() {
+      match doc {
+        Doc::Text(text) => {
+          out.push_str(text);
+          column = match text.rfind('\n') {
+            Some(index) => text[index + 1..].chars().count(),
+            None => column + text.chars().count(),
+          };
+        }
+        Doc::Line if mode == Mode::Flat => {
+          out.push(' ');
+          column += 1;
+        }
+        Doc::SoftLine if mode == Mode::Flat => {}
+        Doc::Line | Doc::SoftLine => {
+          out.push('\n');
+          out.push_str(&" ".repeat(indent));
+          column = indent;
+        }
+        Doc::Nest(nested, doc) => stack.push((indent + nested, mode, doc)),
+        Doc::Group(doc) => {
+          let remaining = width as isize - column as isize;
+          let flat = mode == Mode::Flat || fits(remaining, (indent, Mode::Flat, doc), &stack);
+          let mode = if flat { Mode::Flat } else { Mode::Break };
+          stack.push((indent, mode, doc));
+        }
+        Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+      }
+    }
+    out
+  }
+}
+
+/// Whether `next` printed flat, and what follows it up to the next line break, fits in
+/// `remaining` columns.
+fn fits(mut remaining: isize, next: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)]) -> bool {
+  let mut stack = vec![next];
+  let mut rest = rest.iter().rev();
+  while remaining >= 0 {
+    let Some((indent, mode, doc)) = stack.pop().or_else(|| rest.next().copied()) else {
+      return true;
+    };
+    match doc {
+      Doc::Text(text) => match text.split_once('\n') {
+        Some((first, _)) => return first.chars().count() as isize <= remaining,
+        None => remaining -= text.chars().count() as isize,
+      },
+      Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+      Doc::Line => remaining -= 1,
+      Doc::SoftLine => {}
+      Doc::Nest(nested, doc) => stack.push((indent + nested, mode, doc)),
+      Doc::Group(doc) => stack.push((indent, mode, doc)),
+      Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+    }
+  }
+  false
+}
+
+/// The value at `node_ref` as a document. Blocks like anonymous entities give `None`, they are
+/// printed as they are.
+pub(crate) fn expression_doc(ast: &Ast, node_ref: NodeRef) -> Option<Doc> {
+  let node = ast.get_node(node_ref)?;
+  Some(match &node.node_data {
+    Node::Function(function) if !function.children.borrow().is_empty() => arguments(
+      ast,
+      format!("{}(", function.name),
+      &function.children.borrow(),
+      ")",
+    )?,
+    Node::Operator(op) => {
+      // a chain of operators binding equally breaks as one, `a AND b AND c`
+      let precedence = op.operator.precedence();
+      let mut first = node_ref;
+      let mut rest = vec![];
+      while let Some(Node::Operator(op)) = ast.get_node(first).map(|n| n.node_data.clone()) {
+        if op.operator.precedence() != precedence {
+          break;
+        }
+        rest.push((op.operator, op.right.get()));
+        first = op.left.get();
+      }
+      let mut tail = vec![];
+      for (operator, right) in rest.into_iter().rev() {
+        tail.push(Doc::text(format!(" {}", operator.symbol())));
+        tail.push(Doc::Line);
+        tail.push(operand(
+          ast,
+          right,
+          precedence_of(ast, right) <= precedence,
+        )?);
+      }
+      Doc::group(Doc::Concat(vec![
+        operand(ast, first, precedence_of(ast, first) < precedence)?,
+        Doc::nest(2, Doc::Concat(tail)),
+      ]))
+    }
+    Node::UnaryOperator(op) => {
+      let operator = match op.operator {
+        UnaryOperator::Minus => "-",
+        UnaryOperator::Not => "NOT ",
+      };
+      let expr = op.expr.get();
+      Doc::Concat(vec![
+        Doc::text(operator),
+        operand(
+          ast,
+          expr,
+          precedence_of(ast, expr) < op.operator.precedence(),
+        )?,
+      ])
+    }
+    Node::Reference(reference) if reference.resolved_node.get() != NodeRef(-1) => {
+      expression_doc(ast, reference.resolved_node.get())?
+    }
+    Node::Entity(_)
+    | Node::Property(_)
+    | Node::Script(_)
+    | Node::Title(_)
+    | Node::TableAlias(_)
+    | Node::Const(_) => return None,
+    _ => Doc::text(ast.node_to_cdl(node_ref).ok()?),
+  })
+}
+
+/// Property values, `a, b, c`. Continued lines start after a comma, as the parser expects.
+pub(crate) fn values_doc(ast: &Ast, values: &[NodeRef]) -> Option<Doc> {
+  if let [value] = values {
+    return expression_doc(ast, *value);
+  }
+  Some(Doc::group(Doc::nest(2, separated(ast, values)?)))
+}
+
+fn arguments(ast: &Ast, open: String, args: &[NodeRef], close: &str) -> Option<Doc> {
+  Some(Doc::group(Doc::Concat(vec![
+    Doc::Text(open),
+    Doc::nest(2, Doc::Concat(vec![Doc::SoftLine, separated(ast, args)?])),
+    Doc::SoftLine,
+    Doc::text(close),
+  ])))
+}
+
+fn separated(ast: &Ast, nodes: &[NodeRef]) -> Option<Doc> {
+  let mut docs = vec![];
+  for (index, node_ref) in nodes.iter().enumerate() {
+    if index > 0 {
+      docs.push(Doc::text(","));
+      docs.push(Doc::Line);
+    }
+    docs.push(expression_doc(ast, *node_ref)?);
+  }
+  Some(Doc::Concat(docs))
+}
+
+fn operand(ast: &Ast, node_ref: NodeRef, parens: bool) -> Option<Doc> {
+  let doc = expression_doc(ast, node_ref)?;
+  Some(match parens {
+    true => Doc::Concat(vec![Doc::text("("), doc, Doc::text(")")]),
+    false => doc,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn call(name: &str, args: Vec<Doc>) -> Doc {
+    let mut inner = vec![Doc::SoftLine];
+    for (index, arg) in args.into_iter().enumerate() {
+      if index > 0 {
+        inner.extend([Doc::text(","), Doc::Line]);
+      }
+      inner.push(arg);
+    }
+    Doc::group(Doc::Concat(vec![
+      Doc::text(format!("{}(", name)),
+      Doc::nest(2, Doc::Concat(inner)),
+      Doc::SoftLine,
+      Doc::text(")"),
+    ]))
+  }
+
+  #[test]
+  fn breaks_outer_groups_first() {
+    let doc = call(
+      "iif",
+      vec![
+        Doc::text("a"),
+        call("iif", vec![Doc::text("b"), Doc::text("c"), Doc::text("d")]),
+      ],
+    );
+    assert_eq!("iif(a, iif(b, c, d))", doc.render(20, 0, 0));
+    assert_eq!("iif(\n  a,\n  iif(b, c, d)\n)", doc.render(15, 0, 0));
+    assert_eq!(
+      "iif(\n    a,\n    iif(\n      b,\n      c,\n      d\n    )\n  )",
+      doc.render(12, 2, 8)
+    );
+  }
+}
@@ -0,0 +1,445 @@
+use std::fmt::{self, Write};
+
+use crate::{
+  pretty::{expression_doc, values_doc, Doc},
+  walk, Ast, AstBooleanNode, AstColorNode, AstConstNode, AstEntityNode, AstFormulaNode,
+  AstFunctionNode, AstIdentifierNode, AstNumberNode, AstOperatorNode, AstPropertyNode,
+  AstReferenceNode, AstScriptNode, AstStringNode, AstTableAliasNode, AstTitleNode,
+  AstUnaryOperatorNode, AstVPathNode, CommentPlace, Comments, Node, NodeRef, UnaryOperator,
+  Visitor, Walk,
+};
+
+pub(crate) struct CdlPrinter<'a> {
+  cdl: &'a mut dyn Write,
+  indent: usize,
+  /// Expressions are wrapped to this width when set, otherwise kept on one line.
+  width: Option<usize>,
+  comments: Option<&'a Comments>,
+  error: Option<fmt::Error>,
+}
+
+impl<'a> CdlPrinter<'a> {
+  pub(crate) fn new(cdl: &'a mut dyn Write) -> CdlPrinter<'a> {
+    CdlPrinter {
+      cdl,
+      indent: 0,
+      width: None,
+      comments: None,
+      error: None,
+    }
+  }
+
+  pub(crate) fn with_width(cdl: &'a mut dyn Write, width: usize) -> CdlPrinter<'a> {
+    CdlPrinter {
+      width: Some(width),
+      ..CdlPrinter::new(cdl)
+    }
+  }
+
+  pub(crate) fn with_comments(
+    cdl: &'a mut dyn Write,
+    width: usize,
+    comments: &'a Comments,
+  ) -> CdlPrinter<'a> {
+    CdlPrinter {
+      comments: Some(comments),
+      ..CdlPrinter::with_width(cdl, width)
+    }
+  }
+
+  pub(crate) fn print(mut self, ast: &Ast, node_ref: NodeRef) -> fmt::Result {
+    if let Some(width) = self.width {
+      if let Some(doc) = expression_doc(ast, node_ref) {
+        return write!(self.cdl, "{}", doc.render(width, 0, 0));
+      }
+    }
+    walk(&mut self, ast, node_ref);
+    match self.error {
+      Some(error) => Err(error),
+      None => Ok(()),
+    }
+  }
+
+  fn check(&mut self, result: fmt::Result, next: Walk) -> Walk {
+    match result {
+      Ok(()) if self.error.is_none() => next,
+      Ok(()) => Walk::Stop,
+      Err(error) => {
+        self.error = Some(error);
+        Walk::Stop
+      }
+    }
+  }
+
+  fn print_children(&mut self, ast: &Ast, children: &[NodeRef], separator: &str) -> Walk {
+    for (index, child) in children.iter().enumerate() {
+      if index > 0 {
+        let result = write!(self.cdl, "{}", separator);
+        if self.check(result, Walk::Continue) == Walk::Stop {
+          return Walk::Stop;
+        }
+      }
+      if walk(self, ast, *child) == Walk::Stop {
+        return Walk::Stop;
+      }
+    }
+    Walk::Continue
+  }
+
+  /// Property values are separated by commas, except an entity value which follows the value
+  /// before it on the same line, as in `prop: widget {`.
+  fn print_values(&mut self, ast: &Ast, values: &[NodeRef]) -> Walk {
+    for (index, value) in values.iter().enumerate() {
+      if index > 0 {
+        let is_entity = ast
+          .get_node(*value)
+          .is_some_and(|node| matches!(node.node_data, Node::Entity(_)));
+        let result = write!(self.cdl, "{}", if is_entity { " " } else { ", " });
+        if self.check(result, Walk::Continue) == Walk::Stop {
+          return Walk::Stop;
+        }
+      }
+      if walk(self, ast, *value) == Walk::Stop {
+        return Walk::Stop;
+      }
+    }
+    Walk::Continue
+  }
+
+  fn print_operand(&mut self, ast: &Ast, node_ref: NodeRef, parens: bool) -> Walk {
+    if parens {
+      let result = write!(self.cdl, "(");
+      if self.check(result, Walk::Continue) == Walk::Stop {
+        return Walk::Stop;
+      }
+    }
+    if walk(self, ast, node_ref) == Walk::Stop {
+      return Walk::Stop;
+    }
+    if parens {
+      let result = write!(self.cdl, ")");
+      return self.check(result, Walk::Continue);
+    }
+    Walk::Continue
+  }
+
+  fn is_property_value(&self, ast: &Ast, node_ref: NodeRef) -> bool {
+    ast
+      .get_parent(node_ref)
+      .first()
+      .and_then(|parent| ast.get_node(*parent))
+      .map(|parent| matches!(parent.node_data, Node::Property(_)))
+      .unwrap_or(false)
+  }
+
+  fn comments(&self, node_ref: NodeRef, place: CommentPlace) -> &'a [String] {
+    match self.comments {
+      Some(comments) => comments.get(node_ref, place),
+      None => &[],
+    }
+  }
+
+  /// Writes the comments at `place` on their own lines, at the current indent.
+  fn write_comment_lines(&mut self, node_ref: NodeRef, place: CommentPlace) -> fmt::Result {
+    for comment in self.comments(node_ref, place) {
+      writeln!(self.cdl, "{}{}", create_indent(self.indent), comment)?;
+    }
+    Ok(())
+  }
+
+  fn write_comments_inline(&mut self, node_ref: NodeRef, place: CommentPlace) -> fmt::Result {
+    for comment in self.comments(node_ref, place) {
+      write!(self.cdl, " {}", comment)?;
+    }
+    Ok(())
+  }
+
+  /// Ends the line of the statement at `node_ref`, after any comment that trails it.
+  fn end_line(&mut self, node_ref: NodeRef) -> fmt::Result {
+    self.write_comments_inline(node_ref, CommentPlace::EndOfLine)?;
+    writeln!(self.cdl)
+  }
+
+  fn write_entity_header(&mut self, indent_str: &str, entity: &AstEntityNode) -> fmt::Result {
+    let mut header: Vec<String> = entity.terms.iter().map(|t| t.to_string()).collect();
+    if let Some(label) = &entity.label {
+      header.push(label.to_string());
+    }
+    for r in &entity.refs {
+      header.push(format!("@{}", r));
+    }
+    if let Some(id) = &entity.ident {
+      header.push(format!("#{}", id));
+    }
+    if let Some(num) = &entity.entity_number {
+      header.push(num.to_string());
+    }
+    header.push("{".to_string());
+    write!(self.cdl, "{}{}", indent_str, header.join(" "))
+  }
+
+  /// Writes the rest of the line of the statement at `node_ref` starting at column `prefix`,
+  /// and ends it.
+  fn write_doc(&mut self, node_ref: NodeRef, doc: &Doc, prefix: usize) -> Walk {
+    let width = self.width.unwrap_or(usize::MAX);
+    let result = write!(self.cdl, "{}", doc.render(width, self.indent * 2, prefix))
+      .and_then(|_| self.end_line(node_ref));
+    self.check(result, Walk::SkipChildren)
+  }
+
+  fn write_vpath(&mut self, vpath: &AstVPathNode) -> fmt::Result {
+    if let Some(table) = &vpath.table {
+      write!(self.cdl, "{}", table)?;
+    }
+    write!(self.cdl, ":")?;
+    if vpath.is_hierarchy {
+      write!(self.cdl, "^")?;
+    }
+    if let Some(variable) = &vpath.variable {
+      write!(self.cdl, "{}", variable)?;
+    }
+    if let Some(func) = &vpath.function {
+      write!(self.cdl, "{}()", func)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'a> Visitor for CdlPrinter<'a> {
+  fn visit_script(&mut self, _ast: &Ast, node_ref: NodeRef, _script: &AstScriptNode) -> Walk {
+    let result = self.write_comment_lines(node_ref, CommentPlace::Before);
+    self.check(result, Walk::Continue)
+  }
+
+  fn leave_script(&mut self, _ast: &Ast, node_ref: NodeRef, _script: &AstScriptNode) {
+    let result = self.write_comment_lines(node_ref, CommentPlace::BeforeClose);
+    self.check(result, Walk::Continue);
+  }
+
+  fn visit_title(&mut self, _ast: &Ast, node_ref: NodeRef, title: &AstTitleNode) -> Walk {
+    let result = self
+      .write_comment_lines(node_ref, CommentPlace::Before)
+      .and_then(|_| write!(self.cdl, "title {}", title.title.as_str()))
+      .and_then(|_| self.end_line(node_ref));
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_entity(&mut self, ast: &Ast, node_ref: NodeRef, entity: &AstEntityNode) -> Walk {
+    let result = if self.is_property_value(ast, node_ref) {
+      self.write_entity_header("", entity)
+    } else {
+      self
+        .write_comment_lines(node_ref, CommentPlace::Before)
+        .and_then(|_| self.write_entity_header(&create_indent(self.indent), entity))
+    }
+    .and_then(|_| self.end_line(node_ref));
+    self.indent += 1;
+    self.check(result, Walk::Continue)
+  }
+
+  fn leave_entity(&mut self, ast: &Ast, node_ref: NodeRef, _entity: &AstEntityNode) {
+    let result = self.write_comment_lines(node_ref, CommentPlace::BeforeClose);
+    self.indent -= 1;
+    let result = result
+      .and_then(|_| write!(self.cdl, "{}}}", create_indent(self.indent)))
+      .and_then(|_| self.write_comments_inline(node_ref, CommentPlace::AfterClose));
+    let result = if self.is_property_value(ast, node_ref) {
+      result
+    } else {
+      result.and_then(|_| writeln!(self.cdl))
+    };
+    self.check(result, Walk::Continue);
+  }
+
+  fn visit_property(&mut self, ast: &Ast, node_ref: NodeRef, prop: &AstPropertyNode) -> Walk {
+    let result = self
+      .write_comment_lines(node_ref, CommentPlace::Before)
+      .and_then(|_| write!(self.cdl, "{}{}: ", create_indent(self.indent), prop.name));
+    if self.check(result, Walk::Continue) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let children = prop.children.borrow().clone();
+    let prefix = self.indent * 2 + prop.name.as_str().chars().count() + 2;
+    if let Some(doc) = self.width.and_then(|_| values_doc(ast, &children)) {
+      return self.write_doc(node_ref, &doc, prefix);
+    }
+    if self.print_values(ast, &children) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let result = self.end_line(node_ref);
+    self.check(result, Walk::SkipChildren)
+  }
+
+  fn visit_identifier(
+    &mut self,
+    _ast: &Ast,
+    _node_ref: NodeRef,
+    identifier: &AstIdentifierNode,
+  ) -> Walk {
+    let result = write!(self.cdl, "{}", identifier.identifier);
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_string(&mut self, _ast: &Ast, _node_ref: NodeRef, string: &AstStringNode) -> Walk {
+    let result = write!(self.cdl, "{}", string.text);
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_number(&mut self, _ast: &Ast, _node_ref: NodeRef, number: &AstNumberNode) -> Walk {
+    let result = match &number.raw {
+      Some(raw) => write!(self.cdl, "{}", raw),
+      None => write!(self.cdl, "{}", number.value),
+    };
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_boolean(&mut self, _ast: &Ast, _node_ref: NodeRef, boolean: &AstBooleanNode) -> Walk {
+    let result = write!(self.cdl, "{}", boolean.get());
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_vpath(&mut self, _ast: &Ast, _node_ref: NodeRef, vpath: &AstVPathNode) -> Walk {
+    let result = self.write_vpath(vpath);
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_color(&mut self, _ast: &Ast, _node_ref: NodeRef, color: &AstColorNode) -> Walk {
+    let result = write!(self.cdl, "#{}", color.color);
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_reference(&mut self, ast: &Ast, _node_ref: NodeRef, r: &AstReferenceNode) -> Walk {
+    if r.resolved_node.get() == NodeRef(-1) {
+      let result = write!(self.cdl, "@{}", r.ident.as_str());
+      self.check(result, Walk::Continue)
+    } else {
+      walk(self, ast, r.resolved_node.get())
+    }
+  }
+
+  fn visit_function(&mut self, ast: &Ast, _node_ref: NodeRef, func: &AstFunctionNode) -> Walk {
+    let result = write!(self.cdl, "{}(", func.name);
+    if self.check(result, Walk::Continue) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let children = func.children.borrow().clone();
+    if self.print_children(ast, &children, ", ") == Walk::Stop {
+      return Walk::Stop;
+    }
+    let result = write!(self.cdl, ")");
+    self.check(result, Walk::SkipChildren)
+  }
+
+  fn visit_operator(&mut self, ast: &Ast, _node_ref: NodeRef, op: &AstOperatorNode) -> Walk {
+    let precedence = op.operator.precedence();
+    let left = op.left.get();
+    if self.print_operand(ast, left, precedence_of(ast, left) < precedence) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let result = write!(self.cdl, " {} ", op.operator.symbol());
+    if self.check(result, Walk::Continue) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let right = op.right.get();
+    if self.print_operand(ast, right, precedence_of(ast, right) <= precedence) == Walk::Stop {
+      return Walk::Stop;
+    }
+    Walk::SkipChildren
+  }
+
+  fn visit_unary_operator(
+    &mut self,
+    ast: &Ast,
+    _node_ref: NodeRef,
+    op: &AstUnaryOperatorNode,
+  ) -> Walk {
+    let operator = match op.operator {
+      UnaryOperator::Minus => "-",
+      UnaryOperator::Not => "NOT ",
+    };
+    let result = write!(self.cdl, "{}", operator);
+    if self.check(result, Walk::Continue) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let expr = op.expr.get();
+    let parens = precedence_of(ast, expr) < op.operator.precedence();
+    if self.print_operand(ast, expr, parens) == Walk::Stop {
+      return Walk::Stop;
+    }
+    Walk::SkipChildren
+  }
+
+  fn visit_table_alias(
+    &mut self,
+    _ast: &Ast,
+    node_ref: NodeRef,
+    alias: &AstTableAliasNode,
+  ) -> Walk {
+    let result = self
+      .write_comment_lines(node_ref, CommentPlace::Before)
+      .and_then(|_| {
+        write!(
+          self.cdl,
+          "{}table {} = {}",
+          create_indent(self.indent),
+          alias.alias,
+          alias.table
+        )
+      })
+      .and_then(|_| self.end_line(node_ref));
+    self.check(result, Walk::Continue)
+  }
+
+  fn visit_formula(&mut self, ast: &Ast, _node_ref: NodeRef, formula: &AstFormulaNode) -> Walk {
+    let result = write!(self.cdl, "[");
+    if self.check(result, Walk::Continue) == Walk::Stop {
+      return Walk::Stop;
+    }
+    if self.print_children(ast, &formula.children, ", ") == Walk::Stop {
+      return Walk::Stop;
+    }
+    let result = write!(self.cdl, "]");
+    self.check(result, Walk::SkipChildren)
+  }
+
+  fn visit_const(&mut self, ast: &Ast, node_ref: NodeRef, c: &AstConstNode) -> Walk {
+    let result = self
+      .write_comment_lines(node_ref, CommentPlace::Before)
+      .and_then(|_| {
+        write!(
+          self.cdl,
+          "{}const {} = ",
+          create_indent(self.indent),
+          c.name
+        )
+      });
+    if self.check(result, Walk::Continue) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let prefix = self.indent * 2 + c.name.as_str().chars().count() + 9;
+    if let Some(doc) = self.width.and_then(|_| expression_doc(ast, c.value.get())) {
+      return self.write_doc(node_ref, &doc, prefix);
+    }
+    if walk(self, ast, c.value.get()) == Walk::Stop {
+      return Walk::Stop;
+    }
+    let result = self.end_line(node_ref);
+    self.check(result, Walk::SkipChildren)
+  }
+}
+
+pub(crate) fn precedence_of(ast: &Ast, node_ref: NodeRef) -> u8 {
+  let node = match ast.get_node(node_ref) {
+    Some(node) => node,
+    None => return u8::MAX,
+  };
+  match &node.node_data {
+    Node::Operator(op) => op.operator.precedence(),
+    Node::UnaryOperator(op) => op.operator.precedence(),
+    _ => u8::MAX,
+  }
+}
+
+fn create_indent(indent_size: usize) -> String {
+  "  ".repeat(indent_size)
+}
@@ -22,7 +22,7 @@ pub fn select_property_value(ast: &Ast, name: &str) -> Vec<NodeRef> {
     let node_data = &node.node_data;
     if let Node::Property(property) = node_data {
       if name.eq(&property.name.0.to_string()) {
-        result.push(*property.children.borrow().get(0).unwrap());
+        result.push(*property.children.borrow().first().unwrap());
       }
     }
   }
@@ -0,0 +1,407 @@
+use std::rc::Rc;
+
+use crate::{
+  Ast, AstBooleanNode, AstColorNode, AstConstNode, AstEntityNode, AstFormulaNode, AstFunctionNode,
+  AstIdentifierNode, AstNumberNode, AstOperatorNode, AstPropertyNode, AstReferenceNode,
+  AstScriptNode, AstStringNode, AstTableAliasNode, AstTitleNode, AstUnaryOperatorNode,
+  AstVPathNode, Node, NodeRef,
+};
+
+/// Returned from the `visit_*` methods to control how the walk continues.
+/// `SkipChildren` skips both the children and the matching `leave_*` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Walk {
+  Continue,
+  SkipChildren,
+  Stop,
+}
+
+/// Read only visitor. `visit_*` is called before the children of a node are walked,
+/// `leave_*` after.
+#[allow(unused_variables)]
+pub trait Visitor {
+  fn visit_script(&mut self, ast: &Ast, node_ref: NodeRef, script: &AstScriptNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_title(&mut self, ast: &Ast, node_ref: NodeRef, title: &AstTitleNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_entity(&mut self, ast: &Ast, node_ref: NodeRef, entity: &AstEntityNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_property(&mut self, ast: &Ast, node_ref: NodeRef, prop: &AstPropertyNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_identifier(
+    &mut self,
+    ast: &Ast,
+    node_ref: NodeRef,
+    identifier: &AstIdentifierNode,
+  ) -> Walk {
+    Walk::Continue
+  }
+  fn visit_string(&mut self, ast: &Ast, node_ref: NodeRef, string: &AstStringNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_number(&mut self, ast: &Ast, node_ref: NodeRef, number: &AstNumberNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_boolean(&mut self, ast: &Ast, node_ref: NodeRef, boolean: &AstBooleanNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_vpath(&mut self, ast: &Ast, node_ref: NodeRef, vpath: &AstVPathNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_color(&mut self, ast: &Ast, node_ref: NodeRef, color: &AstColorNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_reference(&mut self, ast: &Ast, node_ref: NodeRef, r: &AstReferenceNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_function(&mut self, ast: &Ast, node_ref: NodeRef, func: &AstFunctionNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_operator(&mut self, ast: &Ast, node_ref: NodeRef, op: &AstOperatorNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_unary_operator(
+    &mut self,
+    ast: &Ast,
+    node_ref: NodeRef,
+    op: &AstUnaryOperatorNode,
+  ) -> Walk {
+    Walk::Continue
+  }
+  fn visit_table_alias(&mut self, ast: &Ast, node_ref: NodeRef, alias: &AstTableAliasNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_formula(&mut self, ast: &Ast, node_ref: NodeRef, formula: &AstFormulaNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_const(&mut self, ast: &Ast, node_ref: NodeRef, c: &AstConstNode) -> Walk {
+    Walk::Continue
+  }
+
+  fn leave_script(&mut self, ast: &Ast, node_ref: NodeRef, script: &AstScriptNode) {}
+  fn leave_entity(&mut self, ast: &Ast, node_ref: NodeRef, entity: &AstEntityNode) {}
+  fn leave_property(&mut self, ast: &Ast, node_ref: NodeRef, prop: &AstPropertyNode) {}
+  fn leave_function(&mut self, ast: &Ast, node_ref: NodeRef, func: &AstFunctionNode) {}
+  fn leave_operator(&mut self, ast: &Ast, node_ref: NodeRef, op: &AstOperatorNode) {}
+  fn leave_unary_operator(&mut self, ast: &Ast, node_ref: NodeRef, op: &AstUnaryOperatorNode) {}
+  fn leave_formula(&mut self, ast: &Ast, node_ref: NodeRef, formula: &AstFormulaNode) {}
+  fn leave_const(&mut self, ast: &Ast, node_ref: NodeRef, c: &AstConstNode) {}
+}
+
+/// Same as `Visitor`, but hands out mutable node data. Nodes shared with a clone of the
+/// `Ast` are copied before they are handed to the visitor.
+#[allow(unused_variables)]
+pub trait VisitorMut {
+  fn visit_script(&mut self, node_ref: NodeRef, script: &mut AstScriptNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_title(&mut self, node_ref: NodeRef, title: &mut AstTitleNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_entity(&mut self, node_ref: NodeRef, entity: &mut AstEntityNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_property(&mut self, node_ref: NodeRef, prop: &mut AstPropertyNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_identifier(&mut self, node_ref: NodeRef, identifier: &mut AstIdentifierNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_string(&mut self, node_ref: NodeRef, string: &mut AstStringNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_number(&mut self, node_ref: NodeRef, number: &mut AstNumberNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_boolean(&mut self, node_ref: NodeRef, boolean: &mut AstBooleanNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_vpath(&mut self, node_ref: NodeRef, vpath: &mut AstVPathNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_color(&mut self, node_ref: NodeRef, color: &mut AstColorNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_reference(&mut self, node_ref: NodeRef, r: &mut AstReferenceNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_function(&mut self, node_ref: NodeRef, func: &mut AstFunctionNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_operator(&mut self, node_ref: NodeRef, op: &mut AstOperatorNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_unary_operator(&mut self, node_ref: NodeRef, op: &mut AstUnaryOperatorNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_table_alias(&mut self, node_ref: NodeRef, alias: &mut AstTableAliasNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_formula(&mut self, node_ref: NodeRef, formula: &mut AstFormulaNode) -> Walk {
+    Walk::Continue
+  }
+  fn visit_const(&mut self, node_ref: NodeRef, c: &mut AstConstNode) -> Walk {
+    Walk::Continue
+  }
+
+  fn leave_script(&mut self, node_ref: NodeRef, script: &mut AstScriptNode) {}
+  fn leave_entity(&mut self, node_ref: NodeRef, entity: &mut AstEntityNode) {}
+  fn leave_property(&mut self, node_ref: NodeRef, prop: &mut AstPropertyNode) {}
+  fn leave_function(&mut self, node_ref: NodeRef, func: &mut AstFunctionNode) {}
+  fn leave_operator(&mut self, node_ref: NodeRef, op: &mut AstOperatorNode) {}
+  fn leave_unary_operator(&mut self, node_ref: NodeRef, op: &mut AstUnaryOperatorNode) {}
+  fn leave_formula(&mut self, node_ref: NodeRef, formula: &mut AstFormulaNode) {}
+  fn leave_const(&mut self, node_ref: NodeRef, c: &mut AstConstNode) {}
+}
+
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast, node_ref: NodeRef) -> Walk {
+  let node = match ast.get_node(node_ref) {
+    Some(node) => node,
+    None => return Walk::Continue,
+  };
+  let action = match &node.node_data {
+    Node::Script(script) => visitor.visit_script(ast, node_ref, script),
+    Node::Title(title) => visitor.visit_title(ast, node_ref, title),
+    Node::Entity(entity) => visitor.visit_entity(ast, node_ref, entity),
+    Node::Property(prop) => visitor.visit_property(ast, node_ref, prop),
+    Node::Identifier(identifier) => visitor.visit_identifier(ast, node_ref, identifier),
+    Node::String(string) => visitor.visit_string(ast, node_ref, string),
+    Node::Number(number) => visitor.visit_number(ast, node_ref, number),
+    Node::Boolean(boolean) => visitor.visit_boolean(ast, node_ref, boolean),
+    Node::VPath(vpath) => visitor.visit_vpath(ast, node_ref, vpath),
+    Node::Color(color) => visitor.visit_color(ast, node_ref, color),
+    Node::Reference(r) => visitor.visit_reference(ast, node_ref, r),
+    Node::Function(func) => visitor.visit_function(ast, node_ref, func),
+    Node::Operator(op) => visitor.visit_operator(ast, node_ref, op),
+    Node::UnaryOperator(op) => visitor.visit_unary_operator(ast, node_ref, op),
+    Node::TableAlias(alias) => visitor.visit_table_alias(ast, node_ref, alias),
+    Node::Formula(formula) => visitor.visit_formula(ast, node_ref, formula),
+    Node::Const(c) => visitor.visit_const(ast, node_ref, c),
+  };
+  match action {
+    Walk::Stop => return Walk::Stop,
+    Walk::SkipChildren => return Walk::Continue,
+    Walk::Continue => {}
+  }
+  for child in node.node_data.children() {
+    if walk(visitor, ast, child) == Walk::Stop {
+      return Walk::Stop;
+    }
+  }
+  match &node.node_data {
+    Node::Script(script) => visitor.leave_script(ast, node_ref, script),
+    Node::Entity(entity) => visitor.leave_entity(ast, node_ref, entity),
+    Node::Property(prop) => visitor.leave_property(ast, node_ref, prop),
+    Node::Function(func) => visitor.leave_function(ast, node_ref, func),
+    Node::Operator(op) => visitor.leave_operator(ast, node_ref, op),
+    Node::UnaryOperator(op) => visitor.leave_unary_operator(ast, node_ref, op),
+    Node::Formula(formula) => visitor.leave_formula(ast, node_ref, formula),
+    Node::Const(c) => visitor.leave_const(ast, node_ref, c),
+    _ => {}
+  }
+  Walk::Continue
+}
+
+pub fn walk_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut Ast, node_ref: NodeRef) -> Walk {
+  let index = node_ref.0 as usize;
+  let children = {
+    let nodes = ast.nodes.get_mut();
+    let node = match nodes.get_mut(index) {
+      Some(node) => Rc::make_mut(node),
+      None => return Walk::Continue,
+    };
+    let action = match &mut node.node_data {
+      Node::Script(script) => visitor.visit_script(node_ref, script),
+      Node::Title(title) => visitor.visit_title(node_ref, title),
+      Node::Entity(entity) => visitor.visit_entity(node_ref, entity),
+      Node::Property(prop) => visitor.visit_property(node_ref, prop),
+      Node::Identifier(identifier) => visitor.visit_identifier(node_ref, identifier),
+      Node::String(string) => visitor.visit_string(node_ref, string),
+      Node::Number(number) => visitor.visit_number(node_ref, number),
+      Node::Boolean(boolean) => visitor.visit_boolean(node_ref, boolean),
+      Node::VPath(vpath) => visitor.visit_vpath(node_ref, vpath),
+      Node::Color(color) => visitor.visit_color(node_ref, color),
+      Node::Reference(r) => visitor.visit_reference(node_ref, r),
+      Node::Function(func) => visitor.visit_function(node_ref, func),
+      Node::Operator(op) => visitor.visit_operator(node_ref, op),
+      Node::UnaryOperator(op) => visitor.visit_unary_operator(node_ref, op),
+      Node::TableAlias(alias) => visitor.visit_table_alias(node_ref, alias),
+      Node::Formula(formula) => visitor.visit_formula(node_ref, formula),
+      Node::Const(c) => visitor.visit_const(node_ref, c),
+    };
+    match action {
+      Walk::Stop => return Walk::Stop,
+      Walk::SkipChildren => return Walk::Continue,
+      Walk::Continue => node.node_data.children(),
+    }
+  };
+  for child in children {
+    if walk_mut(visitor, ast, child) == Walk::Stop {
+      return Walk::Stop;
+    }
+  }
+  let node = Rc::make_mut(&mut ast.nodes.get_mut()[index]);
+  match &mut node.node_data {
+    Node::Script(script) => visitor.leave_script(node_ref, script),
+    Node::Entity(entity) => visitor.leave_entity(node_ref, entity),
+    Node::Property(prop) => visitor.leave_property(node_ref, prop),
+    Node::Function(func) => visitor.leave_function(node_ref, func),
+    Node::Operator(op) => visitor.leave_operator(node_ref, op),
+    Node::UnaryOperator(op) => visitor.leave_unary_operator(node_ref, op),
+    Node::Formula(formula) => visitor.leave_formula(node_ref, formula),
+    Node::Const(c) => visitor.leave_const(node_ref, c),
+    _ => {}
+  }
+  Walk::Continue
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::AstNode;
+
+  fn create_ast() -> Ast {
+    let ast = Ast::new();
+    let script_ref = ast.add_node(
+      AstNode::new(
+        Node::Script(AstScriptNode {
+          children: vec![].into(),
+        }),
+        NodeRef(-1),
+      ),
+      0..1,
+    );
+    for name in ["first", "second"] {
+      let entity_ref = ast.add_node(
+        AstNode::new(
+          Node::Entity(AstEntityNode {
+            children: vec![].into(),
+            terms: vec![name.into()],
+            label: None,
+            refs: vec![],
+            ident: Some(name.into()),
+            entity_number: None,
+          }),
+          script_ref,
+        ),
+        0..1,
+      );
+      ast.add_child_to_node(script_ref, entity_ref);
+      let prop_ref = ast.add_node(
+        AstNode::new(
+          Node::Property(AstPropertyNode::new("prop".into())),
+          entity_ref,
+        ),
+        0..1,
+      );
+      ast.add_child_to_node(entity_ref, prop_ref);
+      let number_ref = ast.add_node(
+        AstNode::new(
+          Node::Number(AstNumberNode {
+            value: 1.0,
+            raw: None,
+          }),
+          prop_ref,
+        ),
+        0..1,
+      );
+      ast.add_child_to_node(prop_ref, number_ref);
+    }
+    ast
+  }
+
+  #[derive(Default)]
+  struct Recorder {
+    events: Vec<String>,
+    skip_entities: bool,
+    stop_at_number: bool,
+  }
+
+  impl Visitor for Recorder {
+    fn visit_entity(&mut self, _ast: &Ast, _node_ref: NodeRef, entity: &AstEntityNode) -> Walk {
+      self.events.push(format!("enter {}", entity.terms[0]));
+      if self.skip_entities {
+        Walk::SkipChildren
+      } else {
+        Walk::Continue
+      }
+    }
+    fn visit_number(&mut self, _ast: &Ast, _node_ref: NodeRef, number: &AstNumberNode) -> Walk {
+      self.events.push(format!("number {}", number.value));
+      if self.stop_at_number {
+        Walk::Stop
+      } else {
+        Walk::Continue
+      }
+    }
+    fn leave_entity(&mut self, _ast: &Ast, _node_ref: NodeRef, entity: &AstEntityNode) {
+      self.events.push(format!("leave {}", entity.terms[0]));
+    }
+  }
+
+  #[test]
+  fn walks_pre_and_post_order() {
+    let ast = create_ast();
+    let mut recorder = Recorder::default();
+    ast.walk(&mut recorder);
+    assert_eq!(
+      vec![
+        "enter first",
+        "number 1",
+        "leave first",
+        "enter second",
+        "number 1",
+        "leave second"
+      ],
+      recorder.events
+    );
+  }
+
+  #[test]
+  fn can_skip_children() {
+    let ast = create_ast();
+    let mut recorder = Recorder {
+      skip_entities: true,
+      ..Default::default()
+    };
+    ast.walk(&mut recorder);
+    assert_eq!(vec!["enter first", "enter second"], recorder.events);
+  }
+
+  #[test]
+  fn can_stop_walking() {
+    let ast = create_ast();
+    let mut recorder = Recorder {
+      stop_at_number: true,
+      ..Default::default()
+    };
+    assert_eq!(Walk::Stop, ast.walk(&mut recorder));
+    assert_eq!(vec!["enter first", "number 1"], recorder.events);
+  }
+
+  struct Doubler;
+
+  impl VisitorMut for Doubler {
+    fn visit_number(&mut self, _node_ref: NodeRef, number: &mut AstNumberNode) -> Walk {
+      number.value *= 2.0;
+      Walk::Continue
+    }
+  }
+
+  #[test]
+  fn can_mutate_nodes_without_touching_clones() {
+    let mut ast = create_ast();
+    let original = ast.clone();
+    ast.walk_mut(&mut Doubler);
+    if let Node::Number(number) = &ast.get_node(NodeRef(3)).unwrap().node_data {
+      assert_eq!(2.0, number.value);
+    }
+    if let Node::Number(number) = &original.get_node(NodeRef(3)).unwrap().node_data {
+      assert_eq!(1.0, number.value);
+    }
+  }
+}
@@ -0,0 +1,139 @@
+#ifndef CDL_FFI_H
+#define CDL_FFI_H
+
+/* Generated with cbindgen from crates/cdl-ffi, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum CdlSeverity {
+  CDL_SEVERITY_ERROR = 0,
+  CDL_SEVERITY_WARNING = 1,
+} CdlSeverity;
+
+/**
+ * A parsed script, with its comments for printing it back.
+ */
+typedef struct CdlAst CdlAst;
+
+/**
+ * Diagnostics from parsing or checking a script.
+ */
+typedef struct CdlDiagnostics CdlDiagnostics;
+
+/**
+ * Byte offsets and 1-based line and column of a diagnostic.
+ */
+typedef struct CdlLocation {
+  size_t start;
+  size_t end;
+  size_t start_line;
+  size_t start_column;
+  size_t end_line;
+  size_t end_column;
+} CdlLocation;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Parses `text`. Returns null on failure, then `diagnostics_out`, when not null, is set to a
+ * handle describing the errors. On success it is set to null.
+ *
+ * # Safety
+ * `text` must be a nul terminated string and `diagnostics_out` null or valid for writes.
+ */
+struct CdlAst *cdl_parse(const char *text, struct CdlDiagnostics **diagnostics_out);
+
+/**
+ * Parses, processes and checks `text`, returning all errors and warnings. Never returns null.
+ *
+ * # Safety
+ * `text` must be a nul terminated string.
+ */
+struct CdlDiagnostics *cdl_diagnostics(const char *text);
+
+/**
+ * # Safety
+ * `diagnostics` must be null or a handle from this library.
+ */
+size_t cdl_diagnostics_count(const struct CdlDiagnostics *diagnostics);
+
+/**
+ * The message of the diagnostic at `index`, borrowed from the handle. Null when out of range.
+ *
+ * # Safety
+ * `diagnostics` must be null or a handle from this library.
+ */
+const char *cdl_diagnostics_message(const struct CdlDiagnostics *diagnostics, size_t index);
+
+/**
+ * # Safety
+ * `diagnostics` must be null or a handle from this library.
+ */
+enum CdlSeverity cdl_diagnostics_severity(const struct CdlDiagnostics *diagnostics, size_t index);
+
+/**
+ * Writes the location of the diagnostic at `index` to `location_out`. Returns false when the
+ * diagnostic has no location or `index` is out of range.
+ *
+ * # Safety
+ * `diagnostics` must be null or a handle from this library, `location_out` valid for writes.
+ */
+bool cdl_diagnostics_location(const struct CdlDiagnostics *diagnostics,
+                              size_t index,
+                              struct CdlLocation *location_out);
+
+/**
+ * # Safety
+ * `diagnostics` must be null or a handle from this library that has not been freed.
+ */
+void cdl_diagnostics_free(struct CdlDiagnostics *diagnostics);
+
+/**
+ * The ast as json. Release with [`cdl_string_free`], null on failure.
+ *
+ * # Safety
+ * `ast` must be null or a handle from this library.
+ */
+char *cdl_ast_to_json(const struct CdlAst *ast);
+
+/**
+ * The script printed back as formatted CDL, with its comments. Release with
+ * [`cdl_string_free`], null on failure.
+ *
+ * # Safety
+ * `ast` must be null or a handle from this library.
+ */
+char *cdl_ast_to_cdl(const struct CdlAst *ast);
+
+/**
+ * The properties matching `property`, as a json array of
+ * `{ node_ref, pos, location, cdl }`. Release with [`cdl_string_free`], null on failure.
+ *
+ * # Safety
+ * `ast` must be null or a handle from this library, `property` a nul terminated string.
+ */
+char *cdl_ast_select(const struct CdlAst *ast, const char *property);
+
+/**
+ * # Safety
+ * `ast` must be null or a handle from this library that has not been freed.
+ */
+void cdl_ast_free(struct CdlAst *ast);
+
+/**
+ * # Safety
+ * `text` must be null or a string returned by this library that has not been freed.
+ */
+void cdl_string_free(char *text);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CDL_FFI_H */
@@ -0,0 +1,278 @@
+//! C API for the compiler. Parsed scripts and diagnostics are handed out as opaque handles that
+//! must be released with their `_free` function. Strings returned to the caller are owned by the
+//! caller and released with [`cdl_string_free`], strings borrowed from a handle live as long as
+//! the handle. Handles are not thread safe.
+
+use std::{
+  ffi::{c_char, CStr, CString},
+  panic::{catch_unwind, AssertUnwindSafe},
+  ptr,
+};
+
+use api::{Diagnostic, Severity};
+use ast::{Ast, Comments, DEFAULT_WIDTH};
+
+/// A parsed script, with its comments for printing it back.
+pub struct CdlAst {
+  ast: Ast,
+  comments: Comments,
+}
+
+/// Diagnostics from parsing or checking a script.
+pub struct CdlDiagnostics {
+  items: Vec<Diagnostic>,
+  messages: Vec<CString>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CdlSeverity {
+  Error = 0,
+  Warning = 1,
+}
+
+/// Byte offsets and 1-based line and column of a diagnostic.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CdlLocation {
+  pub start: usize,
+  pub end: usize,
+  pub start_line: usize,
+  pub start_column: usize,
+  pub end_line: usize,
+  pub end_column: usize,
+}
+
+impl CdlDiagnostics {
+  fn new(items: Vec<Diagnostic>) -> CdlDiagnostics {
+    let messages = items
+      .iter()
+      .map(|d| to_cstring(d.message.clone()))
+      .collect();
+    CdlDiagnostics { items, messages }
+  }
+}
+
+fn to_cstring(text: String) -> CString {
+  // interior nul bytes can not be represented, drop them rather than the whole string
+  CString::new(text.replace('\0', "")).unwrap_or_default()
+}
+
+fn into_raw_string(text: String) -> *mut c_char {
+  to_cstring(text).into_raw()
+}
+
+/// Reads a nul terminated UTF-8 string, None for null pointers and invalid UTF-8.
+unsafe fn read_str<'a>(text: *const c_char) -> Option<&'a str> {
+  if text.is_null() {
+    return None;
+  }
+  CStr::from_ptr(text).to_str().ok()
+}
+
+/// Panics must not unwind into the caller, they are turned into `default`.
+fn guard<T>(default: T, f: impl FnOnce() -> T) -> T {
+  catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+fn error(message: &str) -> Vec<Diagnostic> {
+  vec![Diagnostic::error(message.to_string())]
+}
+
+unsafe fn store_diagnostics(out: *mut *mut CdlDiagnostics, items: Vec<Diagnostic>) {
+  if !out.is_null() {
+    *out = Box::into_raw(Box::new(CdlDiagnostics::new(items)));
+  }
+}
+
+/// Parses `text`. Returns null on failure, then `diagnostics_out`, when not null, is set to a
+/// handle describing the errors. On success it is set to null.
+///
+/// # Safety
+/// `text` must be a nul terminated string and `diagnostics_out` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_parse(
+  text: *const c_char,
+  diagnostics_out: *mut *mut CdlDiagnostics,
+) -> *mut CdlAst {
+  if !diagnostics_out.is_null() {
+    *diagnostics_out = ptr::null_mut();
+  }
+  let Some(text) = read_str(text) else {
+    store_diagnostics(diagnostics_out, error("Input is not valid UTF-8"));
+    return ptr::null_mut();
+  };
+  let result = guard(None, || Some(api::parse_with_comments(text)));
+  match result {
+    Some(Ok((ast, comments))) => Box::into_raw(Box::new(CdlAst { ast, comments })),
+    Some(Err(diagnostics)) => {
+      store_diagnostics(diagnostics_out, diagnostics);
+      ptr::null_mut()
+    }
+    None => {
+      store_diagnostics(diagnostics_out, error("Internal error while parsing"));
+      ptr::null_mut()
+    }
+  }
+}
+
+/// Parses, processes and checks `text`, returning all errors and warnings. Never returns null.
+///
+/// # Safety
+/// `text` must be a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_diagnostics(text: *const c_char) -> *mut CdlDiagnostics {
+  let items = match read_str(text) {
+    Some(text) => guard(error("Internal error while checking"), || {
+      api::diagnostics(text)
+    }),
+    None => error("Input is not valid UTF-8"),
+  };
+  Box::into_raw(Box::new(CdlDiagnostics::new(items)))
+}
+
+/// # Safety
+/// `diagnostics` must be null or a handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_diagnostics_count(diagnostics: *const CdlDiagnostics) -> usize {
+  diagnostics.as_ref().map_or(0, |d| d.items.len())
+}
+
+/// The message of the diagnostic at `index`, borrowed from the handle. Null when out of range.
+///
+/// # Safety
+/// `diagnostics` must be null or a handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_diagnostics_message(
+  diagnostics: *const CdlDiagnostics,
+  index: usize,
+) -> *const c_char {
+  diagnostics
+    .as_ref()
+    .and_then(|d| d.messages.get(index))
+    .map_or(ptr::null(), |m| m.as_ptr())
+}
+
+/// # Safety
+/// `diagnostics` must be null or a handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_diagnostics_severity(
+  diagnostics: *const CdlDiagnostics,
+  index: usize,
+) -> CdlSeverity {
+  match diagnostics.as_ref().and_then(|d| d.items.get(index)) {
+    Some(Diagnostic {
+      severity: Severity::Warning,
+      ..
+    }) => CdlSeverity::Warning,
+    _ => CdlSeverity::Error,
+  }
+}
+
+/// Writes the location of the diagnostic at `index` to `location_out`. Returns false when the
+/// diagnostic has no location or `index` is out of range.
+///
+/// # Safety
+/// `diagnostics` must be null or a handle from this library, `location_out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_diagnostics_location(
+  diagnostics: *const CdlDiagnostics,
+  index: usize,
+  location_out: *mut CdlLocation,
+) -> bool {
+  let Some(diagnostic) = diagnostics.as_ref().and_then(|d| d.items.get(index)) else {
+    return false;
+  };
+  let (Some(pos), Some(location)) = (&diagnostic.pos, &diagnostic.location) else {
+    return false;
+  };
+  if location_out.is_null() {
+    return false;
+  }
+  *location_out = CdlLocation {
+    start: pos.start,
+    end: pos.end,
+    start_line: location.start_line,
+    start_column: location.start_pos,
+    end_line: location.end_line,
+    end_column: location.end_pos,
+  };
+  true
+}
+
+/// # Safety
+/// `diagnostics` must be null or a handle from this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_diagnostics_free(diagnostics: *mut CdlDiagnostics) {
+  if !diagnostics.is_null() {
+    drop(Box::from_raw(diagnostics));
+  }
+}
+
+/// The ast as json. Release with [`cdl_string_free`], null on failure.
+///
+/// # Safety
+/// `ast` must be null or a handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_ast_to_json(ast: *const CdlAst) -> *mut c_char {
+  let Some(ast) = ast.as_ref() else {
+    return ptr::null_mut();
+  };
+  guard(None, || serde_json::to_string(&ast.ast).ok()).map_or(ptr::null_mut(), into_raw_string)
+}
+
+/// The script printed back as formatted CDL, with its comments. Release with
+/// [`cdl_string_free`], null on failure.
+///
+/// # Safety
+/// `ast` must be null or a handle from this library.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_ast_to_cdl(ast: *const CdlAst) -> *mut c_char {
+  let Some(ast) = ast.as_ref() else {
+    return ptr::null_mut();
+  };
+  guard(None, || {
+    ast
+      .ast
+      .to_cdl_with_comments(DEFAULT_WIDTH, &ast.comments)
+      .ok()
+  })
+  .map_or(ptr::null_mut(), into_raw_string)
+}
+
+/// The properties matching `property`, as a json array of
+/// `{ node_ref, pos, location, cdl }`. Release with [`cdl_string_free`], null on failure.
+///
+/// # Safety
+/// `ast` must be null or a handle from this library, `property` a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_ast_select(
+  ast: *const CdlAst,
+  property: *const c_char,
+) -> *mut c_char {
+  let (Some(ast), Some(property)) = (ast.as_ref(), read_str(property)) else {
+    return ptr::null_mut();
+  };
+  let selections = guard(None, || api::select_in(&ast.ast, property).ok());
+  selections
+    .and_then(|s| serde_json::to_string(&s).ok())
+    .map_or(ptr::null_mut(), into_raw_string)
+}
+
+/// # Safety
+/// `ast` must be null or a handle from this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_ast_free(ast: *mut CdlAst) {
+  if !ast.is_null() {
+    drop(Box::from_raw(ast));
+  }
+}
+
+/// # Safety
+/// `text` must be null or a string returned by this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn cdl_string_free(text: *mut c_char) {
+  if !text.is_null() {
+    drop(CString::from_raw(text));
+  }
+}
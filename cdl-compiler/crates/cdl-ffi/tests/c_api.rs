@@ -0,0 +1,113 @@
+use std::{
+  ffi::{c_char, CStr, CString},
+  ptr,
+};
+
+use cdl_ffi::*;
+
+const SCRIPT: &str = "widget kpi #a {\n  label: \"foo\"\n}\n";
+
+unsafe fn take_string(text: *mut c_char) -> String {
+  assert!(!text.is_null());
+  let result = CStr::from_ptr(text).to_str().unwrap().to_string();
+  cdl_string_free(text);
+  result
+}
+
+#[test]
+fn parse_and_print() {
+  let text = CString::new("widget kpi #a {\n  label:   \"foo\"\n}\n").unwrap();
+  unsafe {
+    let mut diagnostics = ptr::null_mut();
+    let ast = cdl_parse(text.as_ptr(), &mut diagnostics);
+    assert!(!ast.is_null());
+    assert!(diagnostics.is_null());
+    assert_eq!(SCRIPT, take_string(cdl_ast_to_cdl(ast)));
+    let json: serde_json::Value = serde_json::from_str(&take_string(cdl_ast_to_json(ast))).unwrap();
+    assert!(json["nodes"].is_array());
+    cdl_ast_free(ast);
+  }
+}
+
+#[test]
+fn print_keeps_comments() {
+  let script = "// kpis\nwidget kpi #a {\n  label: \"foo\" // shown on top\n}\n";
+  let text = CString::new(script).unwrap();
+  unsafe {
+    let ast = cdl_parse(text.as_ptr(), ptr::null_mut());
+    assert_eq!(script, take_string(cdl_ast_to_cdl(ast)));
+    cdl_ast_free(ast);
+  }
+}
+
+#[test]
+fn parse_error_gives_diagnostics() {
+  let text = CString::new("widget kpi {\n  label: &\n}\n").unwrap();
+  unsafe {
+    let mut diagnostics = ptr::null_mut();
+    let ast = cdl_parse(text.as_ptr(), &mut diagnostics);
+    assert!(ast.is_null());
+    assert_eq!(1, cdl_diagnostics_count(diagnostics));
+    let message = CStr::from_ptr(cdl_diagnostics_message(diagnostics, 0));
+    assert_eq!("Unknown token \"&\"", message.to_str().unwrap());
+    assert_eq!(CdlSeverity::Error, cdl_diagnostics_severity(diagnostics, 0));
+    let mut location = CdlLocation::default();
+    assert!(cdl_diagnostics_location(diagnostics, 0, &mut location));
+    assert_eq!((2, 10), (location.start_line, location.start_column));
+    assert_eq!(22..23, location.start..location.end);
+    assert!(cdl_diagnostics_message(diagnostics, 1).is_null());
+    assert!(!cdl_diagnostics_location(diagnostics, 1, &mut location));
+    cdl_diagnostics_free(diagnostics);
+  }
+}
+
+#[test]
+fn diagnostics_include_warnings() {
+  let text = CString::new("const unused = 1\nwidget kpi #a {\n  label: \"foo\"\n}\n").unwrap();
+  unsafe {
+    let diagnostics = cdl_diagnostics(text.as_ptr());
+    assert_eq!(1, cdl_diagnostics_count(diagnostics));
+    assert_eq!(
+      CdlSeverity::Warning,
+      cdl_diagnostics_severity(diagnostics, 0)
+    );
+    cdl_diagnostics_free(diagnostics);
+
+    let text = CString::new(SCRIPT).unwrap();
+    let diagnostics = cdl_diagnostics(text.as_ptr());
+    assert_eq!(0, cdl_diagnostics_count(diagnostics));
+    cdl_diagnostics_free(diagnostics);
+  }
+}
+
+#[test]
+fn select_returns_json() {
+  let text = CString::new(SCRIPT).unwrap();
+  let property = CString::new("label").unwrap();
+  unsafe {
+    let ast = cdl_parse(text.as_ptr(), ptr::null_mut());
+    let selections = take_string(cdl_ast_select(ast, property.as_ptr()));
+    let selections: serde_json::Value = serde_json::from_str(&selections).unwrap();
+    assert_eq!("label: \"foo\"", selections[0]["cdl"]);
+    cdl_ast_free(ast);
+  }
+}
+
+#[test]
+fn null_and_invalid_input_is_handled() {
+  unsafe {
+    let mut diagnostics = ptr::null_mut();
+    assert!(cdl_parse(ptr::null(), &mut diagnostics).is_null());
+    assert_eq!(1, cdl_diagnostics_count(diagnostics));
+    cdl_diagnostics_free(diagnostics);
+
+    let invalid = [0xffu8 as c_char, 0];
+    assert!(cdl_parse(invalid.as_ptr(), ptr::null_mut()).is_null());
+    assert!(cdl_ast_to_cdl(ptr::null()).is_null());
+    assert!(cdl_ast_select(ptr::null(), ptr::null()).is_null());
+    assert_eq!(0, cdl_diagnostics_count(ptr::null()));
+    cdl_ast_free(ptr::null_mut());
+    cdl_diagnostics_free(ptr::null_mut());
+    cdl_string_free(ptr::null_mut());
+  }
+}
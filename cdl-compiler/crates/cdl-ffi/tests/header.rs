@@ -0,0 +1,23 @@
+use std::{env, fs, path::PathBuf};
+
+/// The header is checked in so consumers do not need cbindgen. Run with `UPDATE_HEADER=1` to
+/// regenerate it after changing the API.
+#[test]
+fn header_is_up_to_date() {
+  let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+  let mut generated = vec![];
+  cbindgen::generate_with_config(&crate_dir, config)
+    .unwrap()
+    .write(&mut generated);
+  let generated = String::from_utf8(generated).unwrap();
+  let path = crate_dir.join("include/cdl_ffi.h");
+  if env::var("UPDATE_HEADER").is_ok() {
+    fs::write(&path, &generated).unwrap();
+  }
+  let checked_in = fs::read_to_string(&path).unwrap_or_default();
+  assert!(
+    checked_in == generated,
+    "include/cdl_ffi.h is out of date, run the tests with UPDATE_HEADER=1"
+  );
+}
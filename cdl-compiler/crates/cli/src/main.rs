@@ -1,18 +1,23 @@
+mod repl;
+mod watch;
+
 use std::{
   env,
   fs::{self, File},
-  io::{BufReader, BufWriter},
+  io::{self, BufReader, BufWriter, Write},
   path::{Path, PathBuf},
-  time::Instant,
+  process,
+  time::{Duration, Instant},
 };
 
+use anyhow::Context;
 use ast::{Ast, Node, NodeRef};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use lexer::LexedStr;
-use node_processing::NodeProcessor;
+use node_processing::PassManager;
 use parser::parse_text;
 use tempfile::TempDir;
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_flame::FlameLayer;
 use tracing_subscriber::{prelude::*, registry::Registry};
 
@@ -24,6 +29,251 @@ struct Cli {
 
   #[arg(short, long, default_value_t = false)]
   graph: bool,
+
+  #[command(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Semantic diff of two scripts, exits with 1 when they differ
+  Diff {
+    old: String,
+    new: String,
+
+    #[arg(long, default_value_t = false)]
+    json: bool,
+  },
+  /// Three-way merge, prints the result or writes it to --output. Exits with 1 on conflicts
+  Merge {
+    base: String,
+    ours: String,
+    theirs: String,
+
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Git merge driver, writes the result over ours. Register it with
+  /// `git config merge.cdl.driver "cli merge-driver %O %A %B"` and `*.cdl merge=cdl` in
+  /// .gitattributes
+  MergeDriver {
+    base: String,
+    ours: String,
+    theirs: String,
+  },
+  /// Nested dashboard model as json, or its TypeScript declarations with --typescript
+  Export {
+    file: String,
+
+    /// Keep references as links instead of inlining their values
+    #[arg(long, default_value_t = false)]
+    link: bool,
+
+    #[arg(long, default_value_t = false)]
+    typescript: bool,
+  },
+  /// Html page documenting the pages, widgets, formulas and tables of a script, or Markdown with
+  /// --markdown
+  Docs {
+    file: String,
+
+    #[arg(long, default_value_t = false)]
+    markdown: bool,
+
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Translatable labels and titles as a PO catalogue, or json when --output ends in .json
+  ExtractLabels {
+    file: String,
+
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Localised script from a translated PO or json catalogue. Exits with 1 when translations are
+  /// missing or stale
+  Localise {
+    file: String,
+    catalogue: String,
+
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Script with names, strings and hub numbers replaced, for attaching to bug reports
+  Anonymise {
+    file: String,
+
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Write the replaced names as json, to map errors back to the original
+    #[arg(long)]
+    mapping: Option<String>,
+  },
+  /// Size and complexity report for a script
+  Stats {
+    file: String,
+
+    #[arg(long, default_value_t = false)]
+    json: bool,
+  },
+  /// Interactive shell for exploring a script
+  Repl { file: String },
+  /// Check a directory of scripts, and check files again when they change
+  Watch {
+    dir: String,
+
+    /// Wait until a file has been left alone this long before checking it
+    #[arg(long, default_value_t = 200)]
+    debounce: u64,
+
+    /// One json event per line, for editor plugins
+    #[arg(long, default_value_t = false)]
+    json: bool,
+  },
+}
+
+fn run_diff(old: &str, new: &str, json: bool) -> anyhow::Result<bool> {
+  let old = fs::read_to_string(old).with_context(|| format!("Could not read {}", old))?;
+  let new = fs::read_to_string(new).with_context(|| format!("Could not read {}", new))?;
+  let report = diff::diff_text(&old, &new)?;
+  let mut out = io::stdout().lock();
+  let written = if json {
+    writeln!(out, "{}", serde_json::to_string_pretty(&report)?)
+  } else {
+    write!(out, "{}", report)
+  };
+  ignore_broken_pipe(written.and_then(|_| out.flush()))?;
+  Ok(!report.is_empty())
+}
+
+fn run_merge(base: &str, ours: &str, theirs: &str, output: Option<&str>) -> anyhow::Result<bool> {
+  let read =
+    |path: &str| fs::read_to_string(path).with_context(|| format!("Could not read {}", path));
+  let result = diff::merge_text(&read(base)?, &read(ours)?, &read(theirs)?)?;
+  match output {
+    Some(output) => {
+      fs::write(output, &result.text).with_context(|| format!("Could not write {}", output))?
+    }
+    None => {
+      let mut out = io::stdout().lock();
+      ignore_broken_pipe(write!(out, "{}", result.text).and_then(|_| out.flush()))?
+    }
+  }
+  for conflict in &result.conflicts {
+    match &conflict.property {
+      Some(property) => eprintln!("conflict in {}.{}", conflict.entity, property),
+      None => eprintln!("conflict in {} ({:?})", conflict.entity, conflict.kind),
+    }
+  }
+  Ok(!result.is_clean())
+}
+
+fn run_export(file: &str, link: bool, typescript: bool) -> anyhow::Result<bool> {
+  let text = fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+  let references = if link {
+    codegen::References::Link
+  } else {
+    codegen::References::Inline
+  };
+  let model = codegen::lower_text(&text, references)?;
+  if typescript {
+    print!("{}", codegen::typescript(&model));
+  } else {
+    println!("{}", serde_json::to_string_pretty(&model)?);
+  }
+  Ok(false)
+}
+
+fn run_docs(file: &str, markdown: bool, output: Option<&str>) -> anyhow::Result<bool> {
+  let text = fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+  let format = if markdown {
+    codegen::DocsFormat::Markdown
+  } else {
+    codegen::DocsFormat::Html
+  };
+  let docs = codegen::docs_text(&text, format)?;
+  match output {
+    Some(output) => {
+      fs::write(output, docs).with_context(|| format!("Could not write {}", output))?
+    }
+    None => print!("{}", docs),
+  }
+  Ok(false)
+}
+
+fn run_extract_labels(file: &str, output: Option<&str>) -> anyhow::Result<bool> {
+  let text = fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+  let catalogue = i18n::Catalogue::from_sources(&i18n::extract(&text)?);
+  match output {
+    Some(output) => {
+      let catalogue = catalogue.write(i18n::Format::from_path(output))?;
+      fs::write(output, catalogue).with_context(|| format!("Could not write {}", output))?
+    }
+    None => print!("{}", catalogue.write(i18n::Format::Po)?),
+  }
+  Ok(false)
+}
+
+fn run_localise(file: &str, catalogue: &str, output: Option<&str>) -> anyhow::Result<bool> {
+  let read =
+    |path: &str| fs::read_to_string(path).with_context(|| format!("Could not read {}", path));
+  let catalogue = i18n::Catalogue::read(&read(catalogue)?, i18n::Format::from_path(catalogue))?;
+  let localised = i18n::localise(&read(file)?, &catalogue)?;
+  match output {
+    Some(output) => {
+      fs::write(output, &localised.text).with_context(|| format!("Could not write {}", output))?
+    }
+    None => print!("{}", localised.text),
+  }
+  eprint!("{}", localised);
+  Ok(!localised.is_complete())
+}
+
+fn run_anonymise(file: &str, output: Option<&str>, mapping: Option<&str>) -> anyhow::Result<bool> {
+  let text = fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+  let anonymised = anonymise::anonymise(&text)?;
+  match output {
+    Some(output) => {
+      fs::write(output, &anonymised.text).with_context(|| format!("Could not write {}", output))?
+    }
+    None => print!("{}", anonymised.text),
+  }
+  if let Some(mapping) = mapping {
+    let renames = serde_json::to_string_pretty(&anonymised.renames)?;
+    fs::write(mapping, renames).with_context(|| format!("Could not write {}", mapping))?
+  }
+  Ok(false)
+}
+
+fn run_stats(file: &str, json: bool) -> anyhow::Result<bool> {
+  let text = fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+  let stats = node_processing::script_stats(&text)?;
+  if json {
+    println!("{}", serde_json::to_string_pretty(&stats)?);
+  } else {
+    print!("{}", stats);
+  }
+  Ok(false)
+}
+
+/// Output piped into a command that stops reading early, like `cli diff a b | head`, is not an
+/// error.
+fn ignore_broken_pipe(result: io::Result<()>) -> io::Result<()> {
+  match result {
+    Err(error) if error.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+    result => result,
+  }
+}
+
+fn exit_with(result: anyhow::Result<bool>) -> ! {
+  match result {
+    Ok(failed) => process::exit(failed as i32),
+    Err(error) => {
+      eprintln!("{:#}", error);
+      process::exit(2);
+    }
+  }
 }
 
 fn compare_rc_str_to_filters(needle: &LexedStr, filters: &Vec<&str>) -> bool {
@@ -39,7 +289,6 @@ fn compare_rc_str_to_filters(needle: &LexedStr, filters: &Vec<&str>) -> bool {
 fn find_filters(ast: &Ast, filters: &Vec<&str>) -> Vec<NodeRef> {
   let mut result = vec![];
   for (index, node) in ast.nodes.borrow().iter().enumerate() {
-    
     match &node.node_data {
       Node::Entity(ent) => {
         if let Some(id) = &ent.ident {
@@ -78,13 +327,56 @@ fn make_flamegraph(tmpdir: &Path, out: &Path) {
   let mut opts = inferno::flamegraph::Options::default();
   inferno::flamegraph::from_reader(&mut opts, reader, writer).unwrap();
 }
-struct DropDummy;
-impl Drop for DropDummy {
-  fn drop(&mut self) {}
-}
 fn main() {
   //tracing_subscriber::fmt::init();
   let cli = Cli::parse();
+  match &cli.command {
+    Some(Command::Diff { old, new, json }) => exit_with(run_diff(old, new, *json)),
+    Some(Command::Merge {
+      base,
+      ours,
+      theirs,
+      output,
+    }) => exit_with(run_merge(base, ours, theirs, output.as_deref())),
+    Some(Command::MergeDriver { base, ours, theirs }) => {
+      exit_with(run_merge(base, ours, theirs, Some(ours)))
+    }
+    Some(Command::Export {
+      file,
+      link,
+      typescript,
+    }) => exit_with(run_export(file, *link, *typescript)),
+    Some(Command::Docs {
+      file,
+      markdown,
+      output,
+    }) => exit_with(run_docs(file, *markdown, output.as_deref())),
+    Some(Command::ExtractLabels { file, output }) => {
+      exit_with(run_extract_labels(file, output.as_deref()))
+    }
+    Some(Command::Localise {
+      file,
+      catalogue,
+      output,
+    }) => exit_with(run_localise(file, catalogue, output.as_deref())),
+    Some(Command::Anonymise {
+      file,
+      output,
+      mapping,
+    }) => exit_with(run_anonymise(file, output.as_deref(), mapping.as_deref())),
+    Some(Command::Stats { file, json }) => exit_with(run_stats(file, *json)),
+    Some(Command::Repl { file }) => exit_with(repl::run_repl(file)),
+    Some(Command::Watch {
+      dir,
+      debounce,
+      json,
+    }) => exit_with(watch::run_watch(
+      dir,
+      *json,
+      Duration::from_millis(*debounce),
+    )),
+    None => {}
+  }
 
   let (guard, out, tmp_dir) = if cli.graph {
     let out = {
@@ -187,8 +479,16 @@ fn main() {
     info!("time taken to find {} nodes: {:.2?}", found.len(), elapsed);
 
     let now = Instant::now();
-    let np = NodeProcessor::new(ast);
-    let _processed_ast = np.process().unwrap();
+    let (_processed_ast, report) = PassManager::new().run(ast);
+    for phase in &report.phases {
+      info!(
+        "time taken for phase {}: {:.2?}",
+        phase.phase, phase.duration
+      );
+      for diagnostic in &phase.diagnostics {
+        warn!("{:?}: {}", diagnostic.severity, diagnostic.message);
+      }
+    }
 
     let elapsed = now.elapsed();
     info!("time taken to process ast: {:.2?}", elapsed);
@@ -0,0 +1,527 @@
+use std::{
+  borrow::Cow,
+  collections::{BTreeMap, BTreeSet},
+  fmt::Write as _,
+  fs,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ast::{select_property, Ast, Node, NodeRef, Operator, UnaryOperator};
+use node_processing::NodeProcessor;
+use parser::parse_text;
+use rustyline::{
+  completion::{Completer, Pair},
+  error::ReadlineError,
+  highlight::Highlighter,
+  hint::Hinter,
+  history::DefaultHistory,
+  validate::Validator,
+  Context as LineContext, Editor, Helper,
+};
+
+const COMMANDS: &[&str] = &["help", "select", "show", "refs", "type", "loc", "quit"];
+
+const HELP: &str = "\
+select <property>   every property with that name
+show <target>       the node printed as CDL
+refs <target>       references to and from the node
+type <target>       the type of an expression
+loc <target>        where the node is in the script
+quit                leave the shell
+
+A target is an entity `#ident`, a property of one `#ident.property`, a const name or a node number.
+";
+
+/// A processed script and the commands run against it, apart from the line editor.
+pub struct Session {
+  ast: Ast,
+  /// Entity idents and const names.
+  idents: BTreeMap<String, NodeRef>,
+  properties: BTreeSet<String>,
+  /// Processing errors, the script is still usable with them.
+  pub errors: Vec<String>,
+}
+
+impl Session {
+  pub fn load(text: &str) -> Result<Session> {
+    let (ast, error) = NodeProcessor::new(parse_text(text)?).process_partial();
+    let mut idents = BTreeMap::new();
+    let mut properties = BTreeSet::new();
+    for (index, node) in ast.nodes.borrow().iter().enumerate() {
+      match &node.node_data {
+        Node::Entity(entity) => {
+          if let Some(ident) = &entity.ident {
+            // inherited copies share the ident, the first one is the declaration
+            idents.entry(ident.to_string()).or_insert(index.into());
+          }
+        }
+        Node::Const(constant) => {
+          idents
+            .entry(constant.name.to_string())
+            .or_insert(index.into());
+        }
+        Node::Property(property) => {
+          properties.insert(property.name.to_string());
+        }
+        _ => {}
+      }
+    }
+    Ok(Session {
+      ast,
+      idents,
+      properties,
+      errors: error
+        .map(|e| e.diagnostics.into_iter().map(|d| d.message).collect())
+        .unwrap_or_default(),
+    })
+  }
+
+  pub fn node_count(&self) -> usize {
+    self.ast.nodes.borrow().len()
+  }
+
+  /// Runs one line of input and returns what to print.
+  pub fn run(&self, line: &str) -> Result<String> {
+    let line = line.trim();
+    let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let argument = argument.trim();
+    let needs_argument = || match argument.is_empty() {
+      true => Err(anyhow!("{} needs an argument, see help", command)),
+      false => Ok(argument),
+    };
+    match command {
+      "" => Ok(String::new()),
+      "help" => Ok(HELP.to_string()),
+      "select" => {
+        let nodes = select_property(&self.ast, needs_argument()?);
+        Ok(self.list(&nodes))
+      }
+      "show" => {
+        let node_ref = self.target(needs_argument()?)?;
+        Ok(format!("{}\n", self.ast.node_to_cdl(node_ref)?.trim_end()))
+      }
+      "refs" => self.refs(self.target(needs_argument()?)?),
+      "type" => Ok(format!(
+        "{}\n",
+        self.type_of(self.target(needs_argument()?)?, 0)
+      )),
+      "loc" => {
+        let node_ref = self.target(needs_argument()?)?;
+        let location = self
+          .ast
+          .get_location_for_node(node_ref)
+          .ok_or_else(|| anyhow!("No location for node {}", node_ref.0))?;
+        Ok(format!(
+          "{}:{}-{}:{}\n",
+          location.start_line, location.start_pos, location.end_line, location.end_pos
+        ))
+      }
+      _ => bail!("Unknown command {}, see help", command),
+    }
+  }
+
+  fn target(&self, target: &str) -> Result<NodeRef> {
+    if let Ok(index) = target.parse::<usize>() {
+      return match index < self.node_count() {
+        true => Ok(index.into()),
+        false => Err(anyhow!("No node {}", index)),
+      };
+    }
+    let (ident, property) = match target.trim_start_matches('#').split_once('.') {
+      Some((ident, property)) => (ident, Some(property)),
+      None => (target.trim_start_matches('#'), None),
+    };
+    let node_ref = *self
+      .idents
+      .get(ident)
+      .ok_or_else(|| anyhow!("No entity or const named {}", ident))?;
+    let Some(property) = property else {
+      return Ok(node_ref);
+    };
+    self
+      .children(node_ref)
+      .into_iter()
+      .find(|child| {
+        matches!(self.ast.get_node(*child).map(|n| n.node_data.clone()),
+          Some(Node::Property(p)) if p.name.as_str() == property)
+      })
+      .ok_or_else(|| anyhow!("#{} has no property {}", ident, property))
+  }
+
+  fn children(&self, node_ref: NodeRef) -> Vec<NodeRef> {
+    self
+      .ast
+      .get_node(node_ref)
+      .map(|n| n.node_data.children())
+      .unwrap_or_default()
+  }
+
+  /// `line:col  node number  first line of its CDL`, one node per line.
+  fn list(&self, nodes: &[NodeRef]) -> String {
+    let mut out = String::new();
+    for node_ref in nodes {
+      let _ = writeln!(out, "{}", self.describe(*node_ref));
+    }
+    if nodes.is_empty() {
+      out.push_str("Nothing found\n");
+    }
+    out
+  }
+
+  fn describe(&self, node_ref: NodeRef) -> String {
+    let location = self
+      .ast
+      .get_location_for_node(node_ref)
+      .map(|l| format!("{}:{}", l.start_line, l.start_pos))
+      .unwrap_or_default();
+    let cdl = self.ast.node_to_cdl(node_ref).unwrap_or_default();
+    let first_line = cdl.lines().next().unwrap_or_default().trim();
+    format!("{:<9} {:<6} {}", location, node_ref.0, first_line)
+  }
+
+  /// The node a reference points to. References in expressions are left unresolved by
+  /// processing, those go to the innermost ident named in the path.
+  fn reference_target(&self, ident: &str, resolved: NodeRef) -> Option<NodeRef> {
+    if resolved.0 >= 0 {
+      // consts resolve to their value
+      return match self.ast.get_parent(resolved).first() {
+        Some(owner)
+          if matches!(
+            self.ast.get_node(*owner).map(|n| n.node_data.clone()),
+            Some(Node::Const(_))
+          ) =>
+        {
+          Some(*owner)
+        }
+        _ => Some(resolved),
+      };
+    }
+    ident
+      .rsplit('.')
+      .find_map(|part| self.idents.get(part))
+      .copied()
+  }
+
+  fn refs(&self, target: NodeRef) -> Result<String> {
+    let mut to = vec![];
+    for (index, node) in self.ast.nodes.borrow().iter().enumerate() {
+      let node_ref = NodeRef::from(index);
+      let points_here = match &node.node_data {
+        Node::Reference(reference) => {
+          self.reference_target(reference.ident.as_str(), reference.resolved_node.get())
+            == Some(target)
+        }
+        Node::Entity(entity) => entity
+          .refs
+          .iter()
+          .any(|r| self.reference_target(r.as_str(), NodeRef(-1)) == Some(target)),
+        _ => false,
+      };
+      if points_here {
+        to.push(node_ref);
+      }
+    }
+
+    let mut from = vec![];
+    self.references_from(target, &mut from);
+    let mut out = String::from("References to it:\n");
+    out.push_str(&self.list(&to));
+    out.push_str("References from it:\n");
+    if from.is_empty() {
+      out.push_str("Nothing found\n");
+    }
+    for (reference, resolved) in from {
+      let target = match resolved {
+        Some(node_ref) => self.describe(node_ref),
+        None => "unresolved".to_string(),
+      };
+      let _ = writeln!(out, "@{} -> {}", reference, target);
+    }
+    Ok(out)
+  }
+
+  /// References in the nodes owned by `node_ref`, inherited children belong to their declaration.
+  fn references_from(&self, node_ref: NodeRef, found: &mut Vec<(String, Option<NodeRef>)>) {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return;
+    };
+    match &node.node_data {
+      Node::Reference(reference) => found.push((
+        reference.ident.to_string(),
+        self.reference_target(reference.ident.as_str(), reference.resolved_node.get()),
+      )),
+      Node::Entity(entity) => {
+        for r in &entity.refs {
+          found.push((
+            r.to_string(),
+            self.reference_target(r.as_str(), NodeRef(-1)),
+          ));
+        }
+      }
+      _ => {}
+    }
+    for child in node.node_data.children() {
+      if self.ast.get_parent(child).first() == Some(&node_ref) {
+        self.references_from(child, found);
+      }
+    }
+  }
+
+  /// Worked out from the shape of the expression, functions other than the aggregates are
+  /// `unknown`.
+  fn type_of(&self, node_ref: NodeRef, depth: usize) -> String {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return "unknown".to_string();
+    };
+    // bounded, in case of reference cycles
+    if depth > 32 {
+      return "unknown".to_string();
+    }
+    let kind = match &node.node_data {
+      Node::Number(_) => "number",
+      Node::String(_) => "string",
+      Node::Boolean(_) => "boolean",
+      Node::Color(_) => "color",
+      Node::Identifier(_) => "identifier",
+      Node::Formula(_) => "formula",
+      Node::Entity(_) => "entity",
+      Node::Script(_) => "script",
+      Node::Title(_) => "title",
+      Node::TableAlias(_) => "table alias",
+      Node::VPath(vpath) if vpath.is_hierarchy => "hierarchy",
+      Node::VPath(vpath) if vpath.function.is_some() => "number",
+      Node::VPath(_) => "variable",
+      Node::Operator(op) => match op.operator {
+        Operator::Plus | Operator::Minus | Operator::Mul | Operator::Div | Operator::Mod => {
+          "number"
+        }
+        _ => "boolean",
+      },
+      Node::UnaryOperator(op) => match op.operator {
+        UnaryOperator::Minus => "number",
+        UnaryOperator::Not => "boolean",
+      },
+      Node::Function(function) => match function.name.0.to_lowercase().as_str() {
+        "avg" | "average" | "count" | "max" | "min" | "sum" | "median" | "stdev" | "score"
+        | "nps" => "number",
+        _ => "unknown",
+      },
+      Node::Const(constant) => return self.type_of(constant.value.get(), depth + 1),
+      Node::Reference(reference) => {
+        return match self.reference_target(reference.ident.as_str(), reference.resolved_node.get())
+        {
+          Some(target) if reference.resolved_node.get().0 >= 0 => self.type_of(target, depth + 1),
+          _ => "unknown".to_string(),
+        };
+      }
+      Node::Property(property) => {
+        let values = property.children.borrow();
+        return match values.as_slice() {
+          [value] => self.type_of(*value, depth + 1),
+          values => format!(
+            "list of {}",
+            values
+              .iter()
+              .map(|v| self.type_of(*v, depth + 1))
+              .collect::<Vec<_>>()
+              .join(", ")
+          ),
+        };
+      }
+    };
+    kind.to_string()
+  }
+
+  /// Completions for the word ending at `pos`, and where that word starts.
+  pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let start = line[..pos]
+      .rfind(char::is_whitespace)
+      .map(|i| i + 1)
+      .unwrap_or(0);
+    let word = &line[start..pos];
+    let candidates: Vec<String> = if start == 0 {
+      COMMANDS.iter().map(|c| c.to_string()).collect()
+    } else if line.starts_with("select") {
+      self.properties.iter().cloned().collect()
+    } else if let Some((ident, _)) = word.trim_start_matches('#').split_once('.') {
+      let prefix = &word[..word.find('.').unwrap_or(0) + 1];
+      match self.idents.get(ident) {
+        Some(node_ref) => self
+          .children(*node_ref)
+          .into_iter()
+          .filter_map(|child| match &self.ast.get_node(child)?.node_data {
+            Node::Property(property) => Some(format!("{}{}", prefix, property.name)),
+            _ => None,
+          })
+          .collect(),
+        None => vec![],
+      }
+    } else if word.starts_with('#') {
+      self.idents.keys().map(|i| format!("#{}", i)).collect()
+    } else {
+      self.idents.keys().cloned().collect()
+    };
+    let mut candidates = candidates
+      .into_iter()
+      .filter(|c| c.starts_with(word))
+      .collect::<Vec<_>>();
+    candidates.dedup();
+    (start, candidates)
+  }
+}
+
+struct ShellHelper<'a> {
+  session: &'a Session,
+}
+
+impl Completer for ShellHelper<'_> {
+  type Candidate = Pair;
+
+  fn complete(
+    &self,
+    line: &str,
+    pos: usize,
+    _ctx: &LineContext<'_>,
+  ) -> rustyline::Result<(usize, Vec<Pair>)> {
+    let (start, candidates) = self.session.complete(line, pos);
+    let pairs = candidates
+      .into_iter()
+      .map(|c| Pair {
+        display: c.clone(),
+        replacement: c,
+      })
+      .collect();
+    Ok((start, pairs))
+  }
+}
+
+impl Hinter for ShellHelper<'_> {
+  type Hint = String;
+}
+
+impl Highlighter for ShellHelper<'_> {
+  fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+    &'s self,
+    prompt: &'p str,
+    _default: bool,
+  ) -> Cow<'b, str> {
+    Cow::Borrowed(prompt)
+  }
+}
+
+impl Validator for ShellHelper<'_> {}
+
+impl Helper for ShellHelper<'_> {}
+
+/// Loads `file` once and reads commands until `quit` or end of input.
+pub fn run_repl(file: &str) -> Result<bool> {
+  let text = fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+  let session = Session::load(&text)?;
+  println!(
+    "Loaded {}, {} nodes, {} processing errors. Type help for commands.",
+    file,
+    session.node_count(),
+    session.errors.len()
+  );
+  let mut editor = Editor::<ShellHelper, DefaultHistory>::new()?;
+  editor.set_helper(Some(ShellHelper { session: &session }));
+  loop {
+    let line = match editor.readline("cdl> ") {
+      Ok(line) => line,
+      Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+      Err(error) => return Err(error.into()),
+    };
+    editor.add_history_entry(line.as_str())?;
+    if matches!(line.trim(), "quit" | "exit") {
+      break;
+    }
+    match session.run(&line) {
+      Ok(out) => print!("{}", out),
+      Err(error) => eprintln!("{:#}", error),
+    }
+  }
+  Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SCRIPT: &str = r#"title "Sales"
+page #sales {
+  label: "Sales"
+  widget kpi #total {
+    value: max(accounts:AccountID) + @targets.target
+    enabled: NOT @flag
+  }
+  widget kpi @total {
+    label: "Copy"
+  }
+}
+custom properties #targets {
+  target: @goal
+}
+const goal = 5
+const flag = true
+"#;
+
+  fn session() -> Session {
+    Session::load(SCRIPT).unwrap()
+  }
+
+  #[test]
+  fn shows_and_locates_nodes() {
+    let session = session();
+    assert_eq!(
+      "widget kpi #total {\n  value: max(accounts:AccountID) + @targets.target\n  enabled: NOT true\n}\n",
+      session.run("show #total").unwrap()
+    );
+    assert_eq!("5:5-6:1\n", session.run("loc #total.value").unwrap());
+    assert_eq!(
+      "3:3       3      label: \"Sales\"\n9:5       15     label: \"Copy\"\n",
+      session.run("select label").unwrap()
+    );
+    assert_eq!(
+      "No entity or const named missing",
+      session.run("show #missing").unwrap_err().to_string()
+    );
+    assert_eq!(
+      "#total has no property label",
+      session.run("loc #total.label").unwrap_err().to_string()
+    );
+  }
+
+  #[test]
+  fn lists_references_and_types() {
+    let session = session();
+    assert_eq!(
+      "References to it:\n8:3       14     widget kpi @total {\nReferences from it:\n@targets.target -> 12:1      17     custom properties #targets {\n@flag -> 16:1      22     const flag = true\n",
+      session.run("refs #total").unwrap()
+    );
+    assert_eq!(
+      "References to it:\n13:11     19     5\nReferences from it:\nNothing found\n",
+      session.run("refs goal").unwrap()
+    );
+    assert_eq!("number\n", session.run("type #total.value").unwrap());
+    assert_eq!("boolean\n", session.run("type #total.enabled").unwrap());
+    assert_eq!("number\n", session.run("type #targets.target").unwrap());
+  }
+
+  #[test]
+  fn completes_commands_idents_and_properties() {
+    let session = session();
+    assert_eq!((0, vec!["select".to_string()]), session.complete("sel", 3));
+    assert_eq!(
+      (5, vec!["#targets".to_string(), "#total".to_string()]),
+      session.complete("show #t", 7)
+    );
+    assert_eq!(
+      (5, vec!["#total.value".to_string()]),
+      session.complete("type #total.v", 13)
+    );
+    assert_eq!(
+      (7, vec!["label".to_string()]),
+      session.complete("select la", 9)
+    );
+  }
+}
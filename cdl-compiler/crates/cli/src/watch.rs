@@ -0,0 +1,426 @@
+use std::{
+  collections::{hash_map::DefaultHasher, BTreeMap},
+  fs,
+  hash::{Hash, Hasher},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  thread,
+  time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use lexer::LexError;
+use node_processing::PassManager;
+use parser::{parse_text, ParseError};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDiagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub line: Option<usize>,
+  pub column: Option<usize>,
+}
+
+/// One line of the `--json` stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+  /// A new or changed file was checked, the diagnostics replace the ones from before.
+  Checked {
+    file: PathBuf,
+    diagnostics: Vec<FileDiagnostic>,
+    duration_ms: u64,
+  },
+  Removed {
+    file: PathBuf,
+  },
+}
+
+/// Modification time and size, cheap to read for every file on every poll.
+type Stamp = (SystemTime, u64);
+
+struct FileState {
+  stamp: Stamp,
+  hash: u64,
+}
+
+/// Polls a directory of `.cdl` files and checks the ones that change. A file is checked once it
+/// has been left alone for the debounce, so an editor writing it in several steps only gives one
+/// check. Only a hash of each file is kept, not its parsed result: files saved without changing
+/// their content are skipped, changed files are parsed and checked from scratch.
+pub struct Watcher {
+  dir: PathBuf,
+  debounce: Duration,
+  files: BTreeMap<PathBuf, FileState>,
+  /// Changed or removed files waiting for the debounce, with their last stamp and when it was
+  /// first seen.
+  pending: BTreeMap<PathBuf, (Option<Stamp>, Instant)>,
+}
+
+impl Watcher {
+  pub fn new(dir: &Path, debounce: Duration) -> Watcher {
+    Watcher {
+      dir: dir.to_path_buf(),
+      debounce,
+      files: BTreeMap::new(),
+      pending: BTreeMap::new(),
+    }
+  }
+
+  /// Checks every file, without waiting for the debounce.
+  pub fn check_all(&mut self) -> Result<Vec<Event>> {
+    let mut events = vec![];
+    for (path, stamp) in self.scan()? {
+      if let Some(event) = self.check(&path, stamp)? {
+        events.push(event);
+      }
+    }
+    Ok(events)
+  }
+
+  /// Looks for changes, and checks the files that have been quiet for the debounce at `now`.
+  pub fn poll(&mut self, now: Instant) -> Result<Vec<Event>> {
+    let on_disk = self.scan()?;
+    let removed = self
+      .files
+      .keys()
+      .filter(|path| !on_disk.contains_key(*path))
+      .cloned()
+      .collect::<Vec<_>>();
+    let changes = on_disk
+      .into_iter()
+      .filter(|(path, stamp)| self.files.get(path).map(|f| f.stamp) != Some(*stamp))
+      .map(|(path, stamp)| (path, Some(stamp)))
+      .chain(removed.into_iter().map(|path| (path, None)));
+    for (path, stamp) in changes {
+      match self.pending.get(&path) {
+        // the debounce restarts on every write
+        Some((pending, _)) if *pending == stamp => {}
+        _ => {
+          self.pending.insert(path, (stamp, now));
+        }
+      }
+    }
+
+    let ready = self
+      .pending
+      .iter()
+      .filter(|(_, (_, since))| now.saturating_duration_since(*since) >= self.debounce)
+      .map(|(path, (stamp, _))| (path.clone(), *stamp))
+      .collect::<Vec<_>>();
+    let mut events = vec![];
+    for (path, stamp) in ready {
+      self.pending.remove(&path);
+      match stamp {
+        Some(stamp) => events.extend(self.check(&path, stamp)?),
+        None => {
+          if self.files.remove(&path).is_some() {
+            events.push(Event::Removed { file: path });
+          }
+        }
+      }
+    }
+    Ok(events)
+  }
+
+  fn scan(&self) -> Result<BTreeMap<PathBuf, Stamp>> {
+    let mut files = BTreeMap::new();
+    scan_dir(&self.dir, &mut files)
+      .with_context(|| format!("Could not read {}", self.dir.display()))?;
+    Ok(files)
+  }
+
+  fn check(&mut self, path: &Path, stamp: Stamp) -> Result<Option<Event>> {
+    let text = match fs::read_to_string(path) {
+      Ok(text) => text,
+      // removed between the scan and now, the next poll sees it
+      Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(error) => {
+        return Err(error).with_context(|| format!("Could not read {}", path.display()))
+      }
+    };
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    let hash = hasher.finish();
+    if let Some(file) = self.files.get_mut(path) {
+      if file.hash == hash {
+        file.stamp = stamp;
+        return Ok(None);
+      }
+    }
+
+    let start = Instant::now();
+    let diagnostics = check_text(&text);
+    self
+      .files
+      .insert(path.to_path_buf(), FileState { stamp, hash });
+    Ok(Some(Event::Checked {
+      file: path.to_path_buf(),
+      diagnostics,
+      duration_ms: start.elapsed().as_millis() as u64,
+    }))
+  }
+}
+
+/// Adds the `.cdl` files under `dir`. Files and directories removed while scanning are left
+/// out, the next scan reports them as removed.
+fn scan_dir(dir: &Path, files: &mut BTreeMap<PathBuf, Stamp>) -> io::Result<()> {
+  let paths = fs::read_dir(dir)?
+    .map(|entry| entry.map(|entry| entry.path()))
+    .collect::<io::Result<Vec<_>>>()?;
+  scan_paths(&paths, files)
+}
+
+fn scan_paths(paths: &[PathBuf], files: &mut BTreeMap<PathBuf, Stamp>) -> io::Result<()> {
+  for path in paths {
+    let Some(metadata) = skip_not_found(fs::symlink_metadata(path))? else {
+      continue;
+    };
+    if metadata.is_dir() {
+      skip_not_found(scan_dir(path, files))?;
+    } else if path.extension().is_some_and(|e| e == "cdl") {
+      files.insert(path.clone(), (metadata.modified()?, metadata.len()));
+    }
+  }
+  Ok(())
+}
+
+fn skip_not_found<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+  match result {
+    Ok(value) => Ok(Some(value)),
+    Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(error) => Err(error),
+  }
+}
+
+fn check_text(text: &str) -> Vec<FileDiagnostic> {
+  let ast = match parse_text(text) {
+    Ok(ast) => ast,
+    Err(error) => {
+      let location = if let Some(lex_error) = error.downcast_ref::<LexError>() {
+        Some(lex_error.location.clone())
+      } else {
+        error
+          .downcast_ref::<ParseError>()
+          .map(|parse_error| parse_error.location.clone())
+      };
+      let message = match error.downcast_ref::<LexError>() {
+        Some(lex_error) => lex_error.message.clone(),
+        None => format!("{:#}", error.root_cause()),
+      };
+      let diagnostic = FileDiagnostic {
+        severity: Severity::Error,
+        message,
+        line: location.as_ref().map(|l| l.start_line),
+        column: location.as_ref().map(|l| l.start_pos),
+      };
+      return vec![diagnostic];
+    }
+  };
+  let (ast, report) = PassManager::new().run(ast);
+  report
+    .diagnostics()
+    .map(|diagnostic| {
+      let location = diagnostic
+        .node_ref
+        .and_then(|n| ast.get_location_for_node(n));
+      FileDiagnostic {
+        severity: match diagnostic.severity {
+          node_processing::Severity::Error => Severity::Error,
+          node_processing::Severity::Warning => Severity::Warning,
+        },
+        message: diagnostic.message.clone(),
+        line: location.as_ref().map(|l| l.start_line),
+        column: location.as_ref().map(|l| l.start_pos),
+      }
+    })
+    .collect()
+}
+
+fn print_event(out: &mut impl Write, event: &Event, json: bool) -> Result<()> {
+  if json {
+    writeln!(out, "{}", serde_json::to_string(event)?)?;
+  } else {
+    match event {
+      Event::Checked {
+        file, diagnostics, ..
+      } if diagnostics.is_empty() => writeln!(out, "{}: ok", file.display())?,
+      Event::Checked {
+        file, diagnostics, ..
+      } => {
+        for diagnostic in diagnostics {
+          let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+          };
+          writeln!(
+            out,
+            "{}:{}:{}: {}: {}",
+            file.display(),
+            diagnostic.line.unwrap_or(0),
+            diagnostic.column.unwrap_or(0),
+            severity,
+            diagnostic.message
+          )?;
+        }
+      }
+      Event::Removed { file } => writeln!(out, "{}: removed", file.display())?,
+    }
+  }
+  out.flush()?;
+  Ok(())
+}
+
+/// Checks `dir` and keeps checking changed files until interrupted.
+pub fn run_watch(dir: &str, json: bool, debounce: Duration) -> Result<bool> {
+  let mut watcher = Watcher::new(Path::new(dir), debounce);
+  let mut out = io::stdout().lock();
+  for event in watcher.check_all()? {
+    print_event(&mut out, &event, json)?;
+  }
+  let interval = debounce.min(Duration::from_millis(100)) / 2;
+  loop {
+    thread::sleep(interval);
+    for event in watcher.poll(Instant::now())? {
+      print_event(&mut out, &event, json)?;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+
+  const DEBOUNCE: Duration = Duration::from_millis(200);
+
+  fn checked(events: &[Event]) -> Vec<(String, usize)> {
+    events
+      .iter()
+      .filter_map(|event| match event {
+        Event::Checked {
+          file, diagnostics, ..
+        } => Some((
+          file.file_name()?.to_string_lossy().to_string(),
+          diagnostics.len(),
+        )),
+        Event::Removed { .. } => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn checks_every_file_at_start() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("ok.cdl"), "page {\n  label: \"Ok\"\n}\n").unwrap();
+    fs::create_dir(dir.path().join("nested")).unwrap();
+    fs::write(dir.path().join("nested/broken.cdl"), "page {\n  label: \n").unwrap();
+    fs::write(dir.path().join("notes.txt"), "not cdl").unwrap();
+    let mut watcher = Watcher::new(dir.path(), DEBOUNCE);
+    let events = watcher.check_all().unwrap();
+    assert_eq!(
+      vec![("broken.cdl".to_string(), 1), ("ok.cdl".to_string(), 0)],
+      checked(&events)
+    );
+  }
+
+  #[test]
+  fn rechecks_changed_files_after_the_debounce() {
+    let dir = tempdir().unwrap();
+    let sales = dir.path().join("sales.cdl");
+    fs::write(&sales, "page #sales {\n}\n").unwrap();
+    fs::write(dir.path().join("other.cdl"), "page #other {\n}\n").unwrap();
+    let mut watcher = Watcher::new(dir.path(), DEBOUNCE);
+    watcher.check_all().unwrap();
+    let start = Instant::now();
+    assert!(watcher.poll(start).unwrap().is_empty());
+
+    fs::write(&sales, "page #sales {\n  value: @missing\n}\n").unwrap();
+    assert!(watcher.poll(start).unwrap().is_empty());
+    // a second write restarts the debounce
+    fs::write(&sales, "page #sales {\n  value: @missing.value\n}\n").unwrap();
+    assert!(watcher.poll(start + DEBOUNCE / 2).unwrap().is_empty());
+    assert!(watcher.poll(start + DEBOUNCE).unwrap().is_empty());
+    let events = watcher.poll(start + DEBOUNCE * 2).unwrap();
+    assert_eq!(vec![("sales.cdl".to_string(), 1)], checked(&events));
+    assert!(watcher.poll(start + DEBOUNCE * 3).unwrap().is_empty());
+  }
+
+  #[test]
+  fn reports_removed_files_and_skips_unchanged_content() {
+    let dir = tempdir().unwrap();
+    let sales = dir.path().join("sales.cdl");
+    fs::write(&sales, "page {\n}\n").unwrap();
+    let mut watcher = Watcher::new(dir.path(), Duration::ZERO);
+    watcher.check_all().unwrap();
+
+    // same content, whatever happens to the modification time
+    fs::write(&sales, "page {\n}\n").unwrap();
+    assert!(watcher.poll(Instant::now()).unwrap().is_empty());
+
+    fs::remove_file(&sales).unwrap();
+    let events = watcher.poll(Instant::now()).unwrap();
+    assert_eq!(vec![Event::Removed { file: sales }], events);
+    assert_eq!(
+      r#"{"event":"removed","file":"a.cdl"}"#,
+      serde_json::to_string(&Event::Removed {
+        file: "a.cdl".into()
+      })
+      .unwrap()
+    );
+  }
+
+  #[test]
+  fn files_removed_during_a_scan_are_skipped() {
+    let dir = tempdir().unwrap();
+    let kept = dir.path().join("kept.cdl");
+    fs::write(&kept, "page {\n}\n").unwrap();
+    fs::write(dir.path().join("gone.cdl"), "page {\n}\n").unwrap();
+    fs::create_dir(dir.path().join("nested")).unwrap();
+    fs::write(dir.path().join("nested/gone.cdl"), "page {\n}\n").unwrap();
+    let paths = fs::read_dir(dir.path())
+      .unwrap()
+      .map(|entry| entry.unwrap().path())
+      .collect::<Vec<_>>();
+    // removed after the directory was listed
+    fs::remove_file(dir.path().join("gone.cdl")).unwrap();
+    fs::remove_dir_all(dir.path().join("nested")).unwrap();
+    let mut files = BTreeMap::new();
+    scan_paths(&paths, &mut files).unwrap();
+    assert_eq!(vec![kept], files.into_keys().collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn prints_machine_readable_lines() {
+    let event = Event::Checked {
+      file: "a.cdl".into(),
+      diagnostics: vec![FileDiagnostic {
+        severity: Severity::Error,
+        message: "Unknown token \"&\"".to_string(),
+        line: Some(2),
+        column: Some(3),
+      }],
+      duration_ms: 1,
+    };
+    let mut json = vec![];
+    print_event(&mut json, &event, true).unwrap();
+    assert_eq!(
+      "{\"event\":\"checked\",\"file\":\"a.cdl\",\"diagnostics\":[{\"severity\":\"error\",\"message\":\"Unknown token \\\"&\\\"\",\"line\":2,\"column\":3}],\"duration_ms\":1}\n",
+      String::from_utf8(json).unwrap()
+    );
+    let mut text = vec![];
+    print_event(&mut text, &event, false).unwrap();
+    assert_eq!(
+      "a.cdl:2:3: error: Unknown token \"&\"\n",
+      String::from_utf8(text).unwrap()
+    );
+  }
+}
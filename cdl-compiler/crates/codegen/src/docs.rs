@@ -0,0 +1,721 @@
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt::Write,
+};
+
+use anyhow::Result;
+use ast::{Ast, Node, NodeRef};
+use lexer::unescape_string;
+use node_processing::{hub_configs, NodeProcessor};
+use parser::parse_text;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DocsFormat {
+  /// A single page with inline styles.
+  #[default]
+  Html,
+  Markdown,
+}
+
+/// Documentation of a processed ast for people reading the dashboard rather than the script.
+/// Lists pages with their widgets and filters, the formulas they use with links to what their
+/// references point to, definitions outside the pages and which places use which tables.
+pub fn docs(ast: &Ast, format: DocsFormat) -> Result<String> {
+  let site = Collector::new(ast).collect()?;
+  Ok(match format {
+    DocsFormat::Html => site.html(),
+    DocsFormat::Markdown => site.markdown(),
+  })
+}
+
+/// Parses, processes and documents `text`.
+pub fn docs_text(text: &str, format: DocsFormat) -> Result<String> {
+  let ast = NodeProcessor::new(parse_text(text)?).process()?;
+  docs(&ast, format)
+}
+
+#[derive(Debug, Default)]
+struct Site {
+  title: String,
+  pages: Vec<Page>,
+  definitions: Vec<Entry>,
+  /// Table, with the table an alias stands for, and the entries using it.
+  tables: BTreeMap<String, (Option<String>, Vec<Link>)>,
+}
+
+#[derive(Debug)]
+struct Page {
+  entry: Entry,
+  widgets: Vec<Entry>,
+  filters: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+  anchor: String,
+  kind: String,
+  label: Option<String>,
+  extends: Vec<Link>,
+  formulas: Vec<Formula>,
+}
+
+impl Entry {
+  fn title(&self) -> String {
+    match &self.label {
+      Some(label) => format!("{} ({})", label, self.kind),
+      None => self.kind.clone(),
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Formula {
+  /// Property name, prefixed by the entities between it and the documented entry.
+  name: String,
+  cdl: String,
+  references: Vec<Link>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Link {
+  text: String,
+  /// `None` when the target is not part of the documentation.
+  anchor: Option<String>,
+}
+
+struct Collector<'a> {
+  ast: &'a Ast,
+  anchors: HashMap<NodeRef, String>,
+  used_anchors: HashSet<String>,
+  idents: HashMap<String, NodeRef>,
+  tables: BTreeMap<String, (Option<String>, Vec<Link>)>,
+}
+
+impl<'a> Collector<'a> {
+  fn new(ast: &'a Ast) -> Self {
+    Collector {
+      ast,
+      anchors: HashMap::new(),
+      used_anchors: HashSet::new(),
+      idents: HashMap::new(),
+      tables: BTreeMap::new(),
+    }
+  }
+
+  fn collect(mut self) -> Result<Site> {
+    let mut titles = vec![];
+    let mut pages = vec![];
+    let mut definitions = vec![];
+    for child_ref in self.owned_children(self.ast.script_entity) {
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      match &child.node_data {
+        Node::Title(title) => titles.push(unquote(title.title.as_str())),
+        Node::Entity(entity) => match entity.terms.first().map(|t| t.as_str()) {
+          Some("page") => pages.push(child_ref),
+          _ if entity.ident.is_some() => definitions.push(child_ref),
+          _ => {}
+        },
+        _ => {}
+      }
+    }
+
+    // every documented entity needs its anchor before links to it can be made
+    for page in &pages {
+      self.add_anchor(*page);
+      for nested in self.nested(*page) {
+        self.add_anchor(nested);
+      }
+    }
+    for definition in &definitions {
+      self.add_anchor(*definition);
+    }
+
+    // hub tables are listed even when nothing uses them
+    for hub in hub_configs(self.ast).unwrap_or_default() {
+      for table in hub.tables {
+        self.tables.entry(table.alias).or_default().0 = Some(table.table);
+      }
+    }
+
+    let pages = pages
+      .into_iter()
+      .map(|page| {
+        let (widgets, filters): (Vec<_>, Vec<_>) = self
+          .nested(page)
+          .into_iter()
+          .partition(|nested| self.first_term(*nested) == Some("widget"));
+        Ok(Page {
+          entry: self.entry(page)?,
+          widgets: widgets
+            .into_iter()
+            .map(|w| self.entry(w))
+            .collect::<Result<_>>()?,
+          filters: filters
+            .into_iter()
+            .map(|f| self.entry(f))
+            .collect::<Result<_>>()?,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let definitions = definitions
+      .into_iter()
+      .map(|d| self.entry(d))
+      .collect::<Result<Vec<_>>>()?;
+    Ok(Site {
+      title: titles
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| "Dashboard".to_string()),
+      pages,
+      definitions,
+      tables: self.tables,
+    })
+  }
+
+  fn owned_children(&self, node_ref: NodeRef) -> Vec<NodeRef> {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return vec![];
+    };
+    node
+      .node_data
+      .children()
+      .into_iter()
+      .filter(|child| self.ast.get_parent(*child).first() == Some(&node_ref))
+      .collect()
+  }
+
+  fn first_term(&self, node_ref: NodeRef) -> Option<&'static str> {
+    let node = self.ast.get_node(node_ref)?;
+    match &node.node_data {
+      Node::Entity(entity) => match entity.terms.first().map(|t| t.as_str()) {
+        Some("widget") => Some("widget"),
+        Some("filter") => Some("filter"),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// Widgets and filters below `node_ref`, in source order. Widgets inside widgets are listed as
+  /// widgets of their own.
+  fn nested(&self, node_ref: NodeRef) -> Vec<NodeRef> {
+    let mut nested = vec![];
+    for child in self.owned_children(node_ref) {
+      if self.first_term(child).is_some() {
+        nested.push(child);
+      }
+      nested.extend(self.nested(child));
+    }
+    nested
+  }
+
+  fn add_anchor(&mut self, node_ref: NodeRef) {
+    let Some(Node::Entity(entity)) = self.ast.get_node(node_ref).map(|n| n.node_data.clone())
+    else {
+      return;
+    };
+    let base = match &entity.ident {
+      Some(ident) => {
+        self.idents.entry(ident.to_string()).or_insert(node_ref);
+        ident.to_string()
+      }
+      None => entity
+        .terms
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join("-"),
+    };
+    // idents only have to be unique when referenced
+    let mut anchor = base.clone();
+    let mut index = 2;
+    while self.used_anchors.contains(&anchor) {
+      anchor = format!("{}-{}", base, index);
+      index += 1;
+    }
+    self.used_anchors.insert(anchor.clone());
+    self.anchors.insert(node_ref, anchor);
+  }
+
+  /// Anchor of the closest documented entity holding `node_ref`.
+  fn anchor_of(&self, mut node_ref: NodeRef) -> Option<String> {
+    while node_ref.0 >= 0 {
+      if let Some(anchor) = self.anchors.get(&node_ref) {
+        return Some(anchor.clone());
+      }
+      node_ref = *self.ast.get_parent(node_ref).first()?;
+    }
+    None
+  }
+
+  fn entry(&mut self, node_ref: NodeRef) -> Result<Entry> {
+    let Some(Node::Entity(entity)) = self.ast.get_node(node_ref).map(|n| n.node_data.clone())
+    else {
+      anyhow::bail!("Expected an entity");
+    };
+    let anchor = self.anchors[&node_ref].clone();
+    let mut formulas = vec![];
+    self.formulas(node_ref, "", &mut formulas)?;
+    let label = entity
+      .label
+      .as_ref()
+      .map(|l| unquote(l.as_str()))
+      .or_else(|| self.text_property(node_ref, "label"))
+      .or_else(|| self.text_property(node_ref, "title"));
+    let extends = entity
+      .refs
+      .iter()
+      .map(|r| Link {
+        text: format!("@{}", r),
+        anchor: self
+          .idents
+          .get(r.as_str())
+          .and_then(|target| self.anchors.get(target).cloned()),
+      })
+      .collect();
+    let entry = Entry {
+      anchor,
+      kind: entity
+        .terms
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(" "),
+      label,
+      extends,
+      formulas,
+    };
+    let used_in = Link {
+      text: entry.title(),
+      anchor: Some(entry.anchor.clone()),
+    };
+    let mut tables = vec![];
+    self.tables_in(node_ref, &mut tables);
+    for table in tables {
+      let users = &mut self.tables.entry(table).or_default().1;
+      if !users.contains(&used_in) {
+        users.push(used_in.clone());
+      }
+    }
+    Ok(entry)
+  }
+
+  /// Properties with expressions or references, in `node_ref` and the entities below it that are
+  /// not documented on their own.
+  fn formulas(&self, node_ref: NodeRef, prefix: &str, formulas: &mut Vec<Formula>) -> Result<()> {
+    for child_ref in self.owned_children(node_ref) {
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      match &child.node_data {
+        Node::Property(property) => {
+          let values = property.children.borrow().clone();
+          if !values.iter().any(|v| self.is_formula(*v)) {
+            continue;
+          }
+          let cdl = values
+            .iter()
+            .map(|v| Ok(self.ast.node_to_cdl(*v)?.trim().to_string()))
+            .collect::<Result<Vec<_>>>()?
+            .join(", ");
+          let mut references = vec![];
+          for value in values {
+            self.references(value, &mut references);
+          }
+          formulas.push(Formula {
+            name: format!("{}{}", prefix, property.name),
+            cdl,
+            references,
+          });
+        }
+        Node::Entity(entity) if !self.anchors.contains_key(&child_ref) => {
+          let terms = entity
+            .terms
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+          self.formulas(child_ref, &format!("{}{} / ", prefix, terms), formulas)?;
+        }
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  fn is_formula(&self, node_ref: NodeRef) -> bool {
+    matches!(
+      self.ast.get_node(node_ref).map(|n| n.node_data.clone()),
+      Some(
+        Node::Function(_)
+          | Node::Operator(_)
+          | Node::UnaryOperator(_)
+          | Node::VPath(_)
+          | Node::Reference(_)
+          | Node::Formula(_)
+      )
+    )
+  }
+
+  fn references(&self, node_ref: NodeRef, links: &mut Vec<Link>) {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return;
+    };
+    if let Node::Reference(reference) = &node.node_data {
+      // references in expressions are left unresolved by processing, those go to the innermost
+      // documented entity named in the path
+      let target = reference.resolved_node.get();
+      let anchor = match target.0 >= 0 {
+        true => self.anchor_of(target),
+        false => reference
+          .ident
+          .as_str()
+          .rsplit('.')
+          .find_map(|part| self.idents.get(part))
+          .and_then(|target| self.anchors.get(target).cloned()),
+      };
+      let link = Link {
+        text: format!("@{}", reference.ident),
+        anchor,
+      };
+      if !links.contains(&link) {
+        links.push(link);
+      }
+    }
+    for child in node.node_data.children() {
+      self.references(child, links);
+    }
+  }
+
+  /// Tables of the vpaths in the properties of `node_ref` and its undocumented entities.
+  fn tables_in(&self, node_ref: NodeRef, tables: &mut Vec<String>) {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return;
+    };
+    if let Node::VPath(vpath) = &node.node_data {
+      if let Some(table) = &vpath.table {
+        if !tables.contains(&table.to_string()) {
+          tables.push(table.to_string());
+        }
+      }
+      return;
+    }
+    for child in self.owned_children(node_ref) {
+      if !self.anchors.contains_key(&child) {
+        self.tables_in(child, tables);
+      }
+    }
+  }
+
+  fn text_property(&self, node_ref: NodeRef, name: &str) -> Option<String> {
+    self.owned_children(node_ref).into_iter().find_map(|child| {
+      let node = self.ast.get_node(child)?;
+      let Node::Property(property) = &node.node_data else {
+        return None;
+      };
+      if property.name.as_str() != name {
+        return None;
+      }
+      let value = self.ast.get_node(*property.children.borrow().first()?)?;
+      match &value.node_data {
+        Node::String(string) => Some(unquote(string.text.as_str())),
+        _ => None,
+      }
+    })
+  }
+}
+
+fn unquote(raw: &str) -> String {
+  unescape_string(raw).unwrap_or_else(|_| raw.to_string())
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:60em;margin:2em auto;padding:0 1em}\
+code{background:#f4f4f4;padding:0 .2em}\
+section.entry{border-left:3px solid #ddd;padding-left:1em;margin:1em 0}\
+dt{font-weight:bold}";
+
+impl Site {
+  fn html(&self) -> String {
+    let mut html = String::new();
+    let _ = writeln!(
+      html,
+      "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>",
+      escape(&self.title),
+      STYLE
+    );
+    let _ = writeln!(html, "<h1>{}</h1>", escape(&self.title));
+    let _ = writeln!(html, "<h2>Pages</h2>\n<ul>");
+    for page in &self.pages {
+      let _ = writeln!(html, "<li>{}</li>", link_html(&entry_link(&page.entry)));
+    }
+    let _ = writeln!(html, "</ul>");
+    for page in &self.pages {
+      let _ = writeln!(html, "<section class=\"page\">");
+      entry_html(&mut html, &page.entry, "h2");
+      if !page.filters.is_empty() {
+        let _ = writeln!(html, "<h3>Filters</h3>");
+        for filter in &page.filters {
+          entry_html(&mut html, filter, "h4");
+        }
+      }
+      if !page.widgets.is_empty() {
+        let _ = writeln!(html, "<h3>Widgets</h3>");
+        for widget in &page.widgets {
+          entry_html(&mut html, widget, "h4");
+        }
+      }
+      let _ = writeln!(html, "</section>");
+    }
+    if !self.definitions.is_empty() {
+      let _ = writeln!(html, "<h2>Definitions</h2>");
+      for definition in &self.definitions {
+        entry_html(&mut html, definition, "h3");
+      }
+    }
+    if !self.tables.is_empty() {
+      let _ = writeln!(html, "<h2>Tables</h2>\n<dl>");
+      for (table, (source, users)) in &self.tables {
+        let _ = match source {
+          Some(source) => writeln!(
+            html,
+            "<dt><code>{}</code> = <code>{}</code></dt>",
+            escape(table),
+            escape(source)
+          ),
+          None => writeln!(html, "<dt><code>{}</code></dt>", escape(table)),
+        };
+        let users = users.iter().map(link_html).collect::<Vec<_>>();
+        let _ = match users.is_empty() {
+          true => writeln!(html, "<dd>Not used</dd>"),
+          false => writeln!(html, "<dd>{}</dd>", users.join(", ")),
+        };
+      }
+      let _ = writeln!(html, "</dl>");
+    }
+    let _ = writeln!(html, "</body>\n</html>");
+    html
+  }
+
+  fn markdown(&self) -> String {
+    let mut md = String::new();
+    let _ = writeln!(md, "# {}\n\n## Pages\n", self.title);
+    for page in &self.pages {
+      let _ = writeln!(md, "- {}", link_markdown(&entry_link(&page.entry)));
+    }
+    for page in &self.pages {
+      entry_markdown(&mut md, &page.entry, "##");
+      if !page.filters.is_empty() {
+        let _ = writeln!(md, "\n### Filters");
+        for filter in &page.filters {
+          entry_markdown(&mut md, filter, "####");
+        }
+      }
+      if !page.widgets.is_empty() {
+        let _ = writeln!(md, "\n### Widgets");
+        for widget in &page.widgets {
+          entry_markdown(&mut md, widget, "####");
+        }
+      }
+    }
+    if !self.definitions.is_empty() {
+      let _ = writeln!(md, "\n## Definitions");
+      for definition in &self.definitions {
+        entry_markdown(&mut md, definition, "###");
+      }
+    }
+    if !self.tables.is_empty() {
+      let _ = writeln!(md, "\n## Tables\n");
+      for (table, (source, users)) in &self.tables {
+        let name = match source {
+          Some(source) => format!("`{}` = `{}`", table, source),
+          None => format!("`{}`", table),
+        };
+        let users = users.iter().map(link_markdown).collect::<Vec<_>>();
+        let _ = match users.is_empty() {
+          true => writeln!(md, "- {}: not used", name),
+          false => writeln!(md, "- {}: {}", name, users.join(", ")),
+        };
+      }
+    }
+    md
+  }
+}
+
+fn entry_link(entry: &Entry) -> Link {
+  Link {
+    text: entry.title(),
+    anchor: Some(entry.anchor.clone()),
+  }
+}
+
+fn link_html(link: &Link) -> String {
+  match &link.anchor {
+    Some(anchor) => format!("<a href=\"#{}\">{}</a>", escape(anchor), escape(&link.text)),
+    None => escape(&link.text),
+  }
+}
+
+fn link_markdown(link: &Link) -> String {
+  match &link.anchor {
+    Some(anchor) => format!("[{}](#{})", link.text, anchor),
+    None => link.text.clone(),
+  }
+}
+
+/// The escaped formula with its references turned into links.
+fn formula_html(formula: &Formula) -> String {
+  let cdl = escape(&formula.cdl);
+  let mut html = String::with_capacity(cdl.len());
+  let mut rest = cdl.as_str();
+  while let Some(start) = rest.find('@') {
+    html.push_str(&rest[..start]);
+    let end = rest[start + 1..]
+      .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.')))
+      .map(|end| start + 1 + end)
+      .unwrap_or(rest.len());
+    let text = &rest[start..end];
+    match formula.references.iter().find(|r| r.text == text) {
+      Some(link) => html.push_str(&link_html(link)),
+      None => html.push_str(text),
+    }
+    rest = &rest[end..];
+  }
+  html.push_str(rest);
+  html
+}
+
+fn entry_html(html: &mut String, entry: &Entry, heading: &str) {
+  let _ = writeln!(
+    html,
+    "<section class=\"entry\" id=\"{}\">\n<{heading}>{}</{heading}>",
+    escape(&entry.anchor),
+    escape(&entry.title()),
+  );
+  if !entry.extends.is_empty() {
+    let extends = entry.extends.iter().map(link_html).collect::<Vec<_>>();
+    let _ = writeln!(html, "<p>Extends {}</p>", extends.join(", "));
+  }
+  if !entry.formulas.is_empty() {
+    let _ = writeln!(html, "<dl>");
+    for formula in &entry.formulas {
+      let _ = writeln!(
+        html,
+        "<dt>{}</dt><dd><code>{}</code></dd>",
+        escape(&formula.name),
+        formula_html(formula)
+      );
+    }
+    let _ = writeln!(html, "</dl>");
+  }
+  let _ = writeln!(html, "</section>");
+}
+
+fn entry_markdown(md: &mut String, entry: &Entry, heading: &str) {
+  let _ = writeln!(
+    md,
+    "\n{} <a id=\"{}\"></a>{}",
+    heading,
+    entry.anchor,
+    entry.title()
+  );
+  if !entry.extends.is_empty() {
+    let extends = entry.extends.iter().map(link_markdown).collect::<Vec<_>>();
+    let _ = writeln!(md, "\nExtends {}", extends.join(", "));
+  }
+  if !entry.formulas.is_empty() {
+    let _ = writeln!(md);
+  }
+  for formula in &entry.formulas {
+    let _ = write!(
+      md,
+      "- {}: `{}`",
+      formula.name,
+      formula.cdl.replace('`', "'")
+    );
+    let links = formula
+      .references
+      .iter()
+      .filter(|r| r.anchor.is_some())
+      .map(link_markdown)
+      .collect::<Vec<_>>();
+    if !links.is_empty() {
+      let _ = write!(md, " ({})", links.join(", "));
+    }
+    let _ = writeln!(md);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SCRIPT: &str = r#"title "Sales"
+config hub {
+  hub: 1
+  table accounts = custom.Account_2
+  table unused = custom.Other
+}
+custom properties #cp {
+  target: 50
+  total: sum(accounts:revenue)
+}
+page #sales {
+  label: "Sales & revenue"
+  filter multiselect {
+    label: "Region"
+    options: accounts:region
+  }
+  widget kpi #revenue {
+    label: "Revenue"
+    tile kpi {
+      value: @cp.total / @cp.target
+    }
+  }
+  widget kpi @revenue {
+    label: "Copy"
+  }
+}
+"#;
+
+  #[test]
+  fn documents_pages_widgets_and_tables() {
+    let html = docs_text(SCRIPT, DocsFormat::Html).unwrap();
+    assert!(html.contains("<title>Sales</title>"));
+    assert!(html.contains("<li><a href=\"#sales\">Sales &amp; revenue (page)</a></li>"));
+    assert!(
+      html.contains("<section class=\"entry\" id=\"revenue\">\n<h4>Revenue (widget kpi)</h4>")
+    );
+    assert!(html.contains(
+      "<dt>tile kpi / value</dt><dd><code><a href=\"#cp\">@cp.total</a> / <a href=\"#cp\">@cp.target</a></code></dd>"
+    ));
+    assert!(html.contains("<p>Extends <a href=\"#revenue\">@revenue</a></p>"));
+    assert!(html.contains("<dt>options</dt><dd><code>accounts:region</code></dd>"));
+    assert!(html.contains(
+      "<dt><code>accounts</code> = <code>custom.Account_2</code></dt>\n<dd><a href=\"#filter-multiselect\">Region (filter multiselect)</a>, <a href=\"#cp\">custom properties</a></dd>"
+    ));
+    assert!(
+      html.contains("<dt><code>unused</code> = <code>custom.Other</code></dt>\n<dd>Not used</dd>")
+    );
+  }
+
+  #[test]
+  fn documents_as_markdown() {
+    let md = docs_text(SCRIPT, DocsFormat::Markdown).unwrap();
+    assert!(md.starts_with("# Sales\n\n## Pages\n\n- [Sales & revenue (page)](#sales)\n"));
+    assert!(md.contains("#### <a id=\"revenue\"></a>Revenue (widget kpi)\n"));
+    assert!(md.contains(
+      "- tile kpi / value: `@cp.total / @cp.target` ([@cp.total](#cp), [@cp.target](#cp))\n"
+    ));
+    assert!(md.contains("- `accounts` = `custom.Account_2`: [Region (filter multiselect)](#filter-multiselect), [custom properties](#cp)\n"));
+  }
+}
@@ -0,0 +1,7 @@
+mod docs;
+mod lower;
+mod typescript;
+
+pub use docs::*;
+pub use lower::*;
+pub use typescript::*;
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use ast::{Ast, Node, NodeRef};
+use lexer::unescape_string;
+use node_processing::NodeProcessor;
+use parser::parse_text;
+use serde_json::{json, Map, Value};
+
+/// Max number of references followed when inlining a value, guards against reference cycles.
+const MAX_INLINE_DEPTH: usize = 32;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum References {
+  /// Resolved property references are replaced by the value they point to, and entities include
+  /// the properties and children they get through `@ref`.
+  #[default]
+  Inline,
+  /// References are kept as `{ "$ref": "name" }` and entities only hold what they declare.
+  Link,
+}
+
+/// Lowers a processed ast to a nested document, with entities as objects and properties as keys.
+/// Repeated properties and lists become arrays. Expressions become tagged objects, like
+/// `{ "function": "max", "args": [...] }`.
+pub fn lower(ast: &Ast, references: References) -> Result<Value> {
+  let lowering = Lowering { ast, references };
+  let mut titles = vec![];
+  let mut entities = vec![];
+  if let Some(script) = ast.get_node(ast.script_entity) {
+    for child_ref in script.node_data.children() {
+      let Some(child) = ast.get_node(child_ref) else {
+        continue;
+      };
+      match &child.node_data {
+        Node::Title(title) => titles.push(unquote(title.title.as_str())),
+        Node::Entity(_) => entities.push(lowering.entity(child_ref)?),
+        _ => {}
+      }
+    }
+  }
+  Ok(json!({ "titles": titles, "entities": entities }))
+}
+
+/// Parses, processes and lowers `text`.
+pub fn lower_text(text: &str, references: References) -> Result<Value> {
+  let ast = NodeProcessor::new(parse_text(text)?).process()?;
+  lower(&ast, references)
+}
+
+fn unquote(raw: &str) -> String {
+  unescape_string(raw).unwrap_or_else(|_| raw.to_string())
+}
+
+struct Lowering<'a> {
+  ast: &'a Ast,
+  references: References,
+}
+
+impl Lowering<'_> {
+  fn is_owned_by(&self, node_ref: NodeRef, parent: NodeRef) -> bool {
+    self.ast.get_parent(node_ref).first() == Some(&parent)
+  }
+
+  fn entity(&self, node_ref: NodeRef) -> Result<Value> {
+    let mut object = Map::new();
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return Ok(Value::Object(object));
+    };
+    let Node::Entity(entity) = &node.node_data else {
+      return Ok(Value::Object(object));
+    };
+    let terms = entity
+      .terms
+      .iter()
+      .map(|t| t.to_string())
+      .collect::<Vec<_>>()
+      .join(" ");
+    object.insert("type".into(), terms.into());
+    if let Some(ident) = &entity.ident {
+      object.insert("id".into(), ident.to_string().into());
+    }
+    if let Some(label) = &entity.label {
+      object.insert("label".into(), unquote(label.as_str()).into());
+    }
+    if !entity.refs.is_empty() {
+      let refs = entity
+        .refs
+        .iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>();
+      object.insert("extends".into(), refs.into());
+    }
+    if let Some(number) = entity.entity_number {
+      object.insert("number".into(), number.into());
+    }
+
+    let mut names = vec![];
+    let mut own: HashMap<String, Vec<Value>> = HashMap::new();
+    let mut inherited: HashMap<String, Vec<Value>> = HashMap::new();
+    let mut children = vec![];
+    for child_ref in node.node_data.children() {
+      let owned = self.is_owned_by(child_ref, node_ref);
+      if !owned && self.references == References::Link {
+        continue;
+      }
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      match &child.node_data {
+        Node::Property(property) => {
+          let name = property.name.to_string();
+          if !own.contains_key(&name) && !inherited.contains_key(&name) {
+            names.push(name.clone());
+          }
+          let values = if owned { &mut own } else { &mut inherited };
+          values
+            .entry(name)
+            .or_default()
+            .push(self.property(child_ref)?);
+        }
+        Node::Entity(_) => children.push(self.entity(child_ref)?),
+        _ => {}
+      }
+    }
+    let mut properties = Map::new();
+    for name in names {
+      // inherited properties do not override the entity's own, repeats of either are all kept
+      let mut values = match own.remove(&name) {
+        Some(values) => values,
+        None => inherited.remove(&name).unwrap_or_default(),
+      };
+      let value = match values.len() {
+        1 => values.remove(0),
+        _ => Value::Array(values),
+      };
+      properties.insert(name, value);
+    }
+    object.insert("properties".into(), properties.into());
+    object.insert("children".into(), children.into());
+    Ok(Value::Object(object))
+  }
+
+  fn property(&self, node_ref: NodeRef) -> Result<Value> {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return Ok(Value::Null);
+    };
+    let values = node
+      .node_data
+      .children()
+      .into_iter()
+      .map(|child| self.value(child, 0))
+      .collect::<Result<Vec<_>>>()?;
+    Ok(match values.len() {
+      1 => values.into_iter().next().unwrap(),
+      _ => Value::Array(values),
+    })
+  }
+
+  fn value(&self, node_ref: NodeRef, depth: usize) -> Result<Value> {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return Ok(Value::Null);
+    };
+    Ok(match &node.node_data {
+      Node::String(string) => string.value.to_string().into(),
+      Node::Number(number) => number.value.into(),
+      Node::Boolean(boolean) => boolean.get().into(),
+      Node::Identifier(identifier) => json!({ "identifier": identifier.identifier.to_string() }),
+      Node::Color(color) => json!({ "color": format!("#{}", color.color) }),
+      Node::VPath(_) => json!({ "vpath": self.ast.node_to_cdl(node_ref)?.trim() }),
+      Node::Reference(reference) => {
+        let target = reference.resolved_node.get();
+        let inline = self.references == References::Inline
+          && target.0 >= 0
+          && depth < MAX_INLINE_DEPTH
+          && self
+            .ast
+            .get_node(target)
+            .is_some_and(|t| !matches!(t.node_data, Node::Entity(_)));
+        if inline {
+          self.value(target, depth + 1)?
+        } else {
+          json!({ "$ref": reference.ident.to_string() })
+        }
+      }
+      Node::Function(function) => json!({
+        "function": function.name.to_string(),
+        "args": node
+          .node_data
+          .children()
+          .into_iter()
+          .map(|child| self.value(child, depth))
+          .collect::<Result<Vec<_>>>()?,
+      }),
+      Node::Operator(operator) => json!({
+        "operator": operator.operator.symbol(),
+        "left": self.value(operator.left.get(), depth)?,
+        "right": self.value(operator.right.get(), depth)?,
+      }),
+      Node::UnaryOperator(operator) => json!({
+        "operator": operator.operator.symbol(),
+        "operand": self.value(operator.expr.get(), depth)?,
+      }),
+      _ => json!({ "expression": self.ast.node_to_cdl(node_ref)?.trim() }),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SCRIPT: &str = r#"title "Sales"
+config hub {
+  hub: 1
+}
+page #base {
+  label: "Base"
+  color: #112233
+}
+page "Sales page" @base #sales {
+  label: "Sales"
+  size: 1 + 2
+  link: @sales.label
+  total: max(1, NOT a)
+  series: a, b
+  widget kpi {
+    tag: "x"
+    tag: "y"
+  }
+}
+"#;
+
+  #[test]
+  fn lowers_to_nested_objects() {
+    let model = lower_text(SCRIPT, References::Link).unwrap();
+    assert_eq!(json!(["Sales"]), model["titles"]);
+    let page = &model["entities"][2];
+    assert_eq!("page", page["type"]);
+    assert_eq!("sales", page["id"]);
+    assert_eq!("Sales page", page["label"]);
+    assert_eq!(json!(["base"]), page["extends"]);
+    assert_eq!("Sales", page["properties"]["label"]);
+    assert_eq!(
+      json!({ "operator": "+", "left": 1.0, "right": 2.0 }),
+      page["properties"]["size"]
+    );
+    assert_eq!(
+      json!({
+        "function": "max",
+        "args": [1.0, { "operator": "NOT", "operand": { "identifier": "a" } }]
+      }),
+      page["properties"]["total"]
+    );
+    assert_eq!(
+      json!([{ "identifier": "a" }, { "identifier": "b" }]),
+      page["properties"]["series"]
+    );
+    assert_eq!(json!({ "$ref": "sales.label" }), page["properties"]["link"]);
+    assert!(page["properties"].get("color").is_none());
+    assert_eq!(json!(["x", "y"]), page["children"][0]["properties"]["tag"]);
+  }
+
+  #[test]
+  fn inlines_references() {
+    let model = lower_text(SCRIPT, References::Inline).unwrap();
+    let page = &model["entities"][2];
+    assert_eq!("Sales", page["properties"]["link"]);
+    assert_eq!("Sales", page["properties"]["label"]);
+    assert_eq!(json!({ "color": "#112233" }), page["properties"]["color"]);
+  }
+
+  #[test]
+  fn keeps_repeated_inherited_properties() {
+    let script = r#"config hub {
+  hub: 1
+}
+page #base {
+  label: "A"
+  label: "B"
+  tag: "x"
+  tag: "y"
+}
+page @base {
+  tag: "z"
+}
+"#;
+    let model = lower_text(script, References::Inline).unwrap();
+    let page = &model["entities"][2];
+    assert_eq!(json!(["A", "B"]), page["properties"]["label"]);
+    assert_eq!("z", page["properties"]["tag"]);
+  }
+}
@@ -0,0 +1,323 @@
+use std::{
+  collections::{BTreeMap, BTreeSet, HashSet},
+  fmt::Write,
+};
+
+use serde_json::Value;
+
+const PRELUDE: &str = r#"// Generated from a CDL script, do not edit.
+
+export interface Identifier {
+  identifier: string;
+}
+export interface Color {
+  color: string;
+}
+export interface VPath {
+  vpath: string;
+}
+export interface Reference {
+  $ref: string;
+}
+export interface FunctionCall {
+  function: string;
+  args: Value[];
+}
+export interface Operation {
+  operator: string;
+  left: Value;
+  right: Value;
+}
+export interface UnaryOperation {
+  operator: string;
+  operand: Value;
+}
+export interface Expression {
+  expression: string;
+}
+export type Value =
+  | string
+  | number
+  | boolean
+  | Identifier
+  | Color
+  | VPath
+  | Reference
+  | FunctionCall
+  | Operation
+  | UnaryOperation
+  | Expression
+  | Value[];
+"#;
+
+const RESERVED: [&str; 11] = [
+  "Identifier",
+  "Color",
+  "VPath",
+  "Reference",
+  "FunctionCall",
+  "Operation",
+  "UnaryOperation",
+  "Expression",
+  "Value",
+  "Dashboard",
+  "Entity",
+];
+
+/// Everything seen for one entity type across the document.
+#[derive(Debug, Default)]
+struct EntitySchema {
+  count: usize,
+  fields: BTreeMap<&'static str, usize>,
+  properties: BTreeMap<String, (BTreeSet<String>, usize)>,
+  children: BTreeSet<String>,
+}
+
+/// Emits TypeScript declarations for a document from [`crate::lower`], with one interface per
+/// entity type seen. Properties missing on some entities of a type are optional.
+pub fn typescript(model: &Value) -> String {
+  let mut schemas: BTreeMap<String, EntitySchema> = BTreeMap::new();
+  let entities = model["entities"].as_array().cloned().unwrap_or_default();
+  for entity in &entities {
+    collect(entity, &mut schemas);
+  }
+  let names = interface_names(schemas.keys());
+
+  let mut ts = String::from(PRELUDE);
+  for (entity_type, schema) in &schemas {
+    let _ = writeln!(ts, "\nexport interface {} {{", names[entity_type]);
+    let _ = writeln!(ts, "  type: {};", quote(entity_type));
+    for (field, ts_type) in [
+      ("id", "string"),
+      ("label", "string"),
+      ("extends", "string[]"),
+      ("number", "number"),
+    ] {
+      if let Some(seen) = schema.fields.get(field) {
+        let optional = if *seen < schema.count { "?" } else { "" };
+        let _ = writeln!(ts, "  {}{}: {};", field, optional, ts_type);
+      }
+    }
+    let _ = writeln!(ts, "  properties: {{");
+    for (name, (types, seen)) in &schema.properties {
+      let optional = if *seen < schema.count { "?" } else { "" };
+      let _ = writeln!(
+        ts,
+        "    {}{}: {};",
+        property_key(name),
+        optional,
+        union(types)
+      );
+    }
+    let _ = writeln!(ts, "  }};");
+    let children = schema
+      .children
+      .iter()
+      .map(|child| names[child].clone())
+      .collect::<BTreeSet<_>>();
+    let _ = writeln!(ts, "  children: {};", array_of(&children, "never"));
+    let _ = writeln!(ts, "}}");
+  }
+
+  let roots = entities
+    .iter()
+    .filter_map(|entity| entity["type"].as_str())
+    .map(|entity_type| names[entity_type].clone())
+    .collect::<BTreeSet<_>>();
+  let _ = writeln!(
+    ts,
+    "\nexport type Entity = {};",
+    if names.is_empty() {
+      "never".to_string()
+    } else {
+      names
+        .values()
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join(" | ")
+    }
+  );
+  let _ = writeln!(ts, "\nexport interface Dashboard {{");
+  let _ = writeln!(ts, "  titles: string[];");
+  let _ = writeln!(ts, "  entities: {};", array_of(&roots, "never"));
+  let _ = writeln!(ts, "}}");
+  ts
+}
+
+fn collect(entity: &Value, schemas: &mut BTreeMap<String, EntitySchema>) {
+  let Some(entity_type) = entity["type"].as_str() else {
+    return;
+  };
+  let schema = schemas.entry(entity_type.to_string()).or_default();
+  schema.count += 1;
+  for field in ["id", "label", "extends", "number"] {
+    if entity.get(field).is_some() {
+      *schema.fields.entry(field).or_default() += 1;
+    }
+  }
+  if let Some(properties) = entity["properties"].as_object() {
+    for (name, value) in properties {
+      let (types, seen) = schema.properties.entry(name.clone()).or_default();
+      types.insert(type_of(value));
+      *seen += 1;
+    }
+  }
+  let children = entity["children"].as_array().cloned().unwrap_or_default();
+  for child in &children {
+    if let Some(child_type) = child["type"].as_str() {
+      schema.children.insert(child_type.to_string());
+    }
+  }
+  for child in &children {
+    collect(child, schemas);
+  }
+}
+
+fn type_of(value: &Value) -> String {
+  match value {
+    Value::Null => "null".to_string(),
+    Value::Bool(_) => "boolean".to_string(),
+    Value::Number(_) => "number".to_string(),
+    Value::String(_) => "string".to_string(),
+    Value::Array(values) => {
+      let types = values.iter().map(type_of).collect::<BTreeSet<_>>();
+      array_of(&types, "Value")
+    }
+    Value::Object(object) => {
+      let tag = [
+        "identifier",
+        "color",
+        "vpath",
+        "$ref",
+        "function",
+        "expression",
+      ]
+      .into_iter()
+      .find(|key| object.contains_key(*key));
+      match tag {
+        Some("identifier") => "Identifier",
+        Some("color") => "Color",
+        Some("vpath") => "VPath",
+        Some("$ref") => "Reference",
+        Some("function") => "FunctionCall",
+        Some("expression") => "Expression",
+        _ if object.contains_key("operand") => "UnaryOperation",
+        _ if object.contains_key("operator") => "Operation",
+        _ => "Value",
+      }
+      .to_string()
+    }
+  }
+}
+
+fn union(types: &BTreeSet<String>) -> String {
+  types.iter().cloned().collect::<Vec<_>>().join(" | ")
+}
+
+fn array_of(types: &BTreeSet<String>, empty: &str) -> String {
+  match types.len() {
+    0 => format!("{}[]", empty),
+    1 => format!("{}[]", union(types)),
+    _ => format!("({})[]", union(types)),
+  }
+}
+
+/// PascalCase names for the entity types, made unique and kept clear of the prelude types.
+fn interface_names<'a>(entity_types: impl Iterator<Item = &'a String>) -> BTreeMap<String, String> {
+  let mut used: HashSet<String> = RESERVED.iter().map(|name| name.to_string()).collect();
+  let mut names = BTreeMap::new();
+  for entity_type in entity_types {
+    let mut base: String = entity_type
+      .split(|c: char| !c.is_ascii_alphanumeric())
+      .filter(|part| !part.is_empty())
+      .map(|part| {
+        let mut chars = part.chars();
+        let first = chars.next().unwrap().to_ascii_uppercase();
+        std::iter::once(first).chain(chars).collect::<String>()
+      })
+      .collect();
+    if base.is_empty() || base.starts_with(|c: char| c.is_ascii_digit()) {
+      base = format!("Entity{}", base);
+    }
+    let mut name = base.clone();
+    let mut index = 2;
+    while used.contains(&name) {
+      name = format!("{}{}", base, index);
+      index += 1;
+    }
+    used.insert(name.clone());
+    names.insert(entity_type.clone(), name);
+  }
+  names
+}
+
+fn is_identifier(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars
+    .next()
+    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn property_key(name: &str) -> String {
+  if is_identifier(name) {
+    name.to_string()
+  } else {
+    quote(name)
+  }
+}
+
+fn quote(text: &str) -> String {
+  Value::String(text.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{lower_text, References};
+
+  #[test]
+  fn emits_interface_per_entity_type() {
+    let model = lower_text(
+      r#"page #a {
+  label: "A"
+  widget kpi {
+    size: 1 + 2
+    data-set: x
+  }
+}
+page {
+  widget kpi {
+    size: 3
+  }
+}
+"#,
+      References::Link,
+    )
+    .unwrap();
+    let ts = typescript(&model);
+    assert!(ts.contains(
+      "export interface Page {\n  type: \"page\";\n  id?: string;\n  properties: {\n    label?: string;\n  };\n  children: WidgetKpi[];\n}"
+    ));
+    assert!(ts.contains(
+      "export interface WidgetKpi {\n  type: \"widget kpi\";\n  properties: {\n    \"data-set\"?: Identifier;\n    size: Operation | number;\n  };\n  children: never[];\n}"
+    ));
+    assert!(ts.contains("export type Entity = Page | WidgetKpi;"));
+    assert!(ts.contains("  entities: Page[];"));
+  }
+
+  #[test]
+  fn interface_names_are_unique() {
+    let types = [
+      "value".to_string(),
+      "widget kpi".to_string(),
+      "widget-kpi".to_string(),
+    ];
+    let names = interface_names(types.iter());
+    assert_eq!("Value2", names["value"]);
+    assert_eq!("WidgetKpi", names["widget kpi"]);
+    assert_eq!("WidgetKpi2", names["widget-kpi"]);
+  }
+}
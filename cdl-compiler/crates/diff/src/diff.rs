@@ -0,0 +1,556 @@
+use std::{
+  collections::HashMap,
+  fmt::{self, Display},
+};
+
+use anyhow::Result;
+use ast::{Ast, Comments};
+use parser::parse_text;
+use serde::Serialize;
+
+use crate::entities::{collect_script, EntityInfo, Member, MemberKind};
+
+/// One difference between two scripts. Entities are named by their key, `#ident` when the
+/// entity has one, `parent key/#ident` when the ident is used more than once, otherwise the path
+/// of entity types from the script root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+  TitleChanged {
+    old: String,
+    new: String,
+  },
+  EntityAdded {
+    entity: String,
+    header: String,
+  },
+  EntityRemoved {
+    entity: String,
+    header: String,
+  },
+  EntityMoved {
+    entity: String,
+    from: String,
+    to: String,
+  },
+  HeaderChanged {
+    entity: String,
+    old: String,
+    new: String,
+  },
+  PropertyAdded {
+    entity: String,
+    property: String,
+    value: String,
+  },
+  PropertyRemoved {
+    entity: String,
+    property: String,
+    value: String,
+  },
+  PropertyChanged {
+    entity: String,
+    property: String,
+    old: String,
+    new: String,
+  },
+  TableAliasAdded {
+    entity: String,
+    alias: String,
+    table: String,
+  },
+  TableAliasRemoved {
+    entity: String,
+    alias: String,
+    table: String,
+  },
+  TableAliasChanged {
+    entity: String,
+    alias: String,
+    old: String,
+    new: String,
+  },
+  ConstAdded {
+    entity: String,
+    name: String,
+    value: String,
+  },
+  ConstRemoved {
+    entity: String,
+    name: String,
+    value: String,
+  },
+  ConstChanged {
+    entity: String,
+    name: String,
+    old: String,
+    new: String,
+  },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiffReport {
+  pub changes: Vec<Change>,
+}
+
+impl DiffReport {
+  pub fn is_empty(&self) -> bool {
+    self.changes.is_empty()
+  }
+}
+
+impl Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Change::TitleChanged { old, new } => write!(f, "~ title: {} -> {}", old, new),
+      Change::EntityAdded { entity, header } => write!(f, "+ {} ({})", entity, header),
+      Change::EntityRemoved { entity, header } => write!(f, "- {} ({})", entity, header),
+      Change::EntityMoved { entity, from, to } => {
+        write!(f, "> {} moved from {} to {}", entity, from, to)
+      }
+      Change::HeaderChanged { entity, old, new } => {
+        write!(f, "~ {} header: {} -> {}", entity, old, new)
+      }
+      Change::PropertyAdded {
+        entity,
+        property,
+        value,
+      } => write!(f, "+ {}.{}: {}", entity, property, value),
+      Change::PropertyRemoved {
+        entity,
+        property,
+        value,
+      } => write!(f, "- {}.{}: {}", entity, property, value),
+      Change::PropertyChanged {
+        entity,
+        property,
+        old,
+        new,
+      } => write!(f, "~ {}.{}: {} -> {}", entity, property, old, new),
+      Change::TableAliasAdded {
+        entity,
+        alias,
+        table,
+      } => write!(f, "+ {}.table {} = {}", entity, alias, table),
+      Change::TableAliasRemoved {
+        entity,
+        alias,
+        table,
+      } => write!(f, "- {}.table {} = {}", entity, alias, table),
+      Change::TableAliasChanged {
+        entity,
+        alias,
+        old,
+        new,
+      } => write!(f, "~ {}.table {}: {} -> {}", entity, alias, old, new),
+      Change::ConstAdded {
+        entity,
+        name,
+        value,
+      } => write!(f, "+ {}.const {} = {}", entity, name, value),
+      Change::ConstRemoved {
+        entity,
+        name,
+        value,
+      } => write!(f, "- {}.const {} = {}", entity, name, value),
+      Change::ConstChanged {
+        entity,
+        name,
+        old,
+        new,
+      } => write!(f, "~ {}.const {}: {} -> {}", entity, name, old, new),
+    }
+  }
+}
+
+impl Display for DiffReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for change in &self.changes {
+      writeln!(f, "{}", change)?;
+    }
+    Ok(())
+  }
+}
+
+/// Compares two scripts entity by entity, ignoring formatting and comments.
+/// Entities are matched on `#ident`, entities without one on their type path. Idents used more
+/// than once in a script are matched together with their parent, an entity with such an ident is
+/// reported as removed and added rather than moved. An entity is reported as moved when it ends
+/// up under a different parent. Table aliases and consts
+/// outside of any entity belong to the `<script>` entity.
+pub fn diff(old: &Ast, new: &Ast) -> Result<DiffReport> {
+  let old = collect_script(old, &Comments::new())?;
+  let new = collect_script(new, &Comments::new())?;
+  let mut changes = vec![];
+  diff_members(&old.root, &new.root, &mut changes);
+
+  let old_entities = old.entities;
+  let new_entities = new.entities;
+  let old_by_key: HashMap<&str, &EntityInfo> = old_entities
+    .iter()
+    .map(|entity| (entity.key.as_str(), entity))
+    .collect();
+  let new_by_key: HashMap<&str, &EntityInfo> = new_entities
+    .iter()
+    .map(|entity| (entity.key.as_str(), entity))
+    .collect();
+
+  for entity in &old_entities {
+    if !new_by_key.contains_key(entity.key.as_str()) {
+      changes.push(Change::EntityRemoved {
+        entity: entity.key.clone(),
+        header: entity.header.clone(),
+      });
+    }
+  }
+  for entity in &new_entities {
+    match old_by_key.get(entity.key.as_str()) {
+      None => changes.push(Change::EntityAdded {
+        entity: entity.key.clone(),
+        header: entity.header.clone(),
+      }),
+      Some(old_entity) => diff_entity(old_entity, entity, &mut changes),
+    }
+  }
+  Ok(DiffReport { changes })
+}
+
+pub fn diff_text(old: &str, new: &str) -> Result<DiffReport> {
+  let old = parse_text(old)?;
+  let new = parse_text(new)?;
+  diff(&old, &new)
+}
+
+fn diff_entity(old: &EntityInfo, new: &EntityInfo, changes: &mut Vec<Change>) {
+  if old.parent != new.parent {
+    changes.push(Change::EntityMoved {
+      entity: new.key.clone(),
+      from: old.parent.clone(),
+      to: new.parent.clone(),
+    });
+  }
+  if old.header != new.header {
+    changes.push(Change::HeaderChanged {
+      entity: new.key.clone(),
+      old: old.header.clone(),
+      new: new.header.clone(),
+    });
+  }
+  diff_members(old, new, changes);
+}
+
+fn diff_members(old: &EntityInfo, new: &EntityInfo, changes: &mut Vec<Change>) {
+  for member in &old.members {
+    match new.member(member.kind, &member.key) {
+      None => changes.push(member_removed(&new.key, member)),
+      Some(new_value) if *new_value != member.value => {
+        changes.push(member_changed(&new.key, member, new_value))
+      }
+      Some(_) => {}
+    }
+  }
+  for member in &new.members {
+    if old.member(member.kind, &member.key).is_none() {
+      changes.push(member_added(&new.key, member));
+    }
+  }
+}
+
+fn member_added(entity: &str, member: &Member) -> Change {
+  let (entity, key, value) = (entity.to_string(), member.key.clone(), member.value.clone());
+  match member.kind {
+    MemberKind::Title => Change::TitleChanged {
+      old: String::new(),
+      new: value,
+    },
+    MemberKind::Property => Change::PropertyAdded {
+      entity,
+      property: key,
+      value,
+    },
+    MemberKind::TableAlias => Change::TableAliasAdded {
+      entity,
+      alias: key,
+      table: value,
+    },
+    MemberKind::Const => Change::ConstAdded {
+      entity,
+      name: key,
+      value,
+    },
+  }
+}
+
+fn member_removed(entity: &str, member: &Member) -> Change {
+  let (entity, key, value) = (entity.to_string(), member.key.clone(), member.value.clone());
+  match member.kind {
+    MemberKind::Title => Change::TitleChanged {
+      old: value,
+      new: String::new(),
+    },
+    MemberKind::Property => Change::PropertyRemoved {
+      entity,
+      property: key,
+      value,
+    },
+    MemberKind::TableAlias => Change::TableAliasRemoved {
+      entity,
+      alias: key,
+      table: value,
+    },
+    MemberKind::Const => Change::ConstRemoved {
+      entity,
+      name: key,
+      value,
+    },
+  }
+}
+
+fn member_changed(entity: &str, member: &Member, new: &str) -> Change {
+  let (entity, key) = (entity.to_string(), member.key.clone());
+  let (old, new) = (member.value.clone(), new.to_string());
+  match member.kind {
+    MemberKind::Title => Change::TitleChanged { old, new },
+    MemberKind::Property => Change::PropertyChanged {
+      entity,
+      property: key,
+      old,
+      new,
+    },
+    MemberKind::TableAlias => Change::TableAliasChanged {
+      entity,
+      alias: key,
+      old,
+      new,
+    },
+    MemberKind::Const => Change::ConstChanged {
+      entity,
+      name: key,
+      old,
+      new,
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const OLD: &str = r#"title "t"
+page #overview {
+  label: "Overview"
+  widget kpi #sales {
+    label: "Sales"
+    size: small
+  }
+  widget markdown {
+    markdown: "hello"
+  }
+}
+page #details {
+  widget kpi #cost {
+    label: "Cost"
+  }
+}
+"#;
+
+  #[test]
+  fn formatting_is_not_a_change() {
+    let new = "title \"t\"\npage #overview {\nlabel:\"Overview\" // comment\nwidget kpi #sales {\nlabel: \"Sales\"\nsize: small\n}\nwidget markdown {\nmarkdown: \"hello\"\n}\n}\npage #details {\nwidget kpi #cost {\nlabel: \"Cost\"\n}\n}\n";
+    assert!(diff_text(OLD, new).unwrap().is_empty());
+  }
+
+  #[test]
+  fn reports_property_changes() {
+    let new = OLD
+      .replace("label: \"Sales\"", "label: \"Revenue\"")
+      .replace("    size: small\n", "")
+      .replace("label: \"Cost\"", "label: \"Cost\"\n    size: large");
+    let report = diff_text(OLD, &new).unwrap();
+    assert_eq!(
+      vec![
+        Change::PropertyChanged {
+          entity: "#sales".to_string(),
+          property: "label".to_string(),
+          old: "\"Sales\"".to_string(),
+          new: "\"Revenue\"".to_string(),
+        },
+        Change::PropertyRemoved {
+          entity: "#sales".to_string(),
+          property: "size".to_string(),
+          value: "small".to_string(),
+        },
+        Change::PropertyAdded {
+          entity: "#cost".to_string(),
+          property: "size".to_string(),
+          value: "large".to_string(),
+        },
+      ],
+      report.changes
+    );
+  }
+
+  #[test]
+  fn reports_added_removed_and_moved_entities() {
+    let new = r#"title "t"
+page #overview {
+  label: "Overview"
+  widget markdown {
+    markdown: "hello"
+  }
+}
+page #details {
+  widget kpi #cost {
+    label: "Cost"
+  }
+  widget kpi #sales {
+    label: "Sales"
+    size: small
+  }
+  widget kpi #margin {
+    label: "Margin"
+  }
+}
+"#;
+    let report = diff_text(OLD, new).unwrap();
+    assert_eq!(
+      vec![
+        Change::EntityMoved {
+          entity: "#sales".to_string(),
+          from: "#overview".to_string(),
+          to: "#details".to_string(),
+        },
+        Change::EntityAdded {
+          entity: "#margin".to_string(),
+          header: "widget kpi #margin".to_string(),
+        },
+      ],
+      report.changes
+    );
+    assert_eq!(
+      "> #sales moved from #overview to #details\n+ #margin (widget kpi #margin)\n",
+      report.to_string()
+    );
+  }
+
+  #[test]
+  fn entities_without_ident_are_matched_by_path() {
+    let new = OLD.replace("widget markdown {", "widget markdown \"Intro\" {");
+    let report = diff_text(OLD, &new).unwrap();
+    assert_eq!(
+      vec![Change::HeaderChanged {
+        entity: "/page[0]/widget markdown[0]".to_string(),
+        old: "widget markdown".to_string(),
+        new: "widget markdown \"Intro\"".to_string(),
+      }],
+      report.changes
+    );
+  }
+
+  #[test]
+  fn repeated_idents_are_matched_within_their_parent() {
+    let old = "page #p {\n  widget kpi #k {\n    label: \"P\"\n  }\n}\npage #q {\n  widget kpi #k {\n    label: \"Q\"\n  }\n}\n";
+    assert!(diff_text(old, old).unwrap().is_empty());
+    let new = old.replace("\"Q\"", "\"Q2\"");
+    assert_eq!(
+      vec![Change::PropertyChanged {
+        entity: "#q/#k".to_string(),
+        property: "label".to_string(),
+        old: "\"Q\"".to_string(),
+        new: "\"Q2\"".to_string(),
+      }],
+      diff_text(old, &new).unwrap().changes
+    );
+  }
+
+  #[test]
+  fn test_scripts_have_no_changes_against_themselves() {
+    for script in [
+      include_str!("../../../test_script/test.cdl"),
+      include_str!("../../../test_script/large.cdl"),
+      include_str!("../../../test_script/canvas_example.cdl"),
+      include_str!("../../../test_script/workforce.cdl"),
+    ] {
+      let ast = parse_text(script).unwrap();
+      assert!(diff(&ast, &ast).unwrap().is_empty());
+    }
+  }
+
+  #[test]
+  fn reports_title_changes() {
+    let new = OLD.replace("title \"t\"", "title \"Sales\"");
+    let report = diff_text(OLD, &new).unwrap();
+    assert_eq!(
+      vec![Change::TitleChanged {
+        old: "\"t\"".to_string(),
+        new: "\"Sales\"".to_string(),
+      }],
+      report.changes
+    );
+  }
+
+  #[test]
+  fn reports_table_alias_changes() {
+    let old = "config hub {\n  hub: 1\n  table a = ds.one\n  table b = ds.two\n}\n";
+    let new = "config hub {\n  hub: 1\n  table a = ds.three\n  table c = ds.two\n}\n";
+    let report = diff_text(old, new).unwrap();
+    let entity = "/config hub[0]".to_string();
+    assert_eq!(
+      vec![
+        Change::TableAliasChanged {
+          entity: entity.clone(),
+          alias: "a".to_string(),
+          old: "ds.one".to_string(),
+          new: "ds.three".to_string(),
+        },
+        Change::TableAliasRemoved {
+          entity: entity.clone(),
+          alias: "b".to_string(),
+          table: "ds.two".to_string(),
+        },
+        Change::TableAliasAdded {
+          entity,
+          alias: "c".to_string(),
+          table: "ds.two".to_string(),
+        },
+      ],
+      report.changes
+    );
+  }
+
+  #[test]
+  fn reports_const_changes() {
+    let old = format!("const limit = 10\nconst unused = 1\n{}", OLD);
+    let new = format!("const limit = 10 * 2\n{}", OLD);
+    let report = diff_text(&old, &new).unwrap();
+    assert_eq!(
+      vec![
+        Change::ConstChanged {
+          entity: "<script>".to_string(),
+          name: "limit".to_string(),
+          old: "10".to_string(),
+          new: "10 * 2".to_string(),
+        },
+        Change::ConstRemoved {
+          entity: "<script>".to_string(),
+          name: "unused".to_string(),
+          value: "1".to_string(),
+        },
+      ],
+      report.changes
+    );
+    assert_eq!(
+      "~ <script>.const limit: 10 -> 10 * 2\n- <script>.const unused = 1\n",
+      report.to_string()
+    );
+  }
+
+  #[test]
+  fn can_serialize_report() {
+    let new = OLD.replace("label: \"Cost\"", "label: \"Costs\"");
+    let report = diff_text(OLD, &new).unwrap();
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!("property_changed", json["changes"][0]["kind"]);
+    assert_eq!("#cost", json["changes"][0]["entity"]);
+  }
+}
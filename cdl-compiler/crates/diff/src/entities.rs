@@ -0,0 +1,278 @@
+use std::{
+  collections::{HashMap, HashSet},
+  rc::Rc,
+};
+
+use anyhow::Result;
+use ast::{Ast, AstEntityNode, AstNode, CommentPlace, Comments, Node, NodeRef, DEFAULT_WIDTH};
+
+pub(crate) const ROOT: &str = "<script>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum MemberKind {
+  Title,
+  Property,
+  TableAlias,
+  Const,
+}
+
+/// A title, property, table alias or const. The key is its name, repeated names of the same
+/// kind get an `[n]` suffix. The value of a table alias is the table.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Member {
+  pub kind: MemberKind,
+  pub key: String,
+  pub value: String,
+  /// The whole statement in the canonical format, with its comments
+  pub text: String,
+}
+
+/// Flattened view of an entity used for matching across scripts. The key is `#ident` when the
+/// entity has one that is not used anywhere else in the script, `parent key/#ident` when it is,
+/// otherwise the path of entity types from the script root.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EntityInfo {
+  pub key: String,
+  pub parent: String,
+  pub header: String,
+  /// In source order
+  pub members: Vec<Member>,
+  /// Comments on the header and closing brace
+  pub comments: Vec<(CommentPlace, String)>,
+}
+
+impl EntityInfo {
+  pub fn member(&self, kind: MemberKind, key: &str) -> Option<&String> {
+    self
+      .members
+      .iter()
+      .find(|member| member.kind == kind && member.key == key)
+      .map(|member| &member.value)
+  }
+}
+
+/// Entities in source order, parents before their children.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ScriptInfo {
+  /// The titles, table aliases and consts outside of any entity, keyed `ROOT`. Its comments
+  /// are the ones at the start and end of the script.
+  pub root: EntityInfo,
+  pub entities: Vec<EntityInfo>,
+}
+
+/// `comments` only end up in the `text` of members and the `comments` of entities, pass
+/// `Comments::new()` when they don't matter.
+pub(crate) fn collect_script(ast: &Ast, comments: &Comments) -> Result<ScriptInfo> {
+  let root = EntityInfo {
+    key: ROOT.to_string(),
+    parent: String::new(),
+    header: String::new(),
+    members: collect_members(ast, ast.script_entity, comments)?,
+    comments: collect_comments(
+      ast.script_entity,
+      &[CommentPlace::Before, CommentPlace::BeforeClose],
+      comments,
+    ),
+  };
+  let mut idents = HashMap::new();
+  count_idents(ast, ast.script_entity, &mut idents);
+  let mut collector = Collector {
+    ast,
+    comments,
+    idents,
+    keys: HashSet::new(),
+    entities: vec![],
+  };
+  collector.collect_children(ast.script_entity, ROOT, "")?;
+  Ok(ScriptInfo {
+    root,
+    entities: collector.entities,
+  })
+}
+
+/// The entity children of `node_ref`, with their nodes.
+fn child_entities(ast: &Ast, node_ref: NodeRef) -> Vec<(NodeRef, Rc<AstNode>)> {
+  let Some(node) = ast.get_node(node_ref) else {
+    return vec![];
+  };
+  node
+    .node_data
+    .children()
+    .into_iter()
+    .filter_map(|child_ref| Some((child_ref, ast.get_node(child_ref)?)))
+    .filter(|(_, child)| matches!(child.node_data, Node::Entity(_)))
+    .collect()
+}
+
+fn count_idents(ast: &Ast, node_ref: NodeRef, idents: &mut HashMap<String, usize>) {
+  for (child_ref, child) in child_entities(ast, node_ref) {
+    let Node::Entity(entity) = &child.node_data else {
+      continue;
+    };
+    if let Some(ident) = &entity.ident {
+      *idents.entry(ident.to_string()).or_default() += 1;
+    }
+    count_idents(ast, child_ref, idents);
+  }
+}
+
+struct Collector<'a> {
+  ast: &'a Ast,
+  comments: &'a Comments,
+  /// How often each ident is used in the script
+  idents: HashMap<String, usize>,
+  keys: HashSet<String>,
+  entities: Vec<EntityInfo>,
+}
+
+impl Collector<'_> {
+  fn collect_children(&mut self, node_ref: NodeRef, key: &str, path: &str) -> Result<()> {
+    let mut seen_paths: HashMap<String, usize> = HashMap::new();
+    for (child_ref, child) in child_entities(self.ast, node_ref) {
+      let Node::Entity(entity) = &child.node_data else {
+        continue;
+      };
+      let terms = entity
+        .terms
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+      let count = seen_paths.entry(terms.clone()).or_default();
+      let child_path = format!("{}/{}[{}]", path, terms, count);
+      *count += 1;
+      let child_key = self.unique_key(match &entity.ident {
+        // idents used more than once in the script are told apart by their parent
+        Some(ident) if self.idents[ident.as_str()] > 1 => format!("{}/#{}", key, ident),
+        Some(ident) => format!("#{}", ident),
+        None => child_path.clone(),
+      });
+      self.entities.push(EntityInfo {
+        key: child_key.clone(),
+        parent: key.to_string(),
+        header: entity_header(entity),
+        members: collect_members(self.ast, child_ref, self.comments)?,
+        comments: collect_comments(
+          child_ref,
+          &[
+            CommentPlace::Before,
+            CommentPlace::EndOfLine,
+            CommentPlace::BeforeClose,
+            CommentPlace::AfterClose,
+          ],
+          self.comments,
+        ),
+      });
+      self.collect_children(child_ref, &child_key, &child_path)?;
+    }
+    Ok(())
+  }
+
+  /// Adds an `[n]` suffix when `key` is already taken, by a repeated ident under one parent.
+  fn unique_key(&mut self, key: String) -> String {
+    let mut unique = key.clone();
+    let mut index = 1;
+    while !self.keys.insert(unique.clone()) {
+      unique = format!("{}[{}]", key, index);
+      index += 1;
+    }
+    unique
+  }
+}
+
+fn collect_comments(
+  node_ref: NodeRef,
+  places: &[CommentPlace],
+  comments: &Comments,
+) -> Vec<(CommentPlace, String)> {
+  places
+    .iter()
+    .flat_map(|place| {
+      comments
+        .get(node_ref, *place)
+        .iter()
+        .map(|comment| (*place, comment.clone()))
+    })
+    .collect()
+}
+
+fn collect_members(ast: &Ast, node_ref: NodeRef, comments: &Comments) -> Result<Vec<Member>> {
+  let mut members: Vec<Member> = vec![];
+  let Some(node) = ast.get_node(node_ref) else {
+    return Ok(members);
+  };
+  for child_ref in node.node_data.children() {
+    let Some(child) = ast.get_node(child_ref) else {
+      continue;
+    };
+    let (kind, name, value) = match &child.node_data {
+      Node::Title(title) => (
+        MemberKind::Title,
+        "title".to_string(),
+        title.title.to_string(),
+      ),
+      Node::Property(property) => {
+        let name = property.name.to_string();
+        let cdl = ast.node_to_cdl(child_ref)?;
+        let value = cdl
+          .trim()
+          .strip_prefix(&format!("{}:", name))
+          .unwrap_or(cdl.trim())
+          .trim()
+          .to_string();
+        (MemberKind::Property, name, value)
+      }
+      Node::TableAlias(alias) => (
+        MemberKind::TableAlias,
+        alias.alias.to_string(),
+        alias.table.to_string(),
+      ),
+      Node::Const(c) => (
+        MemberKind::Const,
+        c.name.to_string(),
+        ast.node_to_cdl(c.value.get())?.trim().to_string(),
+      ),
+      _ => continue,
+    };
+    // repeated names are told apart by their index
+    let mut key = name.clone();
+    let mut index = 1;
+    while members.iter().any(|m| m.kind == kind && m.key == key) {
+      key = format!("{}[{}]", name, index);
+      index += 1;
+    }
+    let text = ast.node_to_cdl_with_comments(child_ref, DEFAULT_WIDTH, comments)?;
+    members.push(Member {
+      kind,
+      key,
+      value,
+      text,
+    });
+  }
+  Ok(members)
+}
+
+/// Strips the `[n]` suffix added to repeated properties.
+pub(crate) fn property_name(key: &str) -> &str {
+  match key.find('[') {
+    Some(index) if key.ends_with(']') => &key[..index],
+    _ => key,
+  }
+}
+
+fn entity_header(entity: &AstEntityNode) -> String {
+  let mut header: Vec<String> = entity.terms.iter().map(|t| t.to_string()).collect();
+  if let Some(label) = &entity.label {
+    header.push(label.to_string());
+  }
+  for r in &entity.refs {
+    header.push(format!("@{}", r));
+  }
+  if let Some(id) = &entity.ident {
+    header.push(format!("#{}", id));
+  }
+  if let Some(num) = &entity.entity_number {
+    header.push(num.to_string());
+  }
+  header.join(" ")
+}
@@ -0,0 +1,6 @@
+mod diff;
+mod entities;
+mod merge;
+
+pub use diff::*;
+pub use merge::*;
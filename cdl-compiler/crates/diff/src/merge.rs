@@ -0,0 +1,680 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Write,
+  hash::Hash,
+};
+
+use anyhow::Result;
+use ast::CommentPlace;
+use lexer::{lex_borrowed, TokenKind};
+use parser::{parse_cst, Cst};
+use serde::Serialize;
+
+use crate::entities::{collect_script, property_name, EntityInfo, MemberKind, ROOT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+  Title,
+  Header,
+  Property,
+  TableAlias,
+  Const,
+  /// The comments on an entity header or closing brace, ours are kept
+  Comment,
+  /// Changed on one side, removed on the other
+  Entity,
+  /// Moved to different parents on each side, the entity is kept where ours put it
+  Parent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Conflict {
+  pub kind: ConflictKind,
+  pub entity: String,
+  pub property: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MergeResult {
+  pub text: String,
+  pub conflicts: Vec<Conflict>,
+}
+
+impl MergeResult {
+  pub fn is_clean(&self) -> bool {
+    self.conflicts.is_empty()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Merged<T> {
+  Clean(T),
+  Conflict { ours: T, theirs: T },
+}
+
+#[derive(Debug)]
+struct MergedEntity<'a> {
+  key: &'a str,
+  parent: &'a str,
+  header: Merged<&'a str>,
+  comments: &'a [(CommentPlace, String)],
+  /// The statement text of each title, property, table alias and const
+  members: Vec<Merged<Option<&'a str>>>,
+  /// Set when the entity was removed on one side, `true` when ours is the side that kept it
+  kept_by_ours: Option<bool>,
+}
+
+/// Three-way merge of scripts at entity and statement granularity. Entities are matched the
+/// same way as in `diff`, a conflict is only reported when both sides changed the same title,
+/// property, table alias, const, header or entity comment differently, or one side changed an
+/// entity the other removed. Comments go with the statement they belong to. The merged text is
+/// printed in the canonical format, conflicts are marked with `<<<<<<< ours`, `=======` and
+/// `>>>>>>> theirs` around the lines in question.
+pub fn merge(base: &Cst, ours: &Cst, theirs: &Cst) -> Result<MergeResult> {
+  let base = collect_script(base.ast(), &base.comments())?;
+  let ours = collect_script(ours.ast(), &ours.comments())?;
+  let theirs = collect_script(theirs.ast(), &theirs.comments())?;
+  let base_by_key = by_key(&base.entities);
+  let ours_by_key = by_key(&ours.entities);
+  let theirs_by_key = by_key(&theirs.entities);
+
+  let mut conflicts = vec![];
+  let root = merge_entity(Some(&base.root), &ours.root, &theirs.root, &mut conflicts);
+
+  // entities theirs moved to another parent are placed the way theirs has them
+  let moved_by_theirs = theirs
+    .entities
+    .iter()
+    .filter(|t| {
+      let base_parent = base_by_key.get(t.key.as_str()).map(|b| &b.parent);
+      let ours_parent = ours_by_key.get(t.key.as_str()).map(|o| &o.parent);
+      base_parent.is_some() && base_parent == ours_parent && base_parent != Some(&t.parent)
+    })
+    .map(|t| t.key.as_str())
+    .collect::<HashSet<_>>();
+  let keys = merge_order(
+    &ours
+      .entities
+      .iter()
+      .map(|e| e.key.as_str())
+      .filter(|key| !moved_by_theirs.contains(key))
+      .collect::<Vec<_>>(),
+    &theirs
+      .entities
+      .iter()
+      .map(|e| e.key.as_str())
+      .collect::<Vec<_>>(),
+  );
+  let mut entities = vec![];
+  for key in keys {
+    let base_entity = base_by_key.get(key).copied();
+    let ours_entity = ours_by_key.get(key).copied();
+    let theirs_entity = theirs_by_key.get(key).copied();
+    let merged = match (ours_entity, theirs_entity) {
+      (Some(o), Some(t)) => merge_entity(base_entity, o, t, &mut conflicts),
+      (Some(kept), None) | (None, Some(kept)) => {
+        let kept_by_ours = ours_entity.is_some();
+        match base_entity {
+          None => unchanged(kept),
+          Some(b) if b == kept => continue,
+          Some(_) => {
+            conflicts.push(Conflict {
+              kind: ConflictKind::Entity,
+              entity: key.to_string(),
+              property: None,
+            });
+            MergedEntity {
+              kept_by_ours: Some(kept_by_ours),
+              ..unchanged(kept)
+            }
+          }
+        }
+      }
+      (None, None) => continue,
+    };
+    entities.push(merged);
+  }
+
+  let present = entities.iter().map(|e| e.key).collect::<HashSet<_>>();
+  for entity in &mut entities {
+    if entity.parent != ROOT && !present.contains(entity.parent) {
+      conflicts.push(Conflict {
+        kind: ConflictKind::Entity,
+        entity: entity.key.to_string(),
+        property: None,
+      });
+      entity.parent = ROOT;
+    }
+  }
+
+  let mut text = String::new();
+  write_comment_lines(&mut text, root.comments, CommentPlace::Before, 0)?;
+  write_members(&mut text, &root.members, 0)?;
+  let mut children: Children = HashMap::new();
+  for entity in &entities {
+    children.entry(entity.parent).or_default().push(entity);
+  }
+  write_children(&mut text, &children, ROOT, 0)?;
+  write_comment_lines(&mut text, root.comments, CommentPlace::BeforeClose, 0)?;
+  Ok(MergeResult { text, conflicts })
+}
+
+pub fn merge_text(base: &str, ours: &str, theirs: &str) -> Result<MergeResult> {
+  let base = parse_cst(base)?;
+  let ours = parse_cst(ours)?;
+  let theirs = parse_cst(theirs)?;
+  merge(&base, &ours, &theirs)
+}
+
+fn by_key(entities: &[EntityInfo]) -> HashMap<&str, &EntityInfo> {
+  entities
+    .iter()
+    .map(|entity| (entity.key.as_str(), entity))
+    .collect()
+}
+
+fn merge3<T: PartialEq + Clone>(
+  base: Option<&T>,
+  ours: Option<&T>,
+  theirs: Option<&T>,
+) -> Result<Option<T>, ()> {
+  if ours == theirs || base == theirs {
+    Ok(ours.cloned())
+  } else if base == ours {
+    Ok(theirs.cloned())
+  } else {
+    Err(())
+  }
+}
+
+/// Keeps the order of ours, items only in theirs go in after the item they follow in theirs.
+fn merge_order<T: Copy + Eq + Hash>(ours: &[T], theirs: &[T]) -> Vec<T> {
+  let mut result = ours.to_vec();
+  let mut seen = ours.iter().copied().collect::<HashSet<_>>();
+  for (index, item) in theirs.iter().enumerate() {
+    if !seen.insert(*item) {
+      continue;
+    }
+    let position = match index {
+      0 => 0,
+      _ => result
+        .iter()
+        .position(|existing| *existing == theirs[index - 1])
+        .map_or(result.len(), |position| position + 1),
+    };
+    result.insert(position, *item);
+  }
+  result
+}
+
+fn unchanged(entity: &EntityInfo) -> MergedEntity<'_> {
+  MergedEntity {
+    key: &entity.key,
+    parent: &entity.parent,
+    header: Merged::Clean(&entity.header),
+    comments: &entity.comments,
+    members: entity
+      .members
+      .iter()
+      .map(|member| Merged::Clean(Some(member.text.as_str())))
+      .collect(),
+    kept_by_ours: None,
+  }
+}
+
+fn merge_entity<'a>(
+  base: Option<&'a EntityInfo>,
+  ours: &'a EntityInfo,
+  theirs: &'a EntityInfo,
+  conflicts: &mut Vec<Conflict>,
+) -> MergedEntity<'a> {
+  let mut conflict = |kind, property: Option<&str>| {
+    conflicts.push(Conflict {
+      kind,
+      entity: ours.key.clone(),
+      property: property.map(|p| property_name(p).to_string()),
+    })
+  };
+
+  let parent = merge3(
+    base.map(|b| &b.parent),
+    Some(&ours.parent),
+    Some(&theirs.parent),
+  )
+  .unwrap_or_else(|()| {
+    conflict(ConflictKind::Parent, None);
+    Some(ours.parent.clone())
+  });
+  let parent = if parent.as_ref() == Some(&theirs.parent) {
+    &theirs.parent
+  } else {
+    &ours.parent
+  };
+
+  let header = match merge3(
+    base.map(|b| &b.header),
+    Some(&ours.header),
+    Some(&theirs.header),
+  ) {
+    Ok(header) if header.as_ref() == Some(&theirs.header) => Merged::Clean(theirs.header.as_str()),
+    Ok(_) => Merged::Clean(ours.header.as_str()),
+    Err(()) => {
+      conflict(ConflictKind::Header, None);
+      Merged::Conflict {
+        ours: ours.header.as_str(),
+        theirs: theirs.header.as_str(),
+      }
+    }
+  };
+
+  let comments = match merge3(
+    base.map(|b| &b.comments),
+    Some(&ours.comments),
+    Some(&theirs.comments),
+  ) {
+    Ok(comments) if comments.as_ref() == Some(&theirs.comments) => &theirs.comments,
+    Ok(_) => &ours.comments,
+    Err(()) => {
+      conflict(ConflictKind::Comment, None);
+      &ours.comments
+    }
+  };
+
+  let member_keys = |entity: &'a EntityInfo| {
+    entity
+      .members
+      .iter()
+      .map(|m| (m.kind, m.key.as_str()))
+      .collect::<Vec<_>>()
+  };
+  let member_text = |entity: Option<&'a EntityInfo>, kind, key| {
+    entity
+      .and_then(|e| e.members.iter().find(|m| m.kind == kind && m.key == key))
+      .map(|m| m.text.as_str())
+  };
+  let mut members = vec![];
+  for (kind, key) in merge_order(&member_keys(ours), &member_keys(theirs)) {
+    let base_text = member_text(base, kind, key);
+    let ours_text = member_text(Some(ours), kind, key);
+    let theirs_text = member_text(Some(theirs), kind, key);
+    let text = match merge3(base_text.as_ref(), ours_text.as_ref(), theirs_text.as_ref()) {
+      Ok(None) => continue,
+      Ok(text) => Merged::Clean(text),
+      Err(()) => {
+        match kind {
+          MemberKind::Title => conflict(ConflictKind::Title, None),
+          MemberKind::Property => conflict(ConflictKind::Property, Some(key)),
+          MemberKind::TableAlias => conflict(ConflictKind::TableAlias, Some(key)),
+          MemberKind::Const => conflict(ConflictKind::Const, Some(key)),
+        }
+        Merged::Conflict {
+          ours: ours_text,
+          theirs: theirs_text,
+        }
+      }
+    };
+    members.push(text);
+  }
+
+  MergedEntity {
+    key: &ours.key,
+    parent,
+    header,
+    comments,
+    members,
+    kept_by_ours: None,
+  }
+}
+
+/// The merged entities by the key of their parent, in order.
+type Children<'a> = HashMap<&'a str, Vec<&'a MergedEntity<'a>>>;
+
+fn write_children(
+  text: &mut String,
+  children: &Children,
+  parent: &str,
+  indent: usize,
+) -> std::fmt::Result {
+  for entity in children.get(parent).into_iter().flatten() {
+    match entity.kept_by_ours {
+      None => write_entity(text, children, entity, indent)?,
+      Some(kept_by_ours) => {
+        writeln!(text, "<<<<<<< ours")?;
+        if kept_by_ours {
+          write_entity(text, children, entity, indent)?;
+        }
+        writeln!(text, "=======")?;
+        if !kept_by_ours {
+          write_entity(text, children, entity, indent)?;
+        }
+        writeln!(text, ">>>>>>> theirs")?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn write_entity(
+  text: &mut String,
+  children: &Children,
+  entity: &MergedEntity,
+  indent: usize,
+) -> std::fmt::Result {
+  let indent_str = "  ".repeat(indent);
+  let mut open = String::from("{");
+  write_comments_inline(&mut open, entity.comments, CommentPlace::EndOfLine)?;
+  write_comment_lines(text, entity.comments, CommentPlace::Before, indent)?;
+  match &entity.header {
+    Merged::Clean(header) => writeln!(text, "{}{} {}", indent_str, header, open)?,
+    Merged::Conflict { ours, theirs } => {
+      writeln!(text, "<<<<<<< ours")?;
+      writeln!(text, "{}{} {}", indent_str, ours, open)?;
+      writeln!(text, "=======")?;
+      writeln!(text, "{}{} {}", indent_str, theirs, open)?;
+      writeln!(text, ">>>>>>> theirs")?;
+    }
+  }
+  write_members(text, &entity.members, indent + 1)?;
+  write_children(text, children, entity.key, indent + 1)?;
+  write_comment_lines(text, entity.comments, CommentPlace::BeforeClose, indent + 1)?;
+  write!(text, "{}}}", indent_str)?;
+  write_comments_inline(text, entity.comments, CommentPlace::AfterClose)?;
+  writeln!(text)
+}
+
+fn write_members(
+  text: &mut String,
+  members: &[Merged<Option<&str>>],
+  indent: usize,
+) -> std::fmt::Result {
+  for member in members {
+    match member {
+      Merged::Clean(member) => write_member(text, indent, *member)?,
+      Merged::Conflict { ours, theirs } => {
+        writeln!(text, "<<<<<<< ours")?;
+        write_member(text, indent, *ours)?;
+        writeln!(text, "=======")?;
+        write_member(text, indent, *theirs)?;
+        writeln!(text, ">>>>>>> theirs")?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn write_member(text: &mut String, indent: usize, member: Option<&str>) -> std::fmt::Result {
+  let Some(member) = member else {
+    return Ok(());
+  };
+  let indent_str = "  ".repeat(indent);
+  // lines that start inside a multi-line string or comment belong to it and are kept as is
+  let literals = lex_borrowed(member)
+    .map(|tokens| {
+      tokens
+        .into_iter()
+        .filter(|t| matches!(t.kind, TokenKind::String | TokenKind::MultiLineComment))
+        .map(|t| t.pos)
+        .collect::<Vec<_>>()
+    })
+    .unwrap_or_default();
+  let mut start = 0;
+  for line in member.split_inclusive('\n') {
+    if !literals
+      .iter()
+      .any(|pos| pos.start < start && start < pos.end)
+    {
+      text.push_str(&indent_str);
+    }
+    text.push_str(line);
+    start += line.len();
+  }
+  Ok(())
+}
+
+fn write_comment_lines(
+  text: &mut String,
+  comments: &[(CommentPlace, String)],
+  place: CommentPlace,
+  indent: usize,
+) -> std::fmt::Result {
+  let indent_str = "  ".repeat(indent);
+  comments
+    .iter()
+    .filter(|(p, _)| *p == place)
+    .try_for_each(|(_, comment)| writeln!(text, "{}{}", indent_str, comment))
+}
+
+fn write_comments_inline(
+  text: &mut String,
+  comments: &[(CommentPlace, String)],
+  place: CommentPlace,
+) -> std::fmt::Result {
+  comments
+    .iter()
+    .filter(|(p, _)| *p == place)
+    .try_for_each(|(_, comment)| write!(text, " {}", comment))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BASE: &str = r#"title "t"
+page #overview {
+  label: "Overview"
+  widget kpi #sales {
+    label: "Sales"
+    size: small
+  }
+}
+page #details {
+  widget kpi #cost {
+    label: "Cost"
+  }
+}
+"#;
+
+  #[test]
+  fn merges_changes_to_different_properties() {
+    let ours = BASE.replace("label: \"Sales\"", "label: \"Revenue\"");
+    let theirs = BASE.replace("size: small", "size: large");
+    let result = merge_text(BASE, &ours, &theirs).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(
+      BASE
+        .replace("label: \"Sales\"", "label: \"Revenue\"")
+        .replace("size: small", "size: large"),
+      result.text
+    );
+  }
+
+  #[test]
+  fn merges_added_and_removed_entities() {
+    let ours = BASE.replace(
+      "page #details {\n",
+      "page #details {\n  widget kpi #margin {\n    label: \"Margin\"\n  }\n",
+    );
+    let theirs = BASE.replace("  widget kpi #cost {\n    label: \"Cost\"\n  }\n", "");
+    let result = merge_text(BASE, &ours, &theirs).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(
+      BASE.replace(
+        "  widget kpi #cost {\n    label: \"Cost\"\n  }\n",
+        "  widget kpi #margin {\n    label: \"Margin\"\n  }\n",
+      ),
+      result.text
+    );
+  }
+
+  #[test]
+  fn same_property_changed_on_both_sides_conflicts() {
+    let ours = BASE.replace("size: small", "size: medium");
+    let theirs = BASE.replace("size: small", "size: large");
+    let result = merge_text(BASE, &ours, &theirs).unwrap();
+    assert_eq!(
+      vec![Conflict {
+        kind: ConflictKind::Property,
+        entity: "#sales".to_string(),
+        property: Some("size".to_string()),
+      }],
+      result.conflicts
+    );
+    assert!(result
+      .text
+      .contains("<<<<<<< ours\n    size: medium\n=======\n    size: large\n>>>>>>> theirs\n"));
+  }
+
+  #[test]
+  fn same_change_on_both_sides_is_clean() {
+    let changed = BASE.replace("size: small", "size: large");
+    let result = merge_text(BASE, &changed, &changed).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(changed, result.text);
+  }
+
+  #[test]
+  fn removed_entity_changed_on_other_side_conflicts() {
+    let ours = BASE.replace("label: \"Cost\"", "label: \"Costs\"");
+    let theirs = BASE.replace("  widget kpi #cost {\n    label: \"Cost\"\n  }\n", "");
+    let result = merge_text(BASE, &ours, &theirs).unwrap();
+    assert_eq!(ConflictKind::Entity, result.conflicts[0].kind);
+    assert!(result.text.contains(
+      "<<<<<<< ours\n  widget kpi #cost {\n    label: \"Costs\"\n  }\n=======\n>>>>>>> theirs\n"
+    ));
+  }
+
+  #[test]
+  fn follows_entity_moved_on_one_side() {
+    let ours = BASE.replace("size: small", "size: large");
+    let theirs = r#"title "t"
+page #overview {
+  label: "Overview"
+}
+page #details {
+  widget kpi #cost {
+    label: "Cost"
+  }
+  widget kpi #sales {
+    label: "Sales"
+    size: small
+  }
+}
+"#;
+    let result = merge_text(BASE, &ours, theirs).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(theirs.replace("size: small", "size: large"), result.text);
+  }
+
+  const WITH_COMMENTS: &str = r#"// sales overview
+title "t"
+const limit = 10
+page #overview {
+  // the hub the data comes from
+  config hub #hub { // first hub
+    hub: 1
+    table sales = ds.sales
+  }
+  widget kpi #sales {
+    label: "Sales" // shown on top
+  } // end of sales
+}
+// end of script
+"#;
+
+  #[test]
+  fn unchanged_script_keeps_aliases_consts_and_comments() {
+    let result = merge_text(WITH_COMMENTS, WITH_COMMENTS, WITH_COMMENTS).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(WITH_COMMENTS, result.text);
+  }
+
+  #[test]
+  fn merges_aliases_consts_and_comments() {
+    let ours = WITH_COMMENTS
+      .replace("const limit = 10", "const limit = 20")
+      .replace("// shown on top", "// shown first");
+    let theirs = WITH_COMMENTS.replace("table sales = ds.sales", "table sales = ds.revenue");
+    let result = merge_text(WITH_COMMENTS, &ours, &theirs).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(
+      ours.replace("table sales = ds.sales", "table sales = ds.revenue"),
+      result.text
+    );
+  }
+
+  #[test]
+  fn same_const_changed_on_both_sides_conflicts() {
+    let ours = WITH_COMMENTS.replace("const limit = 10", "const limit = 20");
+    let theirs = WITH_COMMENTS.replace("const limit = 10", "const limit = 30");
+    let result = merge_text(WITH_COMMENTS, &ours, &theirs).unwrap();
+    assert_eq!(
+      vec![Conflict {
+        kind: ConflictKind::Const,
+        entity: ROOT.to_string(),
+        property: Some("limit".to_string()),
+      }],
+      result.conflicts
+    );
+    assert!(result
+      .text
+      .contains("<<<<<<< ours\nconst limit = 20\n=======\nconst limit = 30\n>>>>>>> theirs\n"));
+  }
+
+  const DUPLICATE_IDENTS: &str = r#"page #p {
+  widget kpi #k {
+    label: "P"
+  }
+}
+page #q {
+  widget kpi #k {
+    label: "Q"
+  }
+  widget kpi #k {
+    label: "Q2"
+  }
+}
+"#;
+
+  #[test]
+  fn unchanged_script_with_duplicate_idents_is_kept() {
+    let result = merge_text(DUPLICATE_IDENTS, DUPLICATE_IDENTS, DUPLICATE_IDENTS).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(DUPLICATE_IDENTS, result.text);
+  }
+
+  #[test]
+  fn merges_changes_to_entities_with_duplicate_idents() {
+    let ours = DUPLICATE_IDENTS.replace("\"P\"", "\"P1\"");
+    let theirs = DUPLICATE_IDENTS.replace("\"Q2\"", "\"Q3\"");
+    let result = merge_text(DUPLICATE_IDENTS, &ours, &theirs).unwrap();
+    assert!(result.is_clean());
+    assert_eq!(ours.replace("\"Q2\"", "\"Q3\""), result.text);
+  }
+
+  #[test]
+  fn unchanged_test_scripts_are_kept() {
+    for script in [
+      include_str!("../../../test_script/test.cdl"),
+      include_str!("../../../test_script/large.cdl"),
+      include_str!("../../../test_script/canvas_example.cdl"),
+      include_str!("../../../test_script/workforce.cdl"),
+    ] {
+      let cst = parse_cst(script).unwrap();
+      let result = merge(&cst, &cst, &cst).unwrap();
+      assert!(result.is_clean());
+      let merged = parse_cst(&result.text).unwrap();
+      assert!(crate::diff(cst.ast(), merged.ast()).unwrap().is_empty());
+      // the merged text is already in the canonical format
+      assert_eq!(result.text, merge(&merged, &merged, &merged).unwrap().text);
+    }
+  }
+
+  #[test]
+  fn merge_order_keeps_insertions_from_both_sides() {
+    assert_eq!(
+      vec!["a", "x", "b", "y", "c"],
+      merge_order(&["a", "x", "b", "c"], &["a", "b", "y", "c"])
+    );
+  }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{po, Source};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+  pub key: String,
+  pub source: String,
+  /// Empty until translated.
+  #[serde(default)]
+  pub translation: String,
+  /// Line of the source text when extracted, for translators.
+  #[serde(default)]
+  pub line: usize,
+}
+
+/// Translatable strings of a script, with their translations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Catalogue {
+  pub messages: Vec<Message>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// gettext PO, with the key as `msgctxt`.
+  Po,
+  Json,
+}
+
+impl Format {
+  /// `.json` files are json, everything else PO.
+  pub fn from_path(path: &str) -> Format {
+    if path.to_ascii_lowercase().ends_with(".json") {
+      Format::Json
+    } else {
+      Format::Po
+    }
+  }
+}
+
+impl Catalogue {
+  /// An untranslated catalogue.
+  pub fn from_sources(sources: &[Source]) -> Catalogue {
+    Catalogue {
+      messages: sources
+        .iter()
+        .map(|source| Message {
+          key: source.key.clone(),
+          source: source.text.clone(),
+          translation: String::new(),
+          line: source.line,
+        })
+        .collect(),
+    }
+  }
+
+  pub fn get(&self, key: &str) -> Option<&Message> {
+    self.messages.iter().find(|m| m.key == key)
+  }
+
+  pub fn read(text: &str, format: Format) -> Result<Catalogue> {
+    match format {
+      Format::Po => po::read(text),
+      Format::Json => serde_json::from_str(text).context("Could not read json catalogue"),
+    }
+  }
+
+  pub fn write(&self, format: Format) -> Result<String> {
+    match format {
+      Format::Po => Ok(po::write(self)),
+      Format::Json => Ok(serde_json::to_string_pretty(self)?),
+    }
+  }
+}
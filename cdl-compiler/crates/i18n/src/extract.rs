@@ -0,0 +1,213 @@
+use std::{
+  collections::{HashMap, HashSet},
+  ops::Range,
+};
+
+use anyhow::Result;
+use ast::{Ast, Node, NodeRef};
+use lexer::unescape_string;
+use parser::parse_text;
+
+/// Properties holding user facing text.
+const TRANSLATABLE: [&str; 2] = ["label", "title"];
+
+/// A translatable string found in a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+  /// `#ident` of the closest entity with one, followed by the path of entity types below it and
+  /// the property, like `#sales/widget kpi[1].label`. Titles are `title`, `title[1]` and so on.
+  pub key: String,
+  pub text: String,
+  pub line: usize,
+  /// The string literal in the script, quotes included.
+  pub range: Range<usize>,
+}
+
+/// Every translatable string in `text`, in source order. Only plain string values are
+/// translatable, labels built from references or expressions are left out.
+pub fn extract(text: &str) -> Result<Vec<Source>> {
+  let ast = parse_text(text)?;
+  extract_from_ast(&ast)
+}
+
+pub fn extract_from_ast(ast: &Ast) -> Result<Vec<Source>> {
+  let mut extractor = Extractor {
+    ast,
+    sources: vec![],
+    keys: HashSet::new(),
+  };
+  extractor.script()?;
+  Ok(extractor.sources)
+}
+
+struct Extractor<'a> {
+  ast: &'a Ast,
+  sources: Vec<Source>,
+  keys: HashSet<String>,
+}
+
+impl Extractor<'_> {
+  fn script(&mut self) -> Result<()> {
+    let Some(script) = self.ast.get_node(self.ast.script_entity) else {
+      return Ok(());
+    };
+    let mut titles = 0;
+    for child_ref in script.node_data.children() {
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      if let Node::Title(title) = &child.node_data {
+        let key = indexed("title", titles);
+        titles += 1;
+        // the node covers `title "..."`, the literal is at the end
+        let pos = self.ast.get_pos_for_node(child_ref);
+        let range = pos.end - title.title.as_str().len()..pos.end;
+        self.push(key, title.title.as_str(), child_ref, range)?;
+      }
+    }
+    self.children(self.ast.script_entity, "")
+  }
+
+  fn children(&mut self, node_ref: NodeRef, key: &str) -> Result<()> {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return Ok(());
+    };
+    let mut properties: HashMap<String, usize> = HashMap::new();
+    let mut entities: HashMap<String, usize> = HashMap::new();
+    for child_ref in node.node_data.children() {
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      match &child.node_data {
+        Node::Property(property) => {
+          let name = property.name.to_string();
+          let count = properties.entry(name.clone()).or_default();
+          let property_key = format!("{}.{}", key, indexed(&name, *count));
+          *count += 1;
+          if !TRANSLATABLE.contains(&name.as_str()) {
+            continue;
+          }
+          let values = property.children.borrow();
+          let [value_ref] = values.as_slice() else {
+            continue;
+          };
+          let Some(value) = self.ast.get_node(*value_ref) else {
+            continue;
+          };
+          if let Node::String(string) = &value.node_data {
+            let range = self.ast.get_pos_for_node(*value_ref);
+            self.push(property_key, string.text.as_str(), *value_ref, range)?;
+          }
+        }
+        Node::Entity(entity) => {
+          let child_key = match &entity.ident {
+            Some(ident) => format!("#{}", ident),
+            None => {
+              let terms = entity
+                .terms
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+              let count = entities.entry(terms.clone()).or_default();
+              let child_key = format!("{}/{}", key, indexed(&terms, *count));
+              *count += 1;
+              child_key
+            }
+          };
+          self.children(child_ref, &child_key)?;
+        }
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  fn push(
+    &mut self,
+    mut key: String,
+    raw: &str,
+    node_ref: NodeRef,
+    range: Range<usize>,
+  ) -> Result<()> {
+    // idents only have to be unique when referenced, later duplicates get a suffix
+    let base = key.clone();
+    let mut index = 2;
+    while self.keys.contains(&key) {
+      key = format!("{}~{}", base, index);
+      index += 1;
+    }
+    self.keys.insert(key.clone());
+    self.sources.push(Source {
+      key,
+      text: unescape_string(raw)?,
+      line: self
+        .ast
+        .get_location_for_node(node_ref)
+        .map(|l| l.start_line)
+        .unwrap_or_default(),
+      range,
+    });
+    Ok(())
+  }
+}
+
+fn indexed(name: &str, index: usize) -> String {
+  match index {
+    0 => name.to_string(),
+    _ => format!("{}[{}]", name, index),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extracts_labels_with_stable_keys() {
+    let text = r#"title "Sales"
+title 'Second'
+page #sales {
+  label: "Sales \"overview\""
+  widget kpi {
+    label: "First"
+    title: "Heading"
+  }
+  widget kpi {
+    label: "Second"
+    label: "Again"
+    size: "small"
+  }
+  widget kpi #dup {
+    label: @sales.label
+  }
+}
+page {
+  widget kpi #dup {
+    label: "Dup"
+  }
+}
+"#;
+    let sources = extract(text).unwrap();
+    let keys = sources
+      .iter()
+      .map(|s| (s.key.as_str(), s.text.as_str()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        ("title", "Sales"),
+        ("title[1]", "Second"),
+        ("#sales.label", "Sales \"overview\""),
+        ("#sales/widget kpi.label", "First"),
+        ("#sales/widget kpi.title", "Heading"),
+        ("#sales/widget kpi[1].label", "Second"),
+        ("#sales/widget kpi[1].label[1]", "Again"),
+        ("#dup.label", "Dup"),
+      ],
+      keys
+    );
+    assert_eq!(4, sources[2].line);
+    assert_eq!(r#""Sales \"overview\"""#, &text[sources[2].range.clone()]);
+    assert_eq!("'Second'", &text[sources[1].range.clone()]);
+  }
+}
@@ -0,0 +1,8 @@
+mod catalogue;
+mod extract;
+mod localise;
+mod po;
+
+pub use catalogue::*;
+pub use extract::*;
+pub use localise::*;
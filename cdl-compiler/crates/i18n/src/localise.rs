@@ -0,0 +1,146 @@
+use std::{collections::HashSet, fmt};
+
+use anyhow::Result;
+use lexer::escape_string;
+use serde::Serialize;
+
+use crate::{extract, Catalogue};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Localised {
+  pub text: String,
+  /// Keys in the script without a translation, their text is kept as is.
+  pub missing: Vec<String>,
+  /// Keys in the catalogue that are gone from the script, or whose source text has changed since
+  /// it was extracted. Changed texts are kept as is.
+  pub stale: Vec<String>,
+}
+
+impl Localised {
+  pub fn is_complete(&self) -> bool {
+    self.missing.is_empty() && self.stale.is_empty()
+  }
+}
+
+impl fmt::Display for Localised {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for key in &self.missing {
+      writeln!(f, "missing translation for {}", key)?;
+    }
+    for key in &self.stale {
+      writeln!(f, "stale translation for {}", key)?;
+    }
+    Ok(())
+  }
+}
+
+/// Replaces the translatable strings in `text` with their translations from `catalogue`. Only the
+/// string literals are touched, formatting and comments are kept.
+pub fn localise(text: &str, catalogue: &Catalogue) -> Result<Localised> {
+  let sources = extract(text)?;
+  let mut missing = vec![];
+  let mut stale = vec![];
+  let mut replacements = vec![];
+  for source in &sources {
+    match catalogue.get(&source.key) {
+      Some(message) if message.source != source.text => stale.push(source.key.clone()),
+      Some(message) if !message.translation.is_empty() => {
+        replacements.push((source.range.clone(), escape_string(&message.translation)))
+      }
+      _ => missing.push(source.key.clone()),
+    }
+  }
+  let keys = sources
+    .iter()
+    .map(|s| s.key.as_str())
+    .collect::<HashSet<_>>();
+  stale.extend(
+    catalogue
+      .messages
+      .iter()
+      .filter(|m| !keys.contains(m.key.as_str()))
+      .map(|m| m.key.clone()),
+  );
+
+  let mut localised = text.to_string();
+  for (range, literal) in replacements.into_iter().rev() {
+    localised.replace_range(range, &literal);
+  }
+  Ok(Localised {
+    text: localised,
+    missing,
+    stale,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Format, Message};
+
+  const SCRIPT: &str = r#"title "Sales"
+page #sales {
+  label: "Sales" // keep this comment
+  widget kpi {
+    label: 'Revenue'
+    title: "Old heading"
+  }
+}
+"#;
+
+  fn translated(key: &str, source: &str, translation: &str) -> Message {
+    Message {
+      key: key.to_string(),
+      source: source.to_string(),
+      translation: translation.to_string(),
+      line: 0,
+    }
+  }
+
+  #[test]
+  fn injects_translations() {
+    let catalogue = Catalogue {
+      messages: vec![
+        translated("title", "Sales", "Salg"),
+        translated("#sales.label", "Sales", "Salg \"totalt\""),
+        translated("#sales/widget kpi.label", "Revenue", ""),
+        translated("#sales/widget kpi.title", "Heading", "Overskrift"),
+        translated("#gone.label", "Gone", "Borte"),
+      ],
+    };
+    let localised = localise(SCRIPT, &catalogue).unwrap();
+    assert_eq!(
+      r#"title "Salg"
+page #sales {
+  label: "Salg \"totalt\"" // keep this comment
+  widget kpi {
+    label: 'Revenue'
+    title: "Old heading"
+  }
+}
+"#,
+      localised.text
+    );
+    assert_eq!(vec!["#sales/widget kpi.label"], localised.missing);
+    assert_eq!(
+      vec!["#sales/widget kpi.title", "#gone.label"],
+      localised.stale
+    );
+    assert!(parser::parse_text(&localised.text).is_ok());
+  }
+
+  #[test]
+  fn extracted_catalogue_round_trips() {
+    let catalogue = Catalogue::from_sources(&extract(SCRIPT).unwrap());
+    for format in [Format::Po, Format::Json] {
+      let mut read = Catalogue::read(&catalogue.write(format).unwrap(), format).unwrap();
+      assert_eq!(catalogue, read);
+      for message in &mut read.messages {
+        message.translation = message.source.to_uppercase();
+      }
+      let localised = localise(SCRIPT, &read).unwrap();
+      assert!(localised.is_complete());
+      assert!(localised.text.contains("label: \"REVENUE\""));
+    }
+  }
+}
@@ -0,0 +1,205 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{Catalogue, Message};
+
+const HEADER: &str = r#"msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+"#;
+
+pub(crate) fn write(catalogue: &Catalogue) -> String {
+  let mut po = String::from(HEADER);
+  for message in &catalogue.messages {
+    po.push('\n');
+    if message.line > 0 {
+      let _ = writeln!(po, "#. line {}", message.line);
+    }
+    let _ = writeln!(po, "msgctxt {}", quote(&message.key));
+    let _ = writeln!(po, "msgid {}", quote(&message.source));
+    let _ = writeln!(po, "msgstr {}", quote(&message.translation));
+  }
+  po
+}
+
+fn quote(text: &str) -> String {
+  let mut quoted = String::with_capacity(text.len() + 2);
+  quoted.push('"');
+  for c in text.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\t' => quoted.push_str("\\t"),
+      '\r' => quoted.push_str("\\r"),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+fn unquote(text: &str) -> Result<String> {
+  let inner = text
+    .strip_prefix('"')
+    .and_then(|t| t.strip_suffix('"'))
+    .ok_or_else(|| anyhow!("Expected a quoted string, found {}", text))?;
+  let mut value = String::with_capacity(inner.len());
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      value.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => value.push('\n'),
+      Some('t') => value.push('\t'),
+      Some('r') => value.push('\r'),
+      Some(other) => value.push(other),
+      None => bail!("Unterminated escape in {}", text),
+    }
+  }
+  Ok(value)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+  Context,
+  Id,
+  Str,
+}
+
+/// Reads the entries with a `msgctxt`, the header and entries without one are skipped. Obsolete
+/// `#~` entries count as comments.
+pub(crate) fn read(text: &str) -> Result<Catalogue> {
+  let mut messages = vec![];
+  let mut entry: Option<(Message, Field)> = None;
+  let mut line_number = 0;
+  let finish = |entry: Option<(Message, Field)>, messages: &mut Vec<Message>| {
+    if let Some((message, _)) = entry {
+      if !message.key.is_empty() {
+        messages.push(message);
+      }
+    }
+  };
+  for (index, line) in text.lines().enumerate() {
+    let line = line.trim();
+    let context = || format!("line {}: {}", index + 1, line);
+    if line.is_empty() {
+      continue;
+    }
+    if let Some(comment) = line.strip_prefix("#.") {
+      if let Some(number) = comment.trim().strip_prefix("line ") {
+        line_number = number.trim().parse().unwrap_or_default();
+      }
+      continue;
+    }
+    if line.starts_with('#') {
+      continue;
+    }
+    let (field, value) = if let Some(value) = line.strip_prefix("msgctxt ") {
+      (Some(Field::Context), value)
+    } else if let Some(value) = line.strip_prefix("msgid ") {
+      (Some(Field::Id), value)
+    } else if let Some(value) = line.strip_prefix("msgstr ") {
+      (Some(Field::Str), value)
+    } else {
+      (None, line)
+    };
+    let value = unquote(value.trim()).map_err(|e| anyhow!("{}, {}", context(), e))?;
+    let field = match field {
+      Some(field) => {
+        // a new entry starts at msgctxt, or at msgid when it has no context
+        let starts_entry = match &entry {
+          None => true,
+          Some((_, current)) => {
+            field == Field::Context || (field == Field::Id && *current != Field::Context)
+          }
+        };
+        if starts_entry {
+          finish(entry.take(), &mut messages);
+          entry = Some((
+            Message {
+              line: line_number,
+              ..Default::default()
+            },
+            field,
+          ));
+          line_number = 0;
+        }
+        field
+      }
+      None => match &entry {
+        Some((_, current)) => *current,
+        None => bail!("{}, string outside of an entry", context()),
+      },
+    };
+    let (message, current) = entry.as_mut().unwrap();
+    *current = field;
+    match field {
+      Field::Context => message.key.push_str(&value),
+      Field::Id => message.source.push_str(&value),
+      Field::Str => message.translation.push_str(&value),
+    }
+  }
+  finish(entry, &mut messages);
+  Ok(Catalogue { messages })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn po_round_trips() {
+    let catalogue = Catalogue {
+      messages: vec![
+        Message {
+          key: "#sales.label".to_string(),
+          source: "Sales \"overview\"\nand more".to_string(),
+          translation: "Salg".to_string(),
+          line: 4,
+        },
+        Message {
+          key: "title".to_string(),
+          source: "Sales".to_string(),
+          translation: String::new(),
+          line: 1,
+        },
+      ],
+    };
+    let po = write(&catalogue);
+    assert!(po.contains("#. line 4\nmsgctxt \"#sales.label\"\nmsgid \"Sales \\\"overview\\\"\\nand more\"\nmsgstr \"Salg\"\n"));
+    assert_eq!(catalogue, read(&po).unwrap());
+  }
+
+  #[test]
+  fn reads_multiline_strings_and_skips_header() {
+    let po = r#"# translator comment
+msgid ""
+msgstr ""
+"Language: nb\n"
+
+#: somewhere
+msgctxt "page.label"
+msgid ""
+"Long "
+"text"
+msgstr "Lang "
+"tekst"
+"#;
+    let catalogue = read(po).unwrap();
+    assert_eq!(1, catalogue.messages.len());
+    let message = &catalogue.messages[0];
+    assert_eq!("page.label", message.key);
+    assert_eq!("Long text", message.source);
+    assert_eq!("Lang tekst", message.translation);
+  }
+
+  #[test]
+  fn bad_strings_give_errors() {
+    let error = read("msgctxt \"a\"\nmsgid oops\n").unwrap_err();
+    assert!(error.to_string().starts_with("line 2: msgid oops"));
+  }
+}
@@ -0,0 +1,66 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use lexer::{into_owned_tokens, lex, lex_borrowed};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations<T>(f: impl FnOnce() -> T) -> usize {
+  let before = ALLOCATIONS.load(Ordering::Relaxed);
+  black_box(f());
+  ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+// "borrowed" measures lexing alone. The parser needs owned tokens, so "borrowed + interned"
+// is the cost to compare with "owned" when the tokens are parsed afterwards; see the parser
+// benches for parse_tokens end to end.
+fn criterion_benchmark(c: &mut Criterion) {
+  let file = include_str!("../../../test_script/test.cdl");
+
+  println!(
+    "allocations lex:           {}",
+    count_allocations(|| lex(file))
+  );
+  println!(
+    "allocations lex_borrowed:  {}",
+    count_allocations(|| lex_borrowed(file))
+  );
+  println!(
+    "allocations interned:      {}",
+    count_allocations(|| into_owned_tokens(lex_borrowed(file).unwrap()))
+  );
+
+  let mut group = c.benchmark_group("lex test.cdl");
+  group.throughput(Throughput::Bytes(file.len() as u64));
+  group.bench_function("owned", |b| b.iter(|| lex(black_box(file))));
+  group.bench_function("borrowed", |b| b.iter(|| lex_borrowed(black_box(file))));
+  group.bench_function("borrowed + interned", |b| {
+    b.iter(|| into_owned_tokens(lex_borrowed(black_box(file)).unwrap()))
+  });
+  group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
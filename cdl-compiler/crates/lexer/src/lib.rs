@@ -1,13 +1,23 @@
-use anyhow::anyhow;
-use anyhow::Result;
+mod limits;
+mod line_index;
+mod stream;
+mod unescape;
+
+use anyhow::{bail, Result};
 use logos::Lexer;
 use logos::Logos;
 use logos::Span;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::rc::Rc;
 
-#[derive(Debug,Clone,Serialize,PartialEq,Eq,Hash)]
+pub use limits::*;
+pub use line_index::*;
+pub use stream::*;
+pub use unescape::*;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
 pub struct LexedStr(pub Rc<str>);
 
 impl From<&str> for LexedStr {
@@ -17,9 +27,9 @@ impl From<&str> for LexedStr {
 }
 
 impl Display for LexedStr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}", self.0)
-    }
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
 }
 
 impl LexedStr {
@@ -28,19 +38,18 @@ impl LexedStr {
   }
 }
 
-fn to_rcstr(lex: &mut Lexer<TokenLexer>) -> LexedStr {
+fn to_percentage<'src>(lex: &mut Lexer<'src, TokenLexer<'src>>) -> f64 {
   let slice = lex.slice();
-  slice.into()
+  slice[..slice.len() - 1].parse::<f64>().unwrap() / 100.0
 }
 
-fn to_rcstr_skip1(lex: &mut Lexer<TokenLexer>) -> LexedStr {
-  let slice = &lex.slice()[1..];
-  slice.into()
+fn skip1<'src>(lex: &mut Lexer<'src, TokenLexer<'src>>) -> &'src str {
+  &lex.slice()[1..]
 }
 
 #[derive(Logos, Debug, PartialEq)]
 #[logos(skip r"[ \t\f]+")] // Ignore this regex pattern between tokens
-enum TokenLexer {
+enum TokenLexer<'src> {
   #[token("false", |_| false)]
   #[token("true", |_| true)]
   Bool(bool),
@@ -106,30 +115,36 @@ enum TokenLexer {
   #[regex(r"-?(?:0|[1-9]\d*)(?:\.\d+)?(?:[eE][+-]?\d+)?", |lex| lex.slice().parse::<f64>().unwrap())]
   Number(f64),
 
-  #[regex(r#""(?:[^"]|\\")*""#, to_rcstr)]
-  #[regex(r#"'(?:[^']|\\')*'"#, to_rcstr)]
-  String(LexedStr),
+  #[regex(r"-?(?:0|[1-9]\d*)(?:\.\d+)?%", to_percentage)]
+  Percentage(f64),
 
-  #[regex("_?[$a-zA-Z0-9_\\-\\.]*", to_rcstr)]
-  Identifier(LexedStr),
+  #[regex(r#""(?:[^"\\]|\\(?:.|\n))*""#, |lex| lex.slice())]
+  #[regex(r#"'(?:[^'\\]|\\(?:.|\n))*'"#, |lex| lex.slice())]
+  #[regex(r#""""(?:[^"]|"[^"]|""[^"])*""""#, |lex| lex.slice())]
+  #[regex(r#"r"[^"]*""#, |lex| lex.slice())]
+  #[regex(r#"r'[^']*'"#, |lex| lex.slice())]
+  String(&'src str),
 
-  #[regex("@[a-zA-Z0-9_\\-\\.]*", to_rcstr_skip1)]
-  Reference(LexedStr),
+  #[regex("_?[$a-zA-Z0-9_\\-\\.]*", |lex| lex.slice())]
+  Identifier(&'src str),
 
-  #[regex("\\^[a-zA-Z0-9_\\-\\.]*", to_rcstr_skip1)]
-  HierarchyReference(LexedStr),
+  #[regex("@[a-zA-Z0-9_\\-\\.]*", skip1)]
+  Reference(&'src str),
 
-  #[regex("#[0-9a-fA-F]{6}", to_rcstr_skip1)]
-  Color(LexedStr),
+  #[regex("\\^[a-zA-Z0-9_\\-\\.]*", skip1)]
+  HierarchyReference(&'src str),
 
-  #[regex("//[^\n]*", to_rcstr)]
-  LineComment(LexedStr),
+  #[regex("#[0-9a-fA-F]{6}(?:[0-9a-fA-F]{2})?", skip1)]
+  Color(&'src str),
 
-  #[regex(r#"/\*(?:[^*]|\*[^/])*\*/"#, to_rcstr)]
-  MultiLineComment(LexedStr),
+  #[regex("//[^\n]*", |lex| lex.slice())]
+  LineComment(&'src str),
+
+  #[regex(r#"/\*(?:[^*]|\*[^/])*\*/"#, |lex| lex.slice())]
+  MultiLineComment(&'src str),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
   Boolean(bool),
   EOL,
@@ -142,6 +157,7 @@ pub enum TokenKind {
   Colon,
   Comma,
   Number(f64),
+  Percentage(f64),
   String,
   Plus,
   Minus,
@@ -165,14 +181,14 @@ pub enum TokenKind {
   MultiLineComment,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
   pub kind: TokenKind,
   pub pos: Span,
   pub text: Option<LexedStr>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
 pub struct Location {
   pub start_line: usize,
   pub start_pos: usize,
@@ -180,206 +196,155 @@ pub struct Location {
   pub end_pos: usize,
 }
 
-pub fn get_location_from_position(text: &str, position: &Span) -> Location {
-  let mut location = Location::default();
-  let mut line_number = 1;
-  let mut line_pos = 1;
-  for (curr_pos, char) in text.chars().enumerate() {
-    if curr_pos == position.start {
-      location.start_line = line_number;
-      location.start_pos = line_pos;
-    }
-    if curr_pos == position.end {
-      location.end_line = line_number;
-      location.end_pos = line_pos;
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LexError {
+  pub message: String,
+  pub pos: Span,
+  pub location: Location,
+}
+
+impl LexError {
+  fn new(text: &str, pos: Span, message: String) -> LexError {
+    let location = LineIndex::new(text).location(&pos);
+    LexError {
+      message,
+      pos,
+      location,
     }
-    if char == '\n' {
-      line_number += 1;
-      line_pos = 0;
+  }
+}
+
+impl Display for LexError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "[{}:{}]: {}",
+      self.location.start_line, self.location.start_pos, self.message
+    )
+  }
+}
+
+impl std::error::Error for LexError {}
+
+/// Token borrowing its text from the lexed input. Use [`BorrowedToken::to_token`] or
+/// [`into_owned_tokens`] when the text has to outlive the input. The parser only takes owned
+/// tokens, so parsing from these still pays for that conversion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorrowedToken<'src> {
+  pub kind: TokenKind,
+  pub pos: Span,
+  pub text: Option<&'src str>,
+}
+
+impl<'src> BorrowedToken<'src> {
+  pub fn to_token(&self) -> Token {
+    Token {
+      kind: self.kind.clone(),
+      pos: self.pos.clone(),
+      text: self.text.map(|t| t.into()),
     }
-    line_pos += 1;
   }
-  location
+}
+
+/// Converts borrowed tokens to owned ones, sharing one allocation between tokens with the same text.
+pub fn into_owned_tokens(tokens: Vec<BorrowedToken<'_>>) -> Vec<Token> {
+  let mut interned: HashMap<&str, LexedStr> = HashMap::new();
+  tokens
+    .into_iter()
+    .map(|token| Token {
+      text: token
+        .text
+        .map(|t| interned.entry(t).or_insert_with(|| t.into()).clone()),
+      kind: token.kind,
+      pos: token.pos,
+    })
+    .collect()
 }
 
 #[tracing::instrument(name = "lexer")]
 pub fn lex(text: &str) -> Result<Vec<Token>> {
+  lex_with_max_tokens(text, usize::MAX)
+}
+
+/// Like [`lex`], failing with [`LimitExceeded`] as soon as the input goes over `max_tokens`
+/// tokens, before the rest of it is lexed.
+#[tracing::instrument(name = "lexer_with_max_tokens", skip(text))]
+pub fn lex_with_max_tokens(text: &str, max_tokens: usize) -> Result<Vec<Token>> {
+  Ok(
+    lex_tokens(text, max_tokens)?
+      .iter()
+      .map(BorrowedToken::to_token)
+      .collect(),
+  )
+}
+
+/// Same as [`lex`], but without copying any text out of the input.
+#[tracing::instrument(name = "lexer_borrowed", skip(text))]
+pub fn lex_borrowed(text: &str) -> Result<Vec<BorrowedToken<'_>>> {
+  lex_tokens(text, usize::MAX)
+}
+
+fn lex_tokens(text: &str, max_tokens: usize) -> Result<Vec<BorrowedToken<'_>>> {
   let mut lexer = TokenLexer::lexer(text);
-  let mut tokens: Vec<Token> = vec![];
+  let mut tokens: Vec<BorrowedToken> = vec![];
 
   while let Some(lex_result) = lexer.next() {
+    if tokens.len() == max_tokens {
+      bail!(LimitExceeded {
+        limit: Limit::Tokens,
+        max: max_tokens,
+      });
+    }
     if lex_result.is_err() {
-      let location = get_location_from_position(text, &lexer.span());
-      return Err(anyhow!(format!(
-        "[{}:{}]: Unknown token \"{}\"",
-        location.start_line,
-        location.end_line,
-        lexer.slice(),
-      )));
+      let message = format!("Unknown token \"{}\"", lexer.slice());
+      return Err(LexError::new(text, lexer.span(), message).into());
     }
-    let token = lex_result.unwrap();
-    let span = lexer.span();
-    tokens.push(match token {
-      TokenLexer::Bool(b) => Token {
-        kind: TokenKind::Boolean(b),
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Eol => Token {
-        kind: TokenKind::EOL,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::BraceOpen => Token {
-        kind: TokenKind::BraceOpen,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::BraceClose => Token {
-        kind: TokenKind::BraceClose,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::BracketOpen => Token {
-        kind: TokenKind::BracketOpen,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::BracketClose => Token {
-        kind: TokenKind::BracketClose,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::ParenOpen => Token {
-        kind: TokenKind::ParenOpen,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::ParenClose => Token {
-        kind: TokenKind::ParenClose,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Colon => Token {
-        kind: TokenKind::Colon,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Comma => Token {
-        kind: TokenKind::Comma,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Plus => Token {
-        kind: TokenKind::Plus,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Minus => Token {
-        kind: TokenKind::Minus,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Div => Token {
-        kind: TokenKind::Div,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Mul => Token {
-        kind: TokenKind::Mul,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Hash => Token {
-        kind: TokenKind::Hash,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Percent => Token {
-        kind: TokenKind::Percent,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Equal => Token {
-        kind: TokenKind::Equal,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::NotEqual => Token {
-        kind: TokenKind::NotEqual,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::LessThan => Token {
-        kind: TokenKind::LessThan,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::MoreThan => Token {
-        kind: TokenKind::MoreThan,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::LessThanOrEqual => Token {
-        kind: TokenKind::LessThanOrEqual,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::MoreThanOrEqual => Token {
-        kind: TokenKind::MoreThanOrEqual,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::And => Token {
-        kind: TokenKind::And,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Or => Token {
-        kind: TokenKind::Or,
-        pos: span,
-        text: None,
-      },
-      TokenLexer::Number(n) => Token {
-        kind: TokenKind::Number(n),
-        pos: span,
-        text: None,
-      },
-      TokenLexer::String(s) => Token {
-        kind: TokenKind::String,
-        pos: span,
-        text: Some(s.clone()),
-      },
-      TokenLexer::Identifier(i) => Token {
-        kind: TokenKind::Identifier,
-        pos: span,
-        text: Some(i.clone()),
-      },
-      TokenLexer::Reference(r) => Token {
-        kind: TokenKind::Reference,
-        pos: span,
-        text: Some(r.clone()),
-      },
-      TokenLexer::HierarchyReference(r) => Token {
-        kind: TokenKind::HierarchyReference,
-        pos: span,
-        text: Some(r.clone()),
-      },
-      TokenLexer::Color(c) => Token {
-        kind: TokenKind::Color,
-        pos: span,
-        text: Some(c.clone()),
-      },
-      TokenLexer::LineComment(l) => Token {
-        kind: TokenKind::LineComment,
-        pos: span,
-        text: Some(l.clone()),
-      },
-      TokenLexer::MultiLineComment(l) => Token {
-        kind: TokenKind::MultiLineComment,
-        pos: span,
-        text: Some(l.clone()),
-      },
+    let slice = lexer.slice();
+    let (kind, token_text) = match lex_result.unwrap() {
+      TokenLexer::Bool(b) => (TokenKind::Boolean(b), None),
+      TokenLexer::Eol => (TokenKind::EOL, None),
+      TokenLexer::BraceOpen => (TokenKind::BraceOpen, None),
+      TokenLexer::BraceClose => (TokenKind::BraceClose, None),
+      TokenLexer::BracketOpen => (TokenKind::BracketOpen, None),
+      TokenLexer::BracketClose => (TokenKind::BracketClose, None),
+      TokenLexer::ParenOpen => (TokenKind::ParenOpen, None),
+      TokenLexer::ParenClose => (TokenKind::ParenClose, None),
+      TokenLexer::Colon => (TokenKind::Colon, None),
+      TokenLexer::Comma => (TokenKind::Comma, None),
+      TokenLexer::Plus => (TokenKind::Plus, None),
+      TokenLexer::Minus => (TokenKind::Minus, None),
+      TokenLexer::Div => (TokenKind::Div, None),
+      TokenLexer::Mul => (TokenKind::Mul, None),
+      TokenLexer::Hash => (TokenKind::Hash, None),
+      TokenLexer::Percent => (TokenKind::Percent, None),
+      TokenLexer::Equal => (TokenKind::Equal, None),
+      TokenLexer::NotEqual => (TokenKind::NotEqual, None),
+      TokenLexer::LessThan => (TokenKind::LessThan, None),
+      TokenLexer::MoreThan => (TokenKind::MoreThan, None),
+      TokenLexer::LessThanOrEqual => (TokenKind::LessThanOrEqual, None),
+      TokenLexer::MoreThanOrEqual => (TokenKind::MoreThanOrEqual, None),
+      TokenLexer::And => (TokenKind::And, None),
+      TokenLexer::Or => (TokenKind::Or, None),
+      TokenLexer::Number(n) => (TokenKind::Number(n), Some(slice)),
+      TokenLexer::Percentage(n) => (TokenKind::Percentage(n), Some(slice)),
+      TokenLexer::String(s) => {
+        if let Err(err) = unescape_string(s) {
+          let message = format!("Invalid string {}: {}", s, err);
+          return Err(LexError::new(text, lexer.span(), message).into());
+        }
+        (TokenKind::String, Some(s))
+      }
+      TokenLexer::Identifier(i) => (TokenKind::Identifier, Some(i)),
+      TokenLexer::Reference(r) => (TokenKind::Reference, Some(r)),
+      TokenLexer::HierarchyReference(r) => (TokenKind::HierarchyReference, Some(r)),
+      TokenLexer::Color(c) => (TokenKind::Color, Some(c)),
+      TokenLexer::LineComment(l) => (TokenKind::LineComment, Some(l)),
+      TokenLexer::MultiLineComment(l) => (TokenKind::MultiLineComment, Some(l)),
+    };
+    tokens.push(BorrowedToken {
+      kind,
+      pos: lexer.span(),
+      text: token_text,
     });
   }
   Ok(tokens)
@@ -389,6 +354,19 @@ pub fn lex(text: &str) -> Result<Vec<Token>> {
 mod tests {
   use super::*;
 
+  #[test]
+  fn stops_at_the_max_tokens() {
+    assert_eq!(3, lex_with_max_tokens("a b c", 3).unwrap().len());
+    let error = lex_with_max_tokens("a b c", 2).unwrap_err();
+    assert_eq!(
+      Some(&LimitExceeded {
+        limit: Limit::Tokens,
+        max: 2,
+      }),
+      error.downcast_ref::<LimitExceeded>()
+    );
+  }
+
   #[test]
   fn gives_error() {
     let tokens = lex("&&&&");
@@ -397,6 +375,12 @@ mod tests {
     assert_eq!(format!("{}", err), "[1:1]: Unknown token \"&\"");
   }
 
+  #[test]
+  fn error_gives_line_and_column() {
+    let err = lex("foo\n  bar &").unwrap_err();
+    assert_eq!(format!("{}", err), "[2:7]: Unknown token \"&\"");
+  }
+
   #[test]
   fn can_parse_strings() {
     let tokens = lex("\"hello \"");
@@ -547,6 +531,14 @@ mod tests {
     );
   }
 
+  #[test]
+  fn not_is_an_identifier() {
+    let tokens = lex("NOT not notes").unwrap();
+    assert_eq!(TokenKind::Identifier, tokens[0].kind);
+    assert_eq!(TokenKind::Identifier, tokens[1].kind);
+    assert_eq!(TokenKind::Identifier, tokens[2].kind);
+  }
+
   #[test]
   fn can_parse_line_comments() {
     let tokens = lex("// hello comment");
@@ -770,7 +762,7 @@ mod tests {
 
     assert!(tokens.is_ok());
     let res = tokens.unwrap();
-    assert_eq!(147203, res.len());
+    assert_eq!(147131, res.len());
   }
   #[test]
   fn can_parse_number() {
@@ -780,7 +772,7 @@ mod tests {
       tokens[0],
       Token {
         kind: TokenKind::Number(1.0),
-        text: None,
+        text: Some("1".into()),
         pos: 0..1
       }
     );
@@ -788,7 +780,7 @@ mod tests {
       tokens[1],
       Token {
         kind: TokenKind::Number(1.1),
-        text: None,
+        text: Some("1.1".into()),
         pos: 2..5
       }
     );
@@ -796,9 +788,93 @@ mod tests {
       tokens[2],
       Token {
         kind: TokenKind::Number(-3245.2),
-        text: None,
+        text: Some("-3245.2".into()),
         pos: 6..13
       }
     );
   }
+
+  #[test]
+  fn can_parse_percentage() {
+    let tokens = lex("50% -2.5% 10 % 3").unwrap();
+    assert_eq!(
+      tokens[0],
+      Token {
+        kind: TokenKind::Percentage(0.5),
+        text: Some("50%".into()),
+        pos: 0..3
+      }
+    );
+    assert_eq!(TokenKind::Percentage(-0.025), tokens[1].kind);
+    assert_eq!(TokenKind::Number(10.0), tokens[2].kind);
+    assert_eq!(TokenKind::Percent, tokens[3].kind);
+  }
+
+  #[test]
+  fn can_parse_color_with_alpha() {
+    let tokens = lex("#112233ff").unwrap();
+    assert_eq!(
+      tokens[0],
+      Token {
+        kind: TokenKind::Color,
+        text: Some("112233ff".into()),
+        pos: 0..9
+      }
+    );
+  }
+
+  #[test]
+  fn can_parse_escaped_and_raw_strings() {
+    let tokens = lex(r#""a \"b\"" r"C:\temp" 'it\'s'"#).unwrap();
+    let texts = tokens
+      .iter()
+      .map(|t| t.text.as_ref().unwrap().as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(vec![r#""a \"b\"""#, r#"r"C:\temp""#, r"'it\'s'"], texts);
+  }
+
+  #[test]
+  fn can_parse_triple_quoted_strings() {
+    let tokens = lex("\"\"\"say \"hi\"\nbye\"\"\" x").unwrap();
+    assert_eq!(TokenKind::String, tokens[0].kind);
+    assert_eq!(0..18, tokens[0].pos);
+    assert_eq!(TokenKind::Identifier, tokens[1].kind);
+  }
+
+  #[test]
+  fn invalid_escape_gives_error() {
+    let err = lex("foo \"\\u{zz}\"").unwrap_err();
+    assert_eq!(
+      format!("{}", err),
+      "[1:5]: Invalid string \"\\u{zz}\": Invalid unicode escape \\u{"
+    );
+  }
+
+  #[test]
+  fn borrowed_tokens_match_owned_tokens() {
+    let file = include_str!("../../../test_script/test.cdl");
+    let borrowed = lex_borrowed(file).unwrap();
+    let owned = lex(file).unwrap();
+    assert_eq!(owned.len(), borrowed.len());
+    assert!(borrowed.iter().zip(&owned).all(|(b, o)| &b.to_token() == o));
+    assert_eq!(owned, into_owned_tokens(borrowed));
+  }
+
+  #[test]
+  fn borrowed_tokens_point_into_input() {
+    let text = String::from("widget @ref #aabbcc");
+    let tokens = lex_borrowed(&text).unwrap();
+    assert_eq!(Some("widget"), tokens[0].text);
+    assert_eq!(Some("ref"), tokens[1].text);
+    assert_eq!(Some("aabbcc"), tokens[2].text);
+    let input = text.as_bytes().as_ptr_range();
+    assert!(input.contains(&tokens[1].text.unwrap().as_ptr()));
+  }
+
+  #[test]
+  fn owned_tokens_share_repeated_text() {
+    let tokens = into_owned_tokens(lex_borrowed("a b a").unwrap());
+    let first = tokens[0].text.as_ref().unwrap();
+    assert!(Rc::ptr_eq(&first.0, &tokens[2].text.as_ref().unwrap().0));
+  }
 }
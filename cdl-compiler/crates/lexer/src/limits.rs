@@ -0,0 +1,32 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// The limits of `parser::ParseOptions`, the lexer checks the token count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Limit {
+  Depth,
+  Tokens,
+  Size,
+  Nodes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LimitExceeded {
+  pub limit: Limit,
+  pub max: usize,
+}
+
+impl fmt::Display for LimitExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.limit {
+      Limit::Depth => write!(f, "Input is nested deeper than {} levels", self.max),
+      Limit::Tokens => write!(f, "Input has more than {} tokens", self.max),
+      Limit::Size => write!(f, "Input is larger than {} bytes", self.max),
+      Limit::Nodes => write!(f, "Input has more than {} nodes", self.max),
+    }
+  }
+}
+
+impl std::error::Error for LimitExceeded {}
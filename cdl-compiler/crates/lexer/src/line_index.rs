@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use logos::Span;
+use serde::Serialize;
+
+use crate::Location;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LineCol {
+  pub line: usize,
+  pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WideEncoding {
+  Utf16,
+  Utf32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WideLineCol {
+  pub line: usize,
+  pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WideChar {
+  start: usize,
+  end: usize,
+}
+
+impl WideChar {
+  fn len(&self) -> usize {
+    self.end - self.start
+  }
+
+  fn wide_len(&self, encoding: WideEncoding) -> usize {
+    match encoding {
+      WideEncoding::Utf16 if self.len() == 4 => 2,
+      WideEncoding::Utf16 => 1,
+      WideEncoding::Utf32 => 1,
+    }
+  }
+}
+
+/// Maps byte offsets in a source text to zero based line/column pairs. Built once per
+/// source, lookups are a binary search over the line starts. Columns are in UTF-8 bytes,
+/// `to_wide` converts them to UTF-16 code units or chars.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+  line_starts: Vec<usize>,
+  wide_chars: HashMap<usize, Vec<WideChar>>,
+  len: usize,
+}
+
+impl LineIndex {
+  pub fn new(text: &str) -> LineIndex {
+    let mut line_starts = vec![0];
+    let mut wide_chars = HashMap::new();
+    let mut line_wide_chars = vec![];
+    let mut line_start = 0;
+    for (offset, c) in text.char_indices() {
+      if c == '\n' {
+        if !line_wide_chars.is_empty() {
+          wide_chars.insert(line_starts.len() - 1, std::mem::take(&mut line_wide_chars));
+        }
+        line_start = offset + 1;
+        line_starts.push(line_start);
+        continue;
+      }
+      if !c.is_ascii() {
+        let start = offset - line_start;
+        line_wide_chars.push(WideChar {
+          start,
+          end: start + c.len_utf8(),
+        });
+      }
+    }
+    if !line_wide_chars.is_empty() {
+      wide_chars.insert(line_starts.len() - 1, line_wide_chars);
+    }
+    LineIndex {
+      line_starts,
+      wide_chars,
+      len: text.len(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn line_count(&self) -> usize {
+    self.line_starts.len()
+  }
+
+  pub fn line_col(&self, offset: usize) -> LineCol {
+    let offset = offset.min(self.len);
+    let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+    LineCol {
+      line,
+      col: offset - self.line_starts[line],
+    }
+  }
+
+  pub fn offset(&self, line_col: LineCol) -> Option<usize> {
+    let line_start = *self.line_starts.get(line_col.line)?;
+    let line_end = self
+      .line_starts
+      .get(line_col.line + 1)
+      .copied()
+      .unwrap_or(self.len);
+    let offset = line_start + line_col.col;
+    if offset > line_end {
+      return None;
+    }
+    Some(offset)
+  }
+
+  pub fn to_wide(&self, encoding: WideEncoding, line_col: LineCol) -> WideLineCol {
+    let mut col = line_col.col;
+    if let Some(wide_chars) = self.wide_chars.get(&line_col.line) {
+      for c in wide_chars.iter().take_while(|c| c.end <= line_col.col) {
+        col -= c.len() - c.wide_len(encoding);
+      }
+    }
+    WideLineCol {
+      line: line_col.line,
+      col,
+    }
+  }
+
+  pub fn to_utf8(&self, encoding: WideEncoding, wide: WideLineCol) -> LineCol {
+    let mut col = wide.col;
+    if let Some(wide_chars) = self.wide_chars.get(&wide.line) {
+      for c in wide_chars {
+        if c.start >= col {
+          break;
+        }
+        col += c.len() - c.wide_len(encoding);
+      }
+    }
+    LineCol {
+      line: wide.line,
+      col,
+    }
+  }
+
+  /// One based lines and columns, columns counted in chars. Used for messages meant for humans.
+  pub fn location(&self, span: &Span) -> Location {
+    let start = self.to_wide(WideEncoding::Utf32, self.line_col(span.start));
+    let end = self.to_wide(WideEncoding::Utf32, self.line_col(span.end));
+    Location {
+      start_line: start.line + 1,
+      start_pos: start.col + 1,
+      end_line: end.line + 1,
+      end_pos: end.col + 1,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn can_find_line_and_column() {
+    let index = LineIndex::new("first\nsecond\r\nthird");
+    assert_eq!(LineCol { line: 0, col: 0 }, index.line_col(0));
+    assert_eq!(LineCol { line: 0, col: 5 }, index.line_col(5));
+    assert_eq!(LineCol { line: 1, col: 0 }, index.line_col(6));
+    assert_eq!(LineCol { line: 1, col: 7 }, index.line_col(13));
+    assert_eq!(LineCol { line: 2, col: 2 }, index.line_col(16));
+    assert_eq!(3, index.line_count());
+  }
+
+  #[test]
+  fn clamps_offsets_past_the_end() {
+    let index = LineIndex::new("one\ntwo");
+    assert_eq!(LineCol { line: 1, col: 3 }, index.line_col(usize::MAX));
+  }
+
+  #[test]
+  fn can_convert_back_to_offset() {
+    let index = LineIndex::new("one\ntwo\n");
+    assert_eq!(Some(5), index.offset(LineCol { line: 1, col: 1 }));
+    assert_eq!(None, index.offset(LineCol { line: 1, col: 10 }));
+    assert_eq!(None, index.offset(LineCol { line: 5, col: 0 }));
+  }
+
+  #[test]
+  fn can_convert_to_utf16_and_chars() {
+    // 'ø' is 2 bytes in UTF-8, '😀' is 4 bytes in UTF-8 and 2 code units in UTF-16
+    let text = "a\nøx😀y";
+    let index = LineIndex::new(text);
+    let y = index.line_col(text.find('y').unwrap());
+    assert_eq!(LineCol { line: 1, col: 7 }, y);
+    assert_eq!(
+      WideLineCol { line: 1, col: 4 },
+      index.to_wide(WideEncoding::Utf16, y)
+    );
+    assert_eq!(
+      WideLineCol { line: 1, col: 3 },
+      index.to_wide(WideEncoding::Utf32, y)
+    );
+    assert_eq!(
+      y,
+      index.to_utf8(WideEncoding::Utf16, WideLineCol { line: 1, col: 4 })
+    );
+    assert_eq!(
+      y,
+      index.to_utf8(WideEncoding::Utf32, WideLineCol { line: 1, col: 3 })
+    );
+  }
+
+  #[test]
+  fn location_is_one_based() {
+    let index = LineIndex::new("title \"æøå\"\nfoo bar");
+    let location = index.location(&(19..22));
+    assert_eq!(2, location.start_line);
+    assert_eq!(5, location.start_pos);
+    assert_eq!(2, location.end_line);
+    assert_eq!(8, location.end_pos);
+  }
+}
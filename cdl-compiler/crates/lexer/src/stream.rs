@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+use anyhow::{Context, Result};
+
+use crate::{into_owned_tokens, lex_borrowed, LexError, Token};
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Lexes tokens from a reader without loading the whole input. Text is read line by line into a
+/// buffer of roughly `chunk_size` bytes, and only lexed up to the last line end that is outside of
+/// strings and block comments, so tokens crossing chunk boundaries are lexed whole. Spans and error
+/// locations are relative to the start of the input.
+pub struct StreamLexer<R> {
+  reader: R,
+  chunk_size: usize,
+  buffer: String,
+  offset: usize,
+  line: usize,
+  scanner: Scanner,
+  tokens: VecDeque<Token>,
+  finished: bool,
+}
+
+pub fn lex_reader<R: BufRead>(reader: R) -> StreamLexer<R> {
+  StreamLexer::new(reader)
+}
+
+impl<R: BufRead> StreamLexer<R> {
+  pub fn new(reader: R) -> StreamLexer<R> {
+    StreamLexer::with_chunk_size(reader, DEFAULT_CHUNK_SIZE)
+  }
+
+  pub fn with_chunk_size(reader: R, chunk_size: usize) -> StreamLexer<R> {
+    StreamLexer {
+      reader,
+      chunk_size: chunk_size.max(1),
+      buffer: String::new(),
+      offset: 0,
+      line: 0,
+      scanner: Scanner::default(),
+      tokens: VecDeque::new(),
+      finished: false,
+    }
+  }
+
+  fn fill(&mut self) -> Result<()> {
+    let mut eof = false;
+    let mut read_any = false;
+    while !read_any || self.buffer.len() < self.chunk_size {
+      let read = self
+        .reader
+        .read_line(&mut self.buffer)
+        .context("Could not read input")?;
+      if read == 0 {
+        eof = true;
+        break;
+      }
+      read_any = true;
+    }
+    if eof {
+      self.finished = true;
+      return self.lex_chunk(self.buffer.len());
+    }
+    let cut = self.scanner.scan(&self.buffer);
+    if cut > 0 {
+      self.lex_chunk(cut)?;
+    }
+    Ok(())
+  }
+
+  fn lex_chunk(&mut self, end: usize) -> Result<()> {
+    let (offset, line) = (self.offset, self.line);
+    let chunk = &self.buffer[..end];
+    let tokens = lex_borrowed(chunk).map_err(|err| match err.downcast::<LexError>() {
+      Ok(mut err) => {
+        err.pos = err.pos.start + offset..err.pos.end + offset;
+        err.location.start_line += line;
+        err.location.end_line += line;
+        err.into()
+      }
+      Err(err) => err,
+    })?;
+    self
+      .tokens
+      .extend(into_owned_tokens(tokens).into_iter().map(|mut token| {
+        token.pos = token.pos.start + offset..token.pos.end + offset;
+        token
+      }));
+    self.line += chunk.bytes().filter(|b| *b == b'\n').count();
+    self.offset += end;
+    self.buffer.drain(..end);
+    self.scanner.consumed(end);
+    Ok(())
+  }
+}
+
+impl<R: BufRead> Iterator for StreamLexer<R> {
+  type Item = Result<Token>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if let Some(token) = self.tokens.pop_front() {
+        return Some(Ok(token));
+      }
+      if self.finished {
+        return None;
+      }
+      if let Err(err) = self.fill() {
+        self.finished = true;
+        self.tokens.clear();
+        return Some(Err(err));
+      }
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum ScanState {
+  #[default]
+  Normal,
+  LineComment,
+  BlockComment,
+  String(u8),
+  RawString(u8),
+  TripleString,
+}
+
+/// Tracks just enough of the lexer rules to know where strings and comments start and end.
+#[derive(Debug, Default)]
+struct Scanner {
+  state: ScanState,
+  pos: usize,
+}
+
+impl Scanner {
+  /// Scans the unscanned part of `text` and returns the end of the last line that can be lexed on
+  /// its own, or 0 if there is none. Stops early when a decision needs bytes not read yet.
+  fn scan(&mut self, text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut cut = 0;
+    let mut i = self.pos;
+    while i < bytes.len() {
+      let next = |n: usize| bytes.get(i + n).copied();
+      let step = match self.state {
+        ScanState::Normal => match bytes[i] {
+          b'\n' => {
+            cut = i + 1;
+            Some(1)
+          }
+          b'/' => match next(1) {
+            Some(b'/') => {
+              self.state = ScanState::LineComment;
+              Some(2)
+            }
+            Some(b'*') => {
+              self.state = ScanState::BlockComment;
+              Some(2)
+            }
+            Some(_) => Some(1),
+            None => None,
+          },
+          b'"' => match (next(1), next(2)) {
+            (Some(b'"'), Some(b'"')) => {
+              self.state = ScanState::TripleString;
+              Some(3)
+            }
+            (Some(_), Some(_)) => {
+              self.state = ScanState::String(b'"');
+              Some(1)
+            }
+            _ => None,
+          },
+          b'\'' => {
+            self.state = ScanState::String(b'\'');
+            Some(1)
+          }
+          b'r' if i == 0 || !is_identifier_byte(bytes[i - 1]) => match next(1) {
+            Some(quote @ (b'"' | b'\'')) => {
+              self.state = ScanState::RawString(quote);
+              Some(2)
+            }
+            Some(_) => Some(1),
+            None => None,
+          },
+          _ => Some(1),
+        },
+        ScanState::LineComment => {
+          if bytes[i] == b'\n' {
+            self.state = ScanState::Normal;
+            cut = i + 1;
+          }
+          Some(1)
+        }
+        ScanState::BlockComment => match (bytes[i], next(1)) {
+          (b'*', Some(b'/')) => {
+            self.state = ScanState::Normal;
+            Some(2)
+          }
+          (b'*', Some(_)) => Some(2),
+          (b'*', None) => None,
+          _ => Some(1),
+        },
+        ScanState::String(quote) => match bytes[i] {
+          b'\\' => next(1).map(|_| 2),
+          b if b == quote => {
+            self.state = ScanState::Normal;
+            Some(1)
+          }
+          _ => Some(1),
+        },
+        ScanState::RawString(quote) => {
+          if bytes[i] == quote {
+            self.state = ScanState::Normal;
+          }
+          Some(1)
+        }
+        ScanState::TripleString => match (bytes[i], next(1), next(2)) {
+          (b'"', Some(b'"'), Some(b'"')) => {
+            self.state = ScanState::Normal;
+            Some(3)
+          }
+          (b'"', Some(_), Some(_)) => Some(1),
+          (b'"', _, _) => None,
+          _ => Some(1),
+        },
+      };
+      match step {
+        Some(step) => i += step,
+        None => break,
+      }
+    }
+    self.pos = i;
+    cut
+  }
+
+  /// Called after the first `len` bytes have been removed from the scanned text.
+  fn consumed(&mut self, len: usize) {
+    self.pos = self.pos.saturating_sub(len);
+  }
+}
+
+fn is_identifier_byte(b: u8) -> bool {
+  b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.' | b'$')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lex;
+
+  fn stream(text: &str, chunk_size: usize) -> Result<Vec<Token>> {
+    StreamLexer::with_chunk_size(text.as_bytes(), chunk_size).collect()
+  }
+
+  #[test]
+  fn matches_lex_on_large_file() {
+    let file = include_str!("../../../test_script/test.cdl");
+    let expected = lex(file).unwrap();
+    assert_eq!(expected, stream(file, 4096).unwrap());
+    assert_eq!(
+      expected,
+      lex_reader(file.as_bytes())
+        .collect::<Result<Vec<_>>>()
+        .unwrap()
+    );
+  }
+
+  #[test]
+  fn handles_tokens_crossing_chunks() {
+    let text = "a: \"one\ntwo\"\n/* x\n * y **/ */\nb: \"\"\"q\n\"\"\" c: r\"\\\n\"\nd: 'it\\'s\n' // \"\ne: \"\"\n";
+    let expected = lex(text).unwrap();
+    for chunk_size in 1..text.len() {
+      assert_eq!(
+        expected,
+        stream(text, chunk_size).unwrap(),
+        "chunk size {}",
+        chunk_size
+      );
+    }
+  }
+
+  #[test]
+  fn errors_have_absolute_locations() {
+    let err = stream("a\nb\n  c &\n", 1).unwrap_err();
+    assert_eq!(format!("{}", err), "[3:5]: Unknown token \"&\"");
+    assert_eq!(8..9, err.downcast::<LexError>().unwrap().pos);
+  }
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, bail, Result};
+
+/// Returns the value of a string literal, given the raw token text including quotes.
+/// Raw strings (`r"..."`, `r'...'`) are taken as is, other strings have `\n`, `\t`, `\r`,
+/// `\0`, `\\`, `\"`, `\'` and `\u{..}` replaced. Unknown escapes are kept verbatim, older
+/// scripts use them in format strings.
+pub fn unescape_string(raw: &str) -> Result<String> {
+  if let Some(inner) = raw.strip_prefix('r') {
+    return Ok(strip_quotes(inner, 1)?.to_string());
+  }
+  let inner = if raw.starts_with("\"\"\"") {
+    strip_quotes(raw, 3)?
+  } else {
+    strip_quotes(raw, 1)?
+  };
+  unescape(inner)
+}
+
+/// Quotes `value` as a double quoted string literal, the inverse of [`unescape_string`].
+pub fn escape_string(value: &str) -> String {
+  let mut raw = String::with_capacity(value.len() + 2);
+  raw.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => raw.push_str("\\\""),
+      '\\' => raw.push_str("\\\\"),
+      '\n' => raw.push_str("\\n"),
+      '\t' => raw.push_str("\\t"),
+      '\r' => raw.push_str("\\r"),
+      '\0' => raw.push_str("\\0"),
+      c => raw.push(c),
+    }
+  }
+  raw.push('"');
+  raw
+}
+
+fn strip_quotes(raw: &str, len: usize) -> Result<&str> {
+  if raw.len() < len * 2 || !raw.is_char_boundary(len) || !raw.is_char_boundary(raw.len() - len) {
+    bail!("Unterminated string {}", raw);
+  }
+  Ok(&raw[len..raw.len() - len])
+}
+
+fn unescape(text: &str) -> Result<String> {
+  if !text.contains('\\') {
+    return Ok(text.to_string());
+  }
+  let mut value = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      value.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => value.push('\n'),
+      Some('t') => value.push('\t'),
+      Some('r') => value.push('\r'),
+      Some('0') => value.push('\0'),
+      Some('\\') => value.push('\\'),
+      Some('"') => value.push('"'),
+      Some('\'') => value.push('\''),
+      Some('u') => value.push(unescape_unicode(&mut chars)?),
+      Some(other) => {
+        value.push('\\');
+        value.push(other);
+      }
+      None => value.push('\\'),
+    }
+  }
+  Ok(value)
+}
+
+fn unescape_unicode(chars: &mut std::str::Chars) -> Result<char> {
+  if chars.next() != Some('{') {
+    bail!("Expected '{{' after \\u");
+  }
+  let mut hex = String::new();
+  loop {
+    match chars.next() {
+      Some('}') => break,
+      Some(c) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+      _ => bail!("Invalid unicode escape \\u{{{}", hex),
+    }
+  }
+  let code = u32::from_str_radix(&hex, 16).map_err(|_| anyhow!("Empty unicode escape \\u{{}}"))?;
+  char::from_u32(code).ok_or_else(|| anyhow!("Invalid unicode code point \\u{{{}}}", hex))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn can_unescape() {
+    assert_eq!(
+      "a \"b\"\n\t\\",
+      unescape_string(r#""a \"b\"\n\t\\""#).unwrap()
+    );
+    assert_eq!("it's", unescape_string(r"'it\'s'").unwrap());
+    assert_eq!("ø😀", unescape_string(r#""\u{f8}\u{1F600}""#).unwrap());
+  }
+
+  #[test]
+  fn escape_round_trips() {
+    for value in ["plain", "a \"b\"\n\t\\", "it's ø", "\\W\r\0"] {
+      assert_eq!(value, unescape_string(&escape_string(value)).unwrap());
+    }
+    assert_eq!(r#""say \"hi\"""#, escape_string("say \"hi\""));
+  }
+
+  #[test]
+  fn keeps_unknown_escapes() {
+    assert_eq!(r"\W\e\e\k W", unescape_string(r#""\W\e\e\k W""#).unwrap());
+  }
+
+  #[test]
+  fn raw_and_triple_quoted_strings() {
+    assert_eq!(r"C:\temp\n", unescape_string(r#"r"C:\temp\n""#).unwrap());
+    assert_eq!(
+      "say \"hi\"\nbye",
+      unescape_string("\"\"\"say \"hi\"\nbye\"\"\"").unwrap()
+    );
+  }
+
+  #[test]
+  fn invalid_unicode_escape_fails() {
+    assert!(unescape_string(r#""\u{110000}""#).is_err());
+    assert!(unescape_string(r#""\u{}""#).is_err());
+    assert!(unescape_string(r#""\u1234""#).is_err());
+  }
+}
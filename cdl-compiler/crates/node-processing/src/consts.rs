@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+
+use ast::{Ast, AstConstNode, AstNode, AstReferenceNode, Node, NodeRef, Visitor, Walk};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+  pub node_ref: NodeRef,
+  pub message: String,
+}
+
+#[derive(Default)]
+struct ConstCollector {
+  consts: Vec<NodeRef>,
+  references: Vec<NodeRef>,
+}
+
+impl Visitor for ConstCollector {
+  fn visit_const(&mut self, _ast: &Ast, node_ref: NodeRef, _c: &AstConstNode) -> Walk {
+    self.consts.push(node_ref);
+    Walk::Continue
+  }
+
+  fn visit_reference(&mut self, _ast: &Ast, node_ref: NodeRef, _r: &AstReferenceNode) -> Walk {
+    self.references.push(node_ref);
+    Walk::Continue
+  }
+}
+
+fn collect(ast: &Ast) -> ConstCollector {
+  let mut collector = ConstCollector::default();
+  ast.walk(&mut collector);
+  collector
+}
+
+fn const_data(ast: &Ast, node_ref: NodeRef) -> Option<AstConstNode> {
+  match &ast.get_node(node_ref)?.node_data {
+    Node::Const(c) => Some(c.clone()),
+    _ => None,
+  }
+}
+
+/// Reports consts that are never referenced and consts shadowing a const in an enclosing
+/// scope. References must have been resolved by `NodeProcessor` first.
+pub fn check_constants(ast: &Ast) -> Vec<Warning> {
+  let collector = collect(ast);
+  let used = collector
+    .references
+    .iter()
+    .filter_map(|r| match &ast.get_node(*r)?.node_data {
+      Node::Reference(reference) => Some(reference.resolved_node.get()),
+      _ => None,
+    })
+    .collect::<HashSet<_>>();
+
+  let mut warnings = vec![];
+  for const_ref in collector.consts {
+    let Some(c) = const_data(ast, const_ref) else {
+      continue;
+    };
+    if let Some(shadowed) = find_shadowed(ast, const_ref, &c) {
+      let location = ast
+        .get_location_for_node(shadowed)
+        .map(|l| format!(" defined at {}:{}", l.start_line, l.start_pos))
+        .unwrap_or_default();
+      warnings.push(Warning {
+        node_ref: const_ref,
+        message: format!(
+          "Constant {} shadows the constant {}{}",
+          c.name, c.name, location
+        ),
+      });
+    }
+    if !used.contains(&c.value.get()) {
+      warnings.push(Warning {
+        node_ref: const_ref,
+        message: format!("Constant {} is never used", c.name),
+      });
+    }
+  }
+  warnings
+}
+
+fn find_shadowed(ast: &Ast, const_ref: NodeRef, c: &AstConstNode) -> Option<NodeRef> {
+  let scope = *ast.get_parent(const_ref).first()?;
+  let mut current = scope;
+  while let Some(parent) = ast.get_parent(current).first().copied() {
+    for child in ast.get_node(parent)?.node_data.children() {
+      if let Some(other) = const_data(ast, child) {
+        if other.name == c.name {
+          return Some(child);
+        }
+      }
+    }
+    current = parent;
+  }
+  None
+}
+
+/// Replaces references to consts with the value of the const and removes the const
+/// declarations. References must have been resolved by `NodeProcessor` first.
+pub fn inline_constants(ast: &Ast) {
+  let collector = collect(ast);
+  let values = collector
+    .consts
+    .iter()
+    .filter_map(|c| const_data(ast, *c))
+    .map(|c| c.value.get())
+    .collect::<HashSet<_>>();
+
+  for reference in collector.references {
+    let mut target = reference;
+    let mut seen = HashSet::new();
+    // follow consts defined as another const
+    while seen.insert(target) {
+      match &ast.get_node(target).unwrap().node_data {
+        Node::Reference(r) if values.contains(&r.resolved_node.get()) => {
+          target = r.resolved_node.get()
+        }
+        _ => break,
+      }
+    }
+    if target == reference {
+      continue;
+    }
+    let node_data = ast.get_node(target).unwrap().node_data.clone();
+    let parent = ast
+      .get_parent(reference)
+      .first()
+      .copied()
+      .unwrap_or(NodeRef(-1));
+    ast.replace_node(reference, AstNode::new(node_data, parent));
+  }
+
+  for scope in [ast.script_entity].into_iter().chain(
+    collector
+      .consts
+      .iter()
+      .filter_map(|c| ast.get_parent(*c).first().copied()),
+  ) {
+    let Some(node) = ast.get_node(scope) else {
+      continue;
+    };
+    let is_const = |child: &NodeRef| const_data(ast, *child).is_some();
+    match &node.node_data {
+      Node::Script(script) => script.children.borrow_mut().retain(|c| !is_const(c)),
+      Node::Entity(entity) => entity.children.borrow_mut().retain(|c| !is_const(c)),
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ast::select_property_value;
+  use parser::parse_text;
+
+  use super::*;
+  use crate::NodeProcessor;
+
+  fn process(text: &str) -> Ast {
+    NodeProcessor::new(parse_text(text).unwrap())
+      .process()
+      .unwrap()
+  }
+
+  fn resolved_value(ast: &Ast, property: &str) -> String {
+    let value = select_property_value(ast, property)[0];
+    let Node::Reference(r) = &ast.get_node(value).unwrap().node_data else {
+      panic!("expected reference");
+    };
+    ast.node_to_cdl(r.resolved_node.get()).unwrap()
+  }
+
+  #[test]
+  fn references_resolve_to_nearest_const() {
+    let ast = process(
+      "const color = #ff0000\nconfig style {\n  const color = #00ff00\n  inner: @color\n}\nwidget kpi {\n  outer: @color\n}\n",
+    );
+    assert_eq!("#00ff00", resolved_value(&ast, "inner"));
+    assert_eq!("#ff0000", resolved_value(&ast, "outer"));
+  }
+
+  #[test]
+  fn reports_unused_and_shadowed_consts() {
+    let ast = process(
+      "const limit = 10\nconst unused = 1\nconfig hub {\n  const limit = 20\n  value: @limit\n}\n",
+    );
+    let messages = check_constants(&ast)
+      .into_iter()
+      .map(|w| w.message)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        "Constant limit is never used",
+        "Constant unused is never used",
+        "Constant limit shadows the constant limit defined at 1:1",
+      ],
+      messages
+    );
+  }
+
+  #[test]
+  fn can_inline_consts() {
+    let ast =
+      process("const base = 10\nconst limit = @base * 2\nwidget kpi {\n  target: @limit + 1\n}\n");
+    inline_constants(&ast);
+    assert_eq!(
+      "widget kpi {\n  target: 10 * 2 + 1\n}\n",
+      ast.to_cdl().unwrap()
+    );
+  }
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+
+use ast::{Ast, AstEntityNode, AstVPathNode, Node, NodeRef, Visitor, Walk};
+
+use crate::{Diagnostic, Phase};
+
+const HIERARCHY_TERM: &str = "reportingHierarchy";
+
+/// Finds the `reportingHierarchy` declarations in `config hub`, and the `table:^hierarchy` paths
+/// to bind to them.
+#[derive(Default)]
+struct HierarchyCollector {
+  config_depth: usize,
+  hierarchies: Vec<NodeRef>,
+  vpaths: Vec<NodeRef>,
+}
+
+fn is_config_hub(entity: &AstEntityNode) -> bool {
+  entity.terms.len() == 2
+    && entity.terms[0].as_str() == "config"
+    && entity.terms[1].as_str() == "hub"
+}
+
+impl Visitor for HierarchyCollector {
+  fn visit_entity(&mut self, _ast: &Ast, node_ref: NodeRef, entity: &AstEntityNode) -> Walk {
+    if is_config_hub(entity) {
+      self.config_depth += 1;
+    }
+    if self.config_depth > 0
+      && entity
+        .terms
+        .first()
+        .is_some_and(|t| t.as_str() == HIERARCHY_TERM)
+    {
+      self.hierarchies.push(node_ref);
+    }
+    Walk::Continue
+  }
+
+  fn leave_entity(&mut self, _ast: &Ast, _node_ref: NodeRef, entity: &AstEntityNode) {
+    if is_config_hub(entity) {
+      self.config_depth -= 1;
+    }
+  }
+
+  fn visit_vpath(&mut self, _ast: &Ast, node_ref: NodeRef, vpath: &AstVPathNode) -> Walk {
+    if vpath.is_hierarchy {
+      self.vpaths.push(node_ref);
+    }
+    Walk::Continue
+  }
+}
+
+/// The hierarchies declared in `config hub`, by the names a vpath can use for them. That is the
+/// hierarchy id, and for self referencing hierarchies the table of their `parent` property, so
+/// `model:^parent` finds a hierarchy with `parent: model:parent`.
+pub fn hierarchy_declarations(ast: &Ast) -> HashMap<String, NodeRef> {
+  let mut collector = HierarchyCollector::default();
+  ast.walk(&mut collector);
+  declarations(ast, &collector.hierarchies)
+}
+
+fn declarations(ast: &Ast, hierarchies: &[NodeRef]) -> HashMap<String, NodeRef> {
+  let mut by_name = HashMap::new();
+  for node_ref in hierarchies {
+    if let Some(table) = parent_table(ast, *node_ref) {
+      by_name.entry(table).or_insert(*node_ref);
+    }
+  }
+  // ids win over parent tables
+  for node_ref in hierarchies {
+    let Some(Node::Entity(entity)) = ast.get_node(*node_ref).map(|n| n.node_data.clone()) else {
+      continue;
+    };
+    if let Some(ident) = entity.ident {
+      by_name.insert(ident.to_string(), *node_ref);
+    }
+  }
+  by_name
+}
+
+fn parent_table(ast: &Ast, hierarchy: NodeRef) -> Option<String> {
+  let node = ast.get_node(hierarchy)?;
+  node.node_data.children().into_iter().find_map(|child| {
+    let child_node = ast.get_node(child)?;
+    let Node::Property(property) = &child_node.node_data else {
+      return None;
+    };
+    if property.name.as_str() != "parent" {
+      return None;
+    }
+    let value = *property.children.borrow().first()?;
+    match &ast.get_node(value)?.node_data {
+      Node::VPath(vpath) => vpath.table.as_ref().map(|t| t.to_string()),
+      _ => None,
+    }
+  })
+}
+
+/// Binds every `table:^hierarchy` path to its hierarchy declaration. Paths without a table are
+/// left alone, paths naming an unknown hierarchy give a warning.
+pub(crate) fn resolve_hierarchies(ast: &Ast, phase: Phase) -> Vec<Diagnostic> {
+  let mut collector = HierarchyCollector::default();
+  ast.walk(&mut collector);
+  let declarations = declarations(ast, &collector.hierarchies);
+  let mut seen = HashSet::new();
+  let mut diagnostics = vec![];
+  for node_ref in collector.vpaths {
+    // nodes pulled in through `@ref` are walked once per entity using them
+    if !seen.insert(node_ref) {
+      continue;
+    }
+    let Some(node) = ast.get_node(node_ref) else {
+      continue;
+    };
+    let Node::VPath(vpath) = &node.node_data else {
+      continue;
+    };
+    let Some(table) = &vpath.table else {
+      continue;
+    };
+    match declarations.get(table.as_str()) {
+      Some(hierarchy) => vpath.set_hierarchy(*hierarchy),
+      None => diagnostics.push(Diagnostic::warning(
+        phase,
+        format!("Unknown hierarchy {}", table),
+        Some(node_ref),
+      )),
+    }
+  }
+  diagnostics
+}
+
+/// The `reportingHierarchy` entity `vpath` binds to, once the ast has been processed.
+pub fn hierarchy_for_vpath(ast: &Ast, vpath: NodeRef) -> Option<NodeRef> {
+  match &ast.get_node(vpath)?.node_data {
+    Node::VPath(vpath) if vpath.hierarchy.get().0 >= 0 => Some(vpath.hierarchy.get()),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ast::select_property_value;
+
+  use super::*;
+  use crate::{NodeProcessor, PassManager, Severity};
+
+  const SCRIPT: &str = r#"config hub {
+  hub: 1
+  reportingHierarchy selfRefLookup #Sites {
+    source: :Location
+  }
+  dataset textAnalytics #ta {
+    reportingHierarchy selfRefCustom #categories {
+      parent: ta.model:parent
+    }
+  }
+}
+page #p {
+  widget kpi {
+    sites: depth(Sites:^hierarchy)
+    categories: ta.model:^parent
+    unknown: count(Other:^hierarchy)
+    plain: Sites:id
+  }
+}
+"#;
+
+  fn vpath(ast: &Ast, property: &str) -> NodeRef {
+    let value = select_property_value(ast, property)[0];
+    match &ast.get_node(value).unwrap().node_data {
+      Node::VPath(_) => value,
+      _ => ast.get_node(value).unwrap().node_data.children()[0],
+    }
+  }
+
+  #[test]
+  fn finds_declarations_by_id_and_parent_table() {
+    let ast = parser::parse_text(SCRIPT).unwrap();
+    let declarations = hierarchy_declarations(&ast);
+    assert_eq!(3, declarations.len());
+    assert_eq!(declarations["categories"], declarations["ta.model"]);
+    assert!(declarations.contains_key("Sites"));
+  }
+
+  #[test]
+  fn binds_vpaths_to_hierarchies() {
+    let ast = NodeProcessor::new(parser::parse_text(SCRIPT).unwrap())
+      .process()
+      .unwrap();
+    let declarations = hierarchy_declarations(&ast);
+    assert_eq!(
+      Some(declarations["Sites"]),
+      hierarchy_for_vpath(&ast, vpath(&ast, "sites"))
+    );
+    assert_eq!(
+      Some(declarations["categories"]),
+      hierarchy_for_vpath(&ast, vpath(&ast, "categories"))
+    );
+    assert_eq!(None, hierarchy_for_vpath(&ast, vpath(&ast, "unknown")));
+    assert_eq!(None, hierarchy_for_vpath(&ast, vpath(&ast, "plain")));
+  }
+
+  #[test]
+  fn unknown_hierarchies_give_warnings() {
+    let (ast, report) = PassManager::new().run(parser::parse_text(SCRIPT).unwrap());
+    let diagnostics = report.diagnostics().collect::<Vec<_>>();
+    assert_eq!(1, diagnostics.len());
+    assert_eq!(Severity::Warning, diagnostics[0].severity);
+    assert_eq!("Unknown hierarchy Other", diagnostics[0].message);
+    assert_eq!(Some(vpath(&ast, "unknown")), diagnostics[0].node_ref);
+  }
+}
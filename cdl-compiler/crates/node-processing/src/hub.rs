@@ -0,0 +1,394 @@
+use anyhow::{anyhow, bail, Result};
+use ast::{Ast, AstEntityNode, Node, NodeRef, DEFAULT_WIDTH};
+use lexer::unescape_string;
+use serde::Serialize;
+
+/// A `table:variable` path. Both parts are optional, `:` alone is the default table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VPath {
+  pub table: Option<String>,
+  pub variable: Option<String>,
+}
+
+/// `table alias = table`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableAlias {
+  pub alias: String,
+  pub table: String,
+  pub node_ref: NodeRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dataset {
+  pub id: Option<String>,
+  /// `survey`, `cases`, `textAnalytics` and so on.
+  pub kind: String,
+  pub public_name: Option<String>,
+  pub node_ref: NodeRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Variable {
+  pub id: Option<String>,
+  /// `singleChoice`, `auto` and so on.
+  pub kind: String,
+  /// Id of the dataset the variable is declared in.
+  pub dataset: Option<String>,
+  pub table: Option<VPath>,
+  pub label: Option<String>,
+  pub node_ref: NodeRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Relation {
+  pub id: Option<String>,
+  /// `oneToMany` and so on.
+  pub kind: String,
+  pub primary_key: VPath,
+  pub foreign_key: VPath,
+  pub node_ref: NodeRef,
+}
+
+/// Typed view of a `config hub` block.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HubConfig {
+  pub hub_id: u64,
+  pub tables: Vec<TableAlias>,
+  pub datasets: Vec<Dataset>,
+  /// Variables declared directly in the hub and in its datasets.
+  pub variables: Vec<Variable>,
+  pub relations: Vec<Relation>,
+  pub node_ref: NodeRef,
+}
+
+/// Every `config hub` block in a processed ast, in source order. Properties inherited through
+/// `@ref` count, references to values are followed.
+pub fn hub_configs(ast: &Ast) -> Result<Vec<HubConfig>> {
+  let Some(script) = ast.get_node(ast.script_entity) else {
+    return Ok(vec![]);
+  };
+  script
+    .node_data
+    .children()
+    .into_iter()
+    .filter(|child| matches!(entity(ast, *child), Some(e) if is_config_hub(&e)))
+    .map(|child| HubConfig::from_entity(ast, child))
+    .collect()
+}
+
+fn is_config_hub(entity: &AstEntityNode) -> bool {
+  entity.terms.len() == 2
+    && entity.terms[0].as_str() == "config"
+    && entity.terms[1].as_str() == "hub"
+}
+
+fn entity(ast: &Ast, node_ref: NodeRef) -> Option<AstEntityNode> {
+  match &ast.get_node(node_ref)?.node_data {
+    Node::Entity(entity) => Some(entity.clone()),
+    _ => None,
+  }
+}
+
+fn kind(entity: &AstEntityNode) -> String {
+  entity
+    .terms
+    .iter()
+    .skip(1)
+    .map(|t| t.to_string())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+impl HubConfig {
+  pub fn from_entity(ast: &Ast, node_ref: NodeRef) -> Result<HubConfig> {
+    let reader = Reader { ast };
+    match entity(ast, node_ref) {
+      Some(entity) if is_config_hub(&entity) => {}
+      _ => bail!("Expected a config hub block{}", reader.at(node_ref)),
+    }
+    let hub_id = match reader.property(node_ref, "hub")? {
+      Some(value) => reader.hub_id(value)?,
+      None => bail!("Missing hub in config hub{}", reader.at(node_ref)),
+    };
+    let mut hub = HubConfig {
+      hub_id,
+      tables: vec![],
+      datasets: vec![],
+      variables: vec![],
+      relations: vec![],
+      node_ref,
+    };
+    reader.collect(node_ref, None, &mut hub)?;
+    Ok(hub)
+  }
+
+  pub fn table(&self, alias: &str) -> Option<&TableAlias> {
+    self.tables.iter().find(|t| t.alias == alias)
+  }
+
+  pub fn dataset(&self, id: &str) -> Option<&Dataset> {
+    self.datasets.iter().find(|d| d.id.as_deref() == Some(id))
+  }
+
+  pub fn variable(&self, id: &str) -> Option<&Variable> {
+    self.variables.iter().find(|v| v.id.as_deref() == Some(id))
+  }
+}
+
+struct Reader<'a> {
+  ast: &'a Ast,
+}
+
+impl Reader<'_> {
+  fn at(&self, node_ref: NodeRef) -> String {
+    self
+      .ast
+      .get_location_for_node(node_ref)
+      .map(|l| format!(" at {}:{}", l.start_line, l.start_pos))
+      .unwrap_or_default()
+  }
+
+  /// The value as written, long expressions wrapped over several lines.
+  fn quote(&self, node_ref: NodeRef) -> String {
+    self
+      .ast
+      .node_to_cdl_with_width(node_ref, DEFAULT_WIDTH)
+      .unwrap_or_default()
+  }
+
+  /// Walks the blocks owned by `node_ref`, `dataset` is the id of the enclosing dataset.
+  fn collect(&self, node_ref: NodeRef, dataset: Option<&str>, hub: &mut HubConfig) -> Result<()> {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return Ok(());
+    };
+    for child_ref in node.node_data.children() {
+      if self.ast.get_parent(child_ref).first() != Some(&node_ref) {
+        continue;
+      }
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      match &child.node_data {
+        Node::TableAlias(alias) => hub.tables.push(TableAlias {
+          alias: alias.alias.to_string(),
+          table: alias.table.to_string(),
+          node_ref: child_ref,
+        }),
+        Node::Entity(entity) => {
+          let id = entity.ident.as_ref().map(|i| i.to_string());
+          match entity.terms.first().map(|t| t.as_str()) {
+            Some("dataset") => {
+              hub.datasets.push(Dataset {
+                id: id.clone(),
+                kind: kind(entity),
+                public_name: self.text(child_ref, "publicName")?,
+                node_ref: child_ref,
+              });
+              self.collect(child_ref, id.as_deref(), hub)?;
+            }
+            Some("variable") => hub.variables.push(Variable {
+              id,
+              kind: kind(entity),
+              dataset: dataset.map(|d| d.to_string()),
+              table: match self.property(child_ref, "table")? {
+                Some(value) => Some(self.vpath(value, "table")?),
+                None => None,
+              },
+              label: self.text(child_ref, "label")?,
+              node_ref: child_ref,
+            }),
+            Some("relation") => {
+              let key = |name: &str| -> Result<VPath> {
+                match self.property(child_ref, name)? {
+                  Some(value) => self.vpath(value, name),
+                  None => bail!("Missing {} in relation{}", name, self.at(child_ref)),
+                }
+              };
+              hub.relations.push(Relation {
+                id,
+                kind: kind(entity),
+                primary_key: key("primaryKey")?,
+                foreign_key: key("foreignKey")?,
+                node_ref: child_ref,
+              });
+            }
+            _ => self.collect(child_ref, dataset, hub)?,
+          }
+        }
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  /// The value of the first `name` property of an entity, own properties come before inherited
+  /// ones. References are followed to the value they point to.
+  fn property(&self, entity_ref: NodeRef, name: &str) -> Result<Option<NodeRef>> {
+    let Some(node) = self.ast.get_node(entity_ref) else {
+      return Ok(None);
+    };
+    for child_ref in node.node_data.children() {
+      let Some(child) = self.ast.get_node(child_ref) else {
+        continue;
+      };
+      let Node::Property(property) = &child.node_data else {
+        continue;
+      };
+      if property.name.as_str() != name {
+        continue;
+      }
+      let values = property.children.borrow();
+      let [value] = values.as_slice() else {
+        bail!("Expected a single value for {}{}", name, self.at(child_ref));
+      };
+      return Ok(Some(self.follow(*value)));
+    }
+    Ok(None)
+  }
+
+  fn follow(&self, mut node_ref: NodeRef) -> NodeRef {
+    // bounded, in case of reference cycles
+    for _ in 0..32 {
+      match self.ast.get_node(node_ref).map(|n| n.node_data.clone()) {
+        Some(Node::Reference(reference)) if reference.resolved_node.get().0 >= 0 => {
+          node_ref = reference.resolved_node.get()
+        }
+        _ => break,
+      }
+    }
+    node_ref
+  }
+
+  fn hub_id(&self, value: NodeRef) -> Result<u64> {
+    match self.ast.get_node(value).map(|n| n.node_data.clone()) {
+      Some(Node::Number(number)) if number.value >= 0.0 && number.value.fract() == 0.0 => {
+        Ok(number.value as u64)
+      }
+      _ => bail!(
+        "Expected hub to be a whole number{}, found {}",
+        self.at(value),
+        self.quote(value)
+      ),
+    }
+  }
+
+  fn vpath(&self, value: NodeRef, name: &str) -> Result<VPath> {
+    match self.ast.get_node(value).map(|n| n.node_data.clone()) {
+      Some(Node::VPath(vpath)) if vpath.function.is_none() && !vpath.is_hierarchy => Ok(VPath {
+        table: vpath.table.map(|t| t.to_string()),
+        variable: vpath.variable.map(|v| v.to_string()),
+      }),
+      _ => bail!(
+        "Expected {} to be a table:variable path{}, found {}",
+        name,
+        self.at(value),
+        self.quote(value)
+      ),
+    }
+  }
+
+  fn text(&self, entity_ref: NodeRef, name: &str) -> Result<Option<String>> {
+    let Some(value) = self.property(entity_ref, name)? else {
+      return Ok(None);
+    };
+    match self.ast.get_node(value).map(|n| n.node_data.clone()) {
+      Some(Node::String(string)) => Ok(Some(unescape_string(string.text.as_str())?)),
+      Some(Node::Identifier(identifier)) => Ok(Some(identifier.identifier.to_string())),
+      _ => Err(anyhow!(
+        "Expected {} to be a string{}, found {}",
+        name,
+        self.at(value),
+        self.quote(value)
+      )),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::NodeProcessor;
+
+  const SCRIPT: &str = r#"config hub {
+  hub: @ids.hub
+  table accounts = custom.Account_2
+  dataset survey #survey {
+    publicName: p123
+    variable singleChoice #nps {
+      table: survey:
+      label: "NPS"
+    }
+  }
+  variable auto #total {
+    table: accounts:
+  }
+  relation oneToMany #rel {
+    primaryKey: accounts:AccountID
+    foreignKey: survey:accountID
+  }
+}
+custom properties #ids {
+  hub: 579
+}
+"#;
+
+  fn processed(text: &str) -> Ast {
+    NodeProcessor::new(parser::parse_text(text).unwrap())
+      .process()
+      .unwrap()
+  }
+
+  #[test]
+  fn reads_hub_config() {
+    let ast = processed(SCRIPT);
+    let hubs = hub_configs(&ast).unwrap();
+    assert_eq!(1, hubs.len());
+    let hub = &hubs[0];
+    assert_eq!(579, hub.hub_id);
+    assert_eq!("custom.Account_2", hub.table("accounts").unwrap().table);
+    let survey = hub.dataset("survey").unwrap();
+    assert_eq!("survey", survey.kind);
+    assert_eq!(Some("p123".to_string()), survey.public_name);
+    let nps = hub.variable("nps").unwrap();
+    assert_eq!(Some("survey".to_string()), nps.dataset);
+    assert_eq!(Some("NPS".to_string()), nps.label);
+    assert_eq!(None, hub.variable("total").unwrap().dataset);
+    assert_eq!(
+      vec![Relation {
+        id: Some("rel".to_string()),
+        kind: "oneToMany".to_string(),
+        primary_key: VPath {
+          table: Some("accounts".to_string()),
+          variable: Some("AccountID".to_string()),
+        },
+        foreign_key: VPath {
+          table: Some("survey".to_string()),
+          variable: Some("accountID".to_string()),
+        },
+        node_ref: hub.relations[0].node_ref,
+      }],
+      hub.relations
+    );
+  }
+
+  #[test]
+  fn malformed_blocks_give_errors() {
+    let error = |text: &str| hub_configs(&processed(text)).unwrap_err().to_string();
+    assert_eq!(
+      "Missing hub in config hub at 1:1",
+      error("config hub {\n  table a = b.c\n}\n")
+    );
+    assert_eq!(
+      "Expected hub to be a whole number at 2:8, found \"x\"",
+      error("config hub {\n  hub: \"x\"\n}\n")
+    );
+    assert_eq!(
+      "Missing foreignKey in relation at 3:3",
+      error("config hub {\n  hub: 1\n  relation oneToMany {\n    primaryKey: a:b\n  }\n}\n")
+    );
+    assert_eq!(
+      "Expected primaryKey to be a table:variable path at 4:17, found max(1)",
+      error("config hub {\n  hub: 1\n  relation oneToMany {\n    primaryKey: max(1)\n    foreignKey: a:b\n  }\n}\n")
+    );
+  }
+}
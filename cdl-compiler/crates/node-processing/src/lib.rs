@@ -1,5 +1,16 @@
+mod consts;
+mod hierarchy;
+mod hub;
+mod passes;
 mod processing_context;
-use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+mod stats;
+mod templates;
+use std::{
+  cell::RefCell,
+  collections::{HashMap, HashSet},
+  fmt,
+  rc::Rc,
+};
 
 use anyhow::Result;
 use ast::{Ast, AstNode, Node, NodeRef};
@@ -7,6 +18,13 @@ use lexer::LexedStr;
 use processing_context::{ProcessingContext, ProcessingStatus};
 use tracing::trace;
 
+pub use consts::{check_constants, inline_constants, Warning};
+pub use hierarchy::{hierarchy_declarations, hierarchy_for_vpath};
+pub use hub::*;
+pub use passes::*;
+pub use stats::*;
+pub use templates::expand_templates;
+
 #[derive(Debug, PartialEq, Hash, Eq, Clone)]
 struct RefKey {
   path: Vec<LexedStr>,
@@ -48,26 +66,26 @@ impl Task {
 
 #[derive(Debug)]
 pub struct ProcessingError {
-  pub error_msgs: Vec<String>,
+  pub diagnostics: Vec<Diagnostic>,
 }
 
 impl fmt::Display for ProcessingError {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-    for error_msg in &self.error_msgs {
-      writeln!(f, "{}", error_msg)?;
+    for diagnostic in &self.diagnostics {
+      writeln!(f, "{}", diagnostic.message)?;
     }
     Ok(())
   }
 }
 
 impl std::error::Error for ProcessingError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
-    }
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    None
+  }
 
-    fn cause(&self) -> Option<&dyn std::error::Error> {
-        self.source()
-    }
+  fn cause(&self) -> Option<&dyn std::error::Error> {
+    self.source()
+  }
 }
 
 #[derive(Debug)]
@@ -87,9 +105,165 @@ impl NodeProcessor {
   }
 
   pub fn process(self) -> Result<Ast, ProcessingError> {
-    let status = self.process_node(self.ast.script_entity, ProcessingContext::new());
+    match self.process_partial() {
+      (ast, None) => Ok(ast),
+      (_, Some(error)) => Err(error),
+    }
+  }
+
+  /// Like [`NodeProcessor::process`], but hands back the ast also when processing fails, with
+  /// everything that could be resolved resolved.
+  pub fn process_partial(self) -> (Ast, Option<ProcessingError>) {
+    let report = PassManager::with_phases(&[
+      Phase::Templates,
+      Phase::Inheritance,
+      Phase::ReferenceResolution,
+    ])
+    .continue_on_error()
+    .run_processor(&self);
+    let diagnostics = report
+      .diagnostics()
+      .filter(|d| d.is_error())
+      .cloned()
+      .collect::<Vec<_>>();
+    let error = (!diagnostics.is_empty()).then_some(ProcessingError { diagnostics });
+    (self.ast, error)
+  }
+
+  pub(crate) fn into_ast(self) -> Ast {
+    self.ast
+  }
+
+  pub(crate) fn run_phase(&self, phase: Phase) -> Vec<Diagnostic> {
+    match phase {
+      Phase::Templates => match expand_templates(&self.ast) {
+        Ok(()) => vec![],
+        Err(error) => vec![Diagnostic::error(phase, error.to_string(), None)],
+      },
+      Phase::Inheritance => self.resolve_inheritance(),
+      Phase::ReferenceResolution => {
+        let mut diagnostics = self.resolve_references();
+        diagnostics.extend(hierarchy::resolve_hierarchies(&self.ast, phase));
+        diagnostics
+      }
+      Phase::Validation => check_constants(&self.ast)
+        .into_iter()
+        .map(|warning| Diagnostic::warning(phase, warning.message, Some(warning.node_ref)))
+        .collect(),
+      Phase::Lowering => {
+        inline_constants(&self.ast);
+        vec![]
+      }
+    }
+  }
+
+  /// Copies the children of `@ref` targets into the entities referring to them. A target is only
+  /// copied from once its own references are merged, so chains of `@ref` see everything.
+  fn resolve_inheritance(&self) -> Vec<Diagnostic> {
+    let mut pending = vec![];
+    self.collect_entities(self.ast.script_entity, &mut pending);
+    pending.retain(|entity_ref| {
+      let Some(Node::Entity(entity)) = self.get_node(*entity_ref).map(|n| n.node_data.clone())
+      else {
+        return false;
+      };
+      if entity.ident.is_some() {
+        self.add_entity_reference_target(*entity_ref, entity.ident.clone());
+      }
+      !entity.refs.is_empty()
+    });
+
+    loop {
+      let waiting = pending.iter().copied().collect::<HashSet<_>>();
+      let mut merged = false;
+      pending.retain(|entity_ref| {
+        let node = self.get_node(*entity_ref).unwrap();
+        let Node::Entity(entity) = &node.node_data else {
+          return false;
+        };
+        let targets = entity
+          .refs
+          .iter()
+          .map(|r| self.get_reference_target(r.clone()))
+          .collect::<Option<Vec<_>>>();
+        let Some(targets) = targets else {
+          return true;
+        };
+        if targets.iter().any(|t| waiting.contains(t)) {
+          return true;
+        }
+        for target in targets {
+          match &self.get_node(target).unwrap().node_data {
+            Node::Entity(target_entity) => self
+              .ast
+              .add_new_children_to_node(*entity_ref, target_entity.children.borrow().clone()),
+            _ => panic!("Expected entity node while resolving entity reference"),
+          }
+        }
+        merged = true;
+        false
+      });
+      if pending.is_empty() {
+        return vec![];
+      }
+      if !merged {
+        break;
+      }
+    }
+
+    pending
+      .into_iter()
+      .map(|entity_ref| {
+        let node = self.get_node(entity_ref).unwrap();
+        let Node::Entity(entity) = &node.node_data else {
+          unreachable!()
+        };
+        let name = match &entity.ident {
+          Some(ident) => format!("#{}", ident),
+          None => entity
+            .terms
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        };
+        let message = match entity
+          .refs
+          .iter()
+          .find(|r| self.get_reference_target((*r).clone()).is_none())
+        {
+          Some(missing) => format!(
+            "Did not find reference @{} target for entity {}",
+            missing.0, name
+          ),
+          None => format!("Circular reference through the targets of entity {}", name),
+        };
+        Diagnostic::error(Phase::Inheritance, message, Some(entity_ref))
+      })
+      .collect()
+  }
+
+  fn collect_entities(&self, node_ref: NodeRef, entities: &mut Vec<NodeRef>) {
+    let Some(node) = self.get_node(node_ref) else {
+      return;
+    };
+    if let Node::Entity(_) = &node.node_data {
+      entities.push(node_ref);
+    }
+    for child in node.node_data.children() {
+      if self.get_parents(child).first() == Some(&node_ref) {
+        self.collect_entities(child, entities);
+      }
+    }
+  }
+
+  fn resolve_references(&self) -> Vec<Diagnostic> {
+    let status = self.process_node(
+      self.ast.script_entity,
+      ProcessingContext::new(Phase::ReferenceResolution),
+    );
     if status.is_complete() {
-      return Ok(self.ast);
+      return vec![];
     }
     loop {
       let num_tasks_before_loop = self.tasks.borrow().len();
@@ -104,13 +278,13 @@ impl NodeProcessor {
       let current_tasks_after_loop = self.tasks.borrow().len();
       if num_tasks_before_loop == current_tasks_after_loop {
         let tasks = self.tasks.take();
-        let error = ProcessingError {
-          error_msgs: tasks.into_iter().map(|t| t.error_msg).collect(),
-        };
-        return Err(error);
+        return tasks
+          .into_iter()
+          .map(|t| Diagnostic::error(t.processing_context.phase, t.error_msg, Some(t.node_ref)))
+          .collect();
       }
     }
-    Ok(self.ast)
+    vec![]
   }
 
   #[tracing::instrument(
@@ -124,7 +298,7 @@ impl NodeProcessor {
     processing_context: ProcessingContext,
   ) -> ProcessingStatus {
     let node = self.get_node(node_ref).unwrap();
-    let node_data = &(*node).node_data;
+    let node_data = &node.node_data;
     let status = match node_data {
       Node::Title(_) => ProcessingStatus::Complete,
       Node::Entity(_) => self.process_entity(node_ref, processing_context.create_for_child()),
@@ -137,10 +311,13 @@ impl NodeProcessor {
       Node::VPath(_) => ProcessingStatus::Complete,
       Node::Color(_) => ProcessingStatus::Complete,
       Node::Reference(_) => self.process_reference(node_ref),
-      Node::Function(_) => ProcessingStatus::Complete,
-      Node::Operator(_) => ProcessingStatus::Complete,
+      Node::Function(_) | Node::Operator(_) | Node::UnaryOperator(_) => {
+        self.resolve_consts_in_expression(node_ref);
+        ProcessingStatus::Complete
+      }
       Node::TableAlias(_) => ProcessingStatus::Complete,
       Node::Formula(_) => ProcessingStatus::Complete,
+      Node::Const(_) => self.process_const(node_ref, processing_context.create_for_child()),
     };
     if status.is_complete() {
       self.set_node_processed(node_ref);
@@ -169,7 +346,7 @@ impl NodeProcessor {
       .get_node(node_ref)
       .expect("Tried to get an script node, got None");
     let children = {
-      match &(*node).node_data {
+      match &node.node_data {
         Node::Script(script_data) => script_data.children.borrow().clone(),
         _ => panic!("Expected script node"),
       }
@@ -193,42 +370,10 @@ impl NodeProcessor {
     match &node.node_data {
       Node::Entity(entity_data) => {
         trace!("Processing entity with name {:?}", &entity_data.ident);
-        if !entity_data.refs.is_empty() {
-          for entity_ref in entity_data.refs.iter() {
-            if let Some(target) = self.get_reference_target(entity_ref.clone()) {
-              if let Some(target_node) = self.get_node(target) {
-                match &target_node.node_data {
-                  Node::Entity(target_entity_data) => {
-                    self.ast.add_new_children_to_node(
-                      node_ref,
-                      target_entity_data.children.borrow().clone(),
-                    );
-                  }
-                  _ => panic!("Expected entity node while resolving entity reference"),
-                }
-              }
-            } else {
-              self.create_task(
-                node_ref,
-                format!(
-                  "Did not find reference @{} target for entity #{}",
-                  entity_ref.0,
-                  entity_data.ident.as_ref().unwrap()
-                ),
-                processing_context,
-              );
-              return ProcessingStatus::Incomplete;
-            }
-          }
-        }
-
         let children = entity_data.children.borrow().clone();
         let status = self.process_children(children, processing_context.create_for_child());
         if status.is_complete() {
-          trace!(
-            "Adding entity reference target {:?}",
-            entity_data.ident.as_ref().unwrap()
-          );
+          trace!("Adding entity reference target {:?}", &entity_data.ident);
           self.add_entity_reference_target(node_ref, entity_data.ident.clone());
         }
         status
@@ -274,6 +419,31 @@ impl NodeProcessor {
     status
   }
 
+  fn process_const(
+    &self,
+    node_ref: NodeRef,
+    processing_context: ProcessingContext,
+  ) -> ProcessingStatus {
+    let node = self
+      .get_node(node_ref)
+      .expect("Tried to get a const node, got None");
+    let (value, name) = match &node.node_data {
+      Node::Const(const_data) => (const_data.value.get(), const_data.name.clone()),
+      _ => panic!("Expected const node"),
+    };
+    let status = self.process_children(vec![value], processing_context.create_for_child());
+    if !status.is_complete() {
+      self.create_task(
+        node_ref,
+        format!("Could not process const {}", name),
+        processing_context,
+      );
+      return status;
+    }
+    self.add_property_reference_target(value, name);
+    status
+  }
+
   fn process_children(
     &self,
     children: Vec<NodeRef>,
@@ -294,16 +464,6 @@ impl NodeProcessor {
             processing_status = ProcessingStatus::Incomplete
           }
         }
-        ProcessingStatus::ChildIncomplete => {
-          if processing_status < ProcessingStatus::ChildIncomplete {
-            processing_status = ProcessingStatus::ChildIncomplete
-          }
-        }
-        ProcessingStatus::CompleteAndAbort => {
-          if processing_status < ProcessingStatus::CompleteAndAbort {
-            processing_status = ProcessingStatus::CompleteAndAbort
-          }
-        }
       }
     }
     processing_status
@@ -330,6 +490,10 @@ impl NodeProcessor {
           trace!("Found property as parent {:?}", &prop.name);
           Some(prop.name.clone())
         }
+        Node::Const(c) => {
+          trace!("Found const as parent {:?}", &c.name);
+          Some(c.name.clone())
+        }
         _ => panic!("did not find expected node type as parent during ref target creation"),
       }
     };
@@ -376,7 +540,10 @@ impl NodeProcessor {
       }
     };
     trace!("Processing reference with name {:?}", &refernce_str);
-    if let Some(target) = self.get_reference_target(refernce_str) {
+    let target = self
+      .find_const_in_scope(node_ref, &refernce_str)
+      .or_else(|| self.get_reference_target(refernce_str));
+    if let Some(target) = target {
       match &node.node_data {
         Node::Reference(ref_data) => ref_data.set_reference(target),
         _ => panic!("Expected reference node"),
@@ -387,6 +554,50 @@ impl NodeProcessor {
     }
   }
 
+  /// References inside expressions are not required to resolve, only the ones naming a
+  /// const in an enclosing scope are resolved.
+  fn resolve_consts_in_expression(&self, node_ref: NodeRef) {
+    let node = self.get_node(node_ref).unwrap();
+    for child in node.node_data.children() {
+      let child_node = self.get_node(child).unwrap();
+      match &child_node.node_data {
+        Node::Reference(ref_data) => {
+          if let Some(target) = self.find_const_in_scope(child, &ref_data.ident) {
+            ref_data.set_reference(target);
+            self.set_node_processed(child);
+          }
+        }
+        _ => self.resolve_consts_in_expression(child),
+      }
+    }
+  }
+
+  /// Consts are looked up in the enclosing scopes first, so a const in a config block
+  /// shadows a top level const with the same name.
+  fn find_const_in_scope(&self, node_ref: NodeRef, name: &LexedStr) -> Option<NodeRef> {
+    let mut current = node_ref;
+    while let Some(parent) = self.get_parents(current).first().copied() {
+      let parent_node = self.get_node(parent)?;
+      let children = match &parent_node.node_data {
+        Node::Script(script) => script.children.borrow().clone(),
+        Node::Entity(entity) => entity.children.borrow().clone(),
+        _ => vec![],
+      };
+      for child in children {
+        let Some(child_node) = self.get_node(child) else {
+          continue;
+        };
+        if let Node::Const(const_data) = &child_node.node_data {
+          if const_data.name == *name {
+            return Some(const_data.value.get());
+          }
+        }
+      }
+      current = parent;
+    }
+    None
+  }
+
   #[tracing::instrument(name = "ref-resolving", skip(self), level = "debug")]
   fn get_reference_target(&self, refernce_str: LexedStr) -> Option<NodeRef> {
     let parts: Vec<_> = refernce_str.0.split('.').collect();
@@ -406,17 +617,18 @@ impl NodeProcessor {
     error_msg: String,
     processing_context: ProcessingContext,
   ) {
-    self
-      .tasks
-      .borrow_mut()
-      .push(Task::new(node_ref, error_msg, processing_context));
+    // inherited children are reached through every entity inheriting them, queue them once
+    let mut tasks = self.tasks.borrow_mut();
+    if tasks.iter().any(|task| task.node_ref == node_ref) {
+      return;
+    }
+    tasks.push(Task::new(node_ref, error_msg, processing_context));
   }
 }
 
 #[cfg(test)]
 mod tests {
   use ast::select_property_value;
-  use tracing::Level;
 
   use super::*;
 
@@ -469,7 +681,10 @@ mod tests {
     let processed_ast = np.process();
     assert!(processed_ast.is_err());
     let errors = processed_ast.unwrap_err();
-    assert_eq!("Could not process property value", errors.error_msgs[0]);
+    assert_eq!(
+      "Could not process property value",
+      errors.diagnostics[0].message
+    );
   }
 
   #[test]
@@ -487,7 +702,7 @@ mod tests {
     let errors = processed_ast.unwrap_err();
     assert_eq!(
       "Did not find reference @second target for entity #first",
-      errors.error_msgs[0]
+      errors.diagnostics[0].message
     );
   }
 
@@ -514,7 +729,7 @@ mod tests {
     print!("{}", processed_ast.to_cdl().unwrap());
     let selected = select_property_value(&processed_ast, "value");
     let s = processed_ast.get_node(selected[0]).unwrap();
-    if let Node::Reference(node) = &(*s).node_data {
+    if let Node::Reference(node) = &s.node_data {
       assert_eq!(NodeRef(11), node.resolved_node.get());
     }
   }
@@ -540,17 +755,17 @@ custom properties #cp {
     let value = select_property_value(&processed_ast, "value")[0];
     let first = select_property_value(&processed_ast, "first");
     let s = processed_ast.get_node(first[0]).unwrap();
-    if let Node::Reference(node) = &(*s).node_data {
+    if let Node::Reference(node) = &s.node_data {
       assert_eq!(value, node.resolved_node.get());
     }
     let second = select_property_value(&processed_ast, "second");
     let s = processed_ast.get_node(second[0]).unwrap();
-    if let Node::Reference(node) = &(*s).node_data {
+    if let Node::Reference(node) = &s.node_data {
       assert_eq!(value, node.resolved_node.get());
     }
     let third = select_property_value(&processed_ast, "third");
     let s = processed_ast.get_node(third[0]).unwrap();
-    if let Node::Reference(node) = &(*s).node_data {
+    if let Node::Reference(node) = &s.node_data {
       assert_eq!(value, node.resolved_node.get());
     }
   }
@@ -576,7 +791,7 @@ custom properties #cp {
     //print!("{}", processed_ast.to_cdl().unwrap());
     let value = select_property_value(&processed_ast, "value");
     let resolved = processed_ast.get_node(value[1]).unwrap();
-    if let Node::Reference(node) = &(*resolved).node_data {
+    if let Node::Reference(node) = &resolved.node_data {
       assert_eq!(value[0], node.resolved_node.get());
     }
   }
@@ -601,7 +816,7 @@ custom properties #cp {
     print!("{}", processed_ast.to_cdl().unwrap());
     let value = select_property_value(&processed_ast, "value");
     let resolved = processed_ast.get_node(value[0]).unwrap();
-    if let Node::Reference(node) = &(*resolved).node_data {
+    if let Node::Reference(node) = &resolved.node_data {
       assert_eq!(value[1], node.resolved_node.get());
     }
   }
@@ -630,4 +845,27 @@ custom properties #cp {
     let processed_ast = np.process();
     assert!(processed_ast.is_ok())
   }
+
+  #[test]
+  fn unresolved_references_are_reported_once() {
+    let cdl = r#"
+    custom properties #base {
+      value: @nothing.value
+    }
+    page @base {
+      widget kpi @base {
+        label: "kpi"
+      }
+    }
+    "#;
+    let ast = parser::parse_text(cdl).unwrap();
+    let (_, error) = NodeProcessor::new(ast).process_partial();
+    let messages = error
+      .unwrap()
+      .diagnostics
+      .into_iter()
+      .map(|d| d.message)
+      .collect::<Vec<_>>();
+    assert_eq!(vec!["Could not process property value"], messages);
+  }
 }
@@ -0,0 +1,275 @@
+use std::{
+  fmt,
+  time::{Duration, Instant},
+};
+
+use ast::{Ast, NodeRef};
+use serde::Serialize;
+use tracing::info_span;
+
+use crate::{processing_context::ProcessingStatus, NodeProcessor};
+
+/// Processing phases, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+  /// Expands `instantiate` blocks.
+  Templates,
+  /// Copies the children of `@ref` targets into the entities referring to them.
+  Inheritance,
+  /// Resolves references in property values and binds `table:^hierarchy` paths.
+  ReferenceResolution,
+  /// Checks that do not change the ast, like unused constants.
+  Validation,
+  /// Rewrites the ast into its simplest form, like inlining constants.
+  Lowering,
+}
+
+impl Phase {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Phase::Templates => "templates",
+      Phase::Inheritance => "inheritance",
+      Phase::ReferenceResolution => "reference-resolution",
+      Phase::Validation => "validation",
+      Phase::Lowering => "lowering",
+    }
+  }
+}
+
+impl fmt::Display for Phase {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+  pub phase: Phase,
+  pub severity: Severity,
+  pub message: String,
+  pub node_ref: Option<NodeRef>,
+}
+
+impl Diagnostic {
+  pub fn error(phase: Phase, message: String, node_ref: Option<NodeRef>) -> Diagnostic {
+    Diagnostic {
+      phase,
+      severity: Severity::Error,
+      message,
+      node_ref,
+    }
+  }
+
+  pub fn warning(phase: Phase, message: String, node_ref: Option<NodeRef>) -> Diagnostic {
+    Diagnostic {
+      phase,
+      severity: Severity::Warning,
+      message,
+      node_ref,
+    }
+  }
+
+  pub fn is_error(&self) -> bool {
+    self.severity == Severity::Error
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseReport {
+  pub phase: Phase,
+  pub duration: Duration,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProcessingReport {
+  /// The phases that ran. Unless the manager continues on errors, phases after the first one
+  /// with errors are skipped.
+  pub phases: Vec<PhaseReport>,
+}
+
+impl ProcessingReport {
+  pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+    self.phases.iter().flat_map(|phase| &phase.diagnostics)
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.diagnostics().any(Diagnostic::is_error)
+  }
+}
+
+/// Runs the processing phases over an ast. Each phase runs in a `processing-phase` tracing span
+/// and its timing and diagnostics end up in the [`ProcessingReport`].
+#[derive(Debug, Clone)]
+pub struct PassManager {
+  phases: Vec<Phase>,
+  stop_on_error: bool,
+}
+
+impl Default for PassManager {
+  fn default() -> Self {
+    PassManager::new()
+  }
+}
+
+impl PassManager {
+  /// Everything except lowering.
+  pub fn new() -> PassManager {
+    PassManager::with_phases(&[
+      Phase::Templates,
+      Phase::Inheritance,
+      Phase::ReferenceResolution,
+      Phase::Validation,
+    ])
+  }
+
+  /// Phases always run in [`Phase`] order, whatever order they are given in.
+  pub fn with_phases(phases: &[Phase]) -> PassManager {
+    let mut phases = phases.to_vec();
+    phases.sort();
+    phases.dedup();
+    PassManager {
+      phases,
+      stop_on_error: true,
+    }
+  }
+
+  pub fn with_lowering(self) -> PassManager {
+    let mut phases = self.phases.clone();
+    phases.push(Phase::Lowering);
+    PassManager {
+      stop_on_error: self.stop_on_error,
+      ..PassManager::with_phases(&phases)
+    }
+  }
+
+  /// Runs every phase even after errors, to get as much of the ast processed as possible.
+  pub fn continue_on_error(self) -> PassManager {
+    PassManager {
+      stop_on_error: false,
+      ..self
+    }
+  }
+
+  pub fn run(&self, ast: Ast) -> (Ast, ProcessingReport) {
+    let processor = NodeProcessor::new(ast);
+    let report = self.run_processor(&processor);
+    (processor.into_ast(), report)
+  }
+
+  pub(crate) fn run_processor(&self, processor: &NodeProcessor) -> ProcessingReport {
+    let mut report = ProcessingReport::default();
+    for phase in &self.phases {
+      let span = info_span!("processing-phase", phase = phase.name());
+      let _entered = span.enter();
+      let start = Instant::now();
+      let diagnostics = processor.run_phase(*phase);
+      let status = phase_status(&diagnostics);
+      report.phases.push(PhaseReport {
+        phase: *phase,
+        duration: start.elapsed(),
+        diagnostics,
+      });
+      if self.stop_on_error && !status.is_complete() {
+        break;
+      }
+    }
+    report
+  }
+}
+
+fn phase_status(diagnostics: &[Diagnostic]) -> ProcessingStatus {
+  if diagnostics.iter().any(Diagnostic::is_error) {
+    ProcessingStatus::Incomplete
+  } else if diagnostics.is_empty() {
+    ProcessingStatus::Complete
+  } else {
+    ProcessingStatus::CompleteWithWarning
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn run(text: &str, manager: PassManager) -> (Ast, ProcessingReport) {
+    manager.run(parser::parse_text(text).unwrap())
+  }
+
+  #[test]
+  fn runs_phases_in_order() {
+    let manager = PassManager::with_phases(&[Phase::Validation, Phase::Templates]).with_lowering();
+    let (_, report) = run("config hub {\n  hub: 1\n}\n", manager);
+    let phases = report.phases.iter().map(|p| p.phase).collect::<Vec<_>>();
+    assert_eq!(
+      vec![Phase::Templates, Phase::Validation, Phase::Lowering],
+      phases
+    );
+    assert!(!report.has_errors());
+  }
+
+  #[test]
+  fn errors_stop_later_phases() {
+    let text = "config hub {\n  hub: 1\n}\ncustom properties #first @second {\n}\n";
+    let (_, report) = run(text, PassManager::new());
+    let last = report.phases.last().unwrap();
+    assert_eq!(Phase::Inheritance, last.phase);
+    assert_eq!(2, report.phases.len());
+    assert_eq!(
+      vec![Diagnostic::error(
+        Phase::Inheritance,
+        "Did not find reference @second target for entity #first".to_string(),
+        Some(NodeRef(4)),
+      )],
+      last.diagnostics
+    );
+  }
+
+  #[test]
+  fn can_continue_on_error() {
+    let text =
+      "custom properties #first @second {\n  value: 1\n}\npage {\n  value: @first.value\n}\n";
+    let (_, report) = run(text, PassManager::new().continue_on_error());
+    assert_eq!(4, report.phases.len());
+    assert_eq!(1, report.diagnostics().count());
+  }
+
+  #[test]
+  fn validation_gives_warnings() {
+    let text = "const unused = 1\nconfig hub {\n  hub: 1\n}\n";
+    let (ast, report) = run(text, PassManager::new());
+    let warnings = report.diagnostics().collect::<Vec<_>>();
+    assert_eq!(1, warnings.len());
+    assert_eq!(Severity::Warning, warnings[0].severity);
+    assert_eq!(Phase::Validation, warnings[0].phase);
+    assert!(ast.to_cdl().unwrap().contains("const unused"));
+
+    let (ast, report) = run(text, PassManager::new().with_lowering());
+    assert!(!report.has_errors());
+    assert!(!ast.to_cdl().unwrap().contains("const"));
+  }
+
+  #[test]
+  fn inherited_properties_can_be_referenced() {
+    let text = r#"config hub {
+  hub: 1
+}
+page #base {
+  color: "red"
+}
+page #child @base {
+  title: @child.color
+}
+"#;
+    let (_, report) = run(text, PassManager::new());
+    assert!(!report.has_errors(), "{:?}", report);
+  }
+}
@@ -1,11 +1,10 @@
-#[allow(dead_code)]
+use crate::Phase;
+
 #[derive(Debug, PartialEq, PartialOrd)]
 pub enum ProcessingStatus {
   Complete = 0,
   CompleteWithWarning = 1,
   Incomplete = 2,
-  ChildIncomplete = 3,
-  CompleteAndAbort = 4,
 }
 
 impl ProcessingStatus {
@@ -17,32 +16,18 @@ impl ProcessingStatus {
   }
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
-pub enum ProcessingStep {
-  Begin,
-  EntityRefs,
-  EntityVariables,
-  Children,
-  SpecialChildren,
-}
-
+/// Carried down the tree while processing, so work left for later knows which phase it is in.
 #[derive(Debug)]
 pub struct ProcessingContext {
-  #[allow(dead_code)]
-  step: ProcessingStep,
+  pub phase: Phase,
 }
 
 impl ProcessingContext {
-  pub fn new() -> ProcessingContext {
-    ProcessingContext {
-      step: ProcessingStep::Begin,
-    }
+  pub fn new(phase: Phase) -> ProcessingContext {
+    ProcessingContext { phase }
   }
 
   pub fn create_for_child(&self) -> ProcessingContext {
-    ProcessingContext {
-      step: ProcessingStep::Begin,
-    }
+    ProcessingContext { phase: self.phase }
   }
 }
@@ -0,0 +1,279 @@
+use std::{
+  collections::{BTreeMap, HashSet},
+  fmt,
+};
+
+use anyhow::Result;
+use ast::{Ast, Node, NodeRef};
+use parser::parse_text;
+use serde::Serialize;
+
+use crate::NodeProcessor;
+
+const LARGEST_ENTITIES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntitySize {
+  pub entity: String,
+  pub line: usize,
+  pub nodes: usize,
+}
+
+/// Size and complexity numbers for a script. Maps are sorted by key so reports from two releases
+/// can be diffed line by line.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ScriptStats {
+  pub source_bytes: usize,
+  pub source_lines: usize,
+  pub nodes: usize,
+  pub entities: BTreeMap<String, usize>,
+  pub properties: BTreeMap<String, usize>,
+  pub expression_depths: BTreeMap<usize, usize>,
+  pub references: usize,
+  pub unresolved_references: usize,
+  pub largest_entities: Vec<EntitySize>,
+}
+
+/// Parses and processes `text` and collects its stats. Processing errors are not fatal, they show
+/// up as unresolved references.
+pub fn script_stats(text: &str) -> Result<ScriptStats> {
+  let ast = parse_text(text)?;
+  let (ast, _) = NodeProcessor::new(ast).process_partial();
+  Ok(ScriptStats::collect(&ast, text))
+}
+
+impl ScriptStats {
+  /// Only counts the nodes each entity declares itself, children pulled in through `@ref` are
+  /// counted where they are declared.
+  pub fn collect(ast: &Ast, text: &str) -> ScriptStats {
+    let mut collector = Collector {
+      ast,
+      identifiers: HashSet::new(),
+      stats: ScriptStats {
+        source_bytes: text.len(),
+        source_lines: text.lines().count(),
+        ..Default::default()
+      },
+      sizes: vec![],
+      entity_refs: vec![],
+    };
+    collector.stats.nodes = collector.visit(ast.script_entity);
+    let Collector {
+      identifiers,
+      mut stats,
+      mut sizes,
+      entity_refs,
+      ..
+    } = collector;
+    stats.unresolved_references += entity_refs
+      .iter()
+      .filter(|r| !identifiers.contains(r.as_str()))
+      .count();
+    sizes.sort_by(|a, b| b.nodes.cmp(&a.nodes).then(a.line.cmp(&b.line)));
+    sizes.truncate(LARGEST_ENTITIES);
+    stats.largest_entities = sizes;
+    stats
+  }
+}
+
+struct Collector<'a> {
+  ast: &'a Ast,
+  identifiers: HashSet<String>,
+  stats: ScriptStats,
+  sizes: Vec<EntitySize>,
+  entity_refs: Vec<String>,
+}
+
+impl Collector<'_> {
+  /// Returns the number of nodes in the subtree owned by `node_ref`.
+  fn visit(&mut self, node_ref: NodeRef) -> usize {
+    let Some(node) = self.ast.get_node(node_ref) else {
+      return 0;
+    };
+    match &node.node_data {
+      Node::Entity(entity) => {
+        let path = entity
+          .terms
+          .iter()
+          .map(|t| t.to_string())
+          .collect::<Vec<_>>()
+          .join(" ");
+        *self.stats.entities.entry(path.clone()).or_default() += 1;
+        if let Some(ident) = &entity.ident {
+          self.identifiers.insert(ident.to_string());
+        }
+        self.stats.references += entity.refs.len();
+        self
+          .entity_refs
+          .extend(entity.refs.iter().map(|r| r.to_string()));
+      }
+      Node::Property(property) => {
+        *self
+          .stats
+          .properties
+          .entry(property.name.to_string())
+          .or_default() += 1;
+        for child in node.node_data.children() {
+          self.record_depth(child);
+        }
+      }
+      Node::Const(constant) => self.record_depth(constant.value.get()),
+      Node::Reference(reference) => {
+        self.stats.references += 1;
+        let in_expression = self
+          .ast
+          .get_parent(node_ref)
+          .first()
+          .and_then(|parent| self.ast.get_node(*parent))
+          .is_some_and(|parent| !matches!(parent.node_data, Node::Property(_) | Node::Const(_)));
+        // references in expressions are not required to resolve
+        if !in_expression && reference.resolved_node.get().0 < 0 {
+          self.stats.unresolved_references += 1;
+        }
+      }
+      _ => {}
+    }
+    let mut count = 1;
+    for child in node.node_data.children() {
+      if self.ast.get_parent(child).first() == Some(&node_ref) {
+        count += self.visit(child);
+      }
+    }
+    if let Node::Entity(entity) = &node.node_data {
+      self.sizes.push(EntitySize {
+        entity: match &entity.ident {
+          Some(ident) => format!("#{}", ident),
+          None => entity
+            .terms
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        },
+        line: self
+          .ast
+          .get_location_for_node(node_ref)
+          .map(|l| l.start_line)
+          .unwrap_or_default(),
+        nodes: count,
+      });
+    }
+    count
+  }
+
+  fn record_depth(&mut self, node_ref: NodeRef) {
+    let depth = expression_depth(self.ast, node_ref);
+    *self.stats.expression_depths.entry(depth).or_default() += 1;
+  }
+}
+
+fn expression_depth(ast: &Ast, node_ref: NodeRef) -> usize {
+  let Some(node) = ast.get_node(node_ref) else {
+    return 0;
+  };
+  match &node.node_data {
+    Node::Function(_) | Node::Operator(_) | Node::UnaryOperator(_) => {
+      1 + node
+        .node_data
+        .children()
+        .into_iter()
+        .map(|child| expression_depth(ast, child))
+        .max()
+        .unwrap_or_default()
+    }
+    _ => 1,
+  }
+}
+
+impl fmt::Display for ScriptStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "source: {} bytes, {} lines, {} nodes",
+      self.source_bytes, self.source_lines, self.nodes
+    )?;
+    writeln!(
+      f,
+      "references: {} ({} unresolved)",
+      self.references, self.unresolved_references
+    )?;
+    write_table(f, "entities", self.entities.iter())?;
+    write_table(f, "properties", self.properties.iter())?;
+    write_table(f, "expression depth", self.expression_depths.iter())?;
+    write_table(
+      f,
+      "largest entities (nodes)",
+      self
+        .largest_entities
+        .iter()
+        .map(|e| (format!("{} (line {})", e.entity, e.line), e.nodes)),
+    )
+  }
+}
+
+fn write_table<K: fmt::Display, V: fmt::Display>(
+  f: &mut fmt::Formatter<'_>,
+  title: &str,
+  rows: impl Iterator<Item = (K, V)>,
+) -> fmt::Result {
+  let rows = rows
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect::<Vec<_>>();
+  let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or_default();
+  writeln!(f, "\n{}:", title)?;
+  for (key, value) in rows {
+    writeln!(f, "  {:<width$}  {:>6}", key, value, width = width)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SCRIPT: &str = r#"title "Stats"
+config hub {
+  hub: 1
+}
+page #first {
+  widget kpi #a {
+    label: "A"
+    size: 1 + 2 * 3
+    link: @a.label
+  }
+  widget kpi @missing #b {
+    label: @nowhere
+    value: max(1, -@x)
+  }
+}
+"#;
+
+  #[test]
+  fn counts_entities_and_properties() {
+    let stats = script_stats(SCRIPT).unwrap();
+    assert_eq!(SCRIPT.len(), stats.source_bytes);
+    assert_eq!(15, stats.source_lines);
+    assert_eq!(Some(&2), stats.entities.get("widget kpi"));
+    assert_eq!(Some(&1), stats.entities.get("page"));
+    assert_eq!(Some(&2), stats.properties.get("label"));
+    assert_eq!(Some(&1), stats.properties.get("hub"));
+  }
+
+  #[test]
+  fn counts_depths_and_references() {
+    let stats = script_stats(SCRIPT).unwrap();
+    assert_eq!(BTreeMap::from([(1, 4), (3, 2)]), stats.expression_depths);
+    assert_eq!(4, stats.references);
+    assert_eq!(2, stats.unresolved_references);
+  }
+
+  #[test]
+  fn lists_largest_entities_first() {
+    let stats = script_stats(SCRIPT).unwrap();
+    assert_eq!("#first", stats.largest_entities[0].entity);
+    assert_eq!(5, stats.largest_entities[0].line);
+    assert_eq!(stats.nodes - 5, stats.largest_entities[0].nodes);
+    let text = stats.to_string();
+    assert!(text.contains("references: 4 (2 unresolved)"));
+  }
+}
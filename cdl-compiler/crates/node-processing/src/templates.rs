@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use ast::{Ast, AstNode, Node, NodeRef, QuoteKind};
+use lexer::{escape_string, LexedStr};
+
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+struct Template {
+  params: Vec<String>,
+  body: Vec<NodeRef>,
+}
+
+/// Expands templates in place. A template is an entity `template #name` with a `params`
+/// property listing `$parameters`, the other children are the body:
+///
+/// ```cdl
+/// template #filterFor {
+///   params: $dataset, $variable
+///   filter multiselect #filter_$variable {
+///     optionsFrom: $dataset:$variable
+///   }
+/// }
+/// instantiate @filterFor {
+///   args: surveyDataset, ITLOB
+///   args: surveyDataset, ITPLAN
+/// }
+/// ```
+///
+/// Every `args` set replaces the `instantiate` entity with a copy of the body. An identifier
+/// that is just a parameter is replaced by the argument node, parameters inside names, vpaths
+/// and strings are replaced by the text of the argument. Copied nodes keep the location of the
+/// template node, `Ast::get_expanded_from` gives the `args` property they were made for.
+pub fn expand_templates(ast: &Ast) -> Result<()> {
+  let mut templates = HashMap::new();
+  collect_templates(ast, ast.script_entity, &mut templates)?;
+  let expander = Expander { ast, templates };
+  expander.expand_children(ast.script_entity, 0)
+}
+
+fn entity_kind(ast: &Ast, node_ref: NodeRef) -> Option<&'static str> {
+  let node = ast.get_node(node_ref)?;
+  match &node.node_data {
+    Node::Entity(entity) if entity.terms.len() == 1 => match entity.terms[0].as_str() {
+      "template" => Some("template"),
+      "instantiate" => Some("instantiate"),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+fn error_at(ast: &Ast, node_ref: NodeRef, message: String) -> anyhow::Error {
+  match ast.get_location_for_node(node_ref) {
+    Some(location) => anyhow!(
+      "[{}:{}]: {}",
+      location.start_line,
+      location.start_pos,
+      message
+    ),
+    None => anyhow!(message),
+  }
+}
+
+fn collect_templates(
+  ast: &Ast,
+  parent: NodeRef,
+  templates: &mut HashMap<String, Template>,
+) -> Result<()> {
+  let Some(node) = ast.get_node(parent) else {
+    return Ok(());
+  };
+  let children = match &node.node_data {
+    Node::Script(script) => &script.children,
+    Node::Entity(entity) => &entity.children,
+    _ => return Ok(()),
+  };
+  let mut kept = vec![];
+  for child in children.borrow().iter().copied() {
+    if entity_kind(ast, child) != Some("template") {
+      collect_templates(ast, child, templates)?;
+      kept.push(child);
+      continue;
+    }
+    let template_node = ast.get_node(child).unwrap();
+    let Node::Entity(entity) = &template_node.node_data else {
+      unreachable!()
+    };
+    let name = entity
+      .ident
+      .as_ref()
+      .ok_or_else(|| error_at(ast, child, "Template is missing a #name".to_string()))?
+      .to_string();
+    let mut params = vec![];
+    let mut body = vec![];
+    for template_child in entity.children.borrow().iter().copied() {
+      let data = &ast.get_node(template_child).unwrap().node_data;
+      match data {
+        Node::Property(prop) if prop.name.as_str() == "params" => {
+          for param in prop.children.borrow().iter().copied() {
+            match &ast.get_node(param).unwrap().node_data {
+              Node::Identifier(id) if id.identifier.as_str().starts_with('$') => {
+                params.push(id.identifier.to_string())
+              }
+              _ => {
+                return Err(error_at(
+                  ast,
+                  param,
+                  "Template parameters must start with $".to_string(),
+                ))
+              }
+            }
+          }
+        }
+        _ if entity_kind(ast, template_child) == Some("template") => {
+          return Err(error_at(
+            ast,
+            template_child,
+            "Templates can not be nested".to_string(),
+          ))
+        }
+        _ => body.push(template_child),
+      }
+    }
+    if templates
+      .insert(name.clone(), Template { params, body })
+      .is_some()
+    {
+      return Err(error_at(
+        ast,
+        child,
+        format!("Template #{} is defined more than once", name),
+      ));
+    }
+  }
+  *children.borrow_mut() = kept;
+  Ok(())
+}
+
+struct Expander<'a> {
+  ast: &'a Ast,
+  templates: HashMap<String, Template>,
+}
+
+struct Instance<'a> {
+  params: &'a [String],
+  args: Vec<NodeRef>,
+  site: NodeRef,
+}
+
+impl<'a> Expander<'a> {
+  fn expand_children(&self, parent: NodeRef, depth: usize) -> Result<()> {
+    let Some(node) = self.ast.get_node(parent) else {
+      return Ok(());
+    };
+    let children = match &node.node_data {
+      Node::Script(script) => &script.children,
+      Node::Entity(entity) => &entity.children,
+      _ => return Ok(()),
+    };
+    let current = children.borrow().clone();
+    let expanded = self.expand_nodes(current, parent, depth)?;
+    *children.borrow_mut() = expanded;
+    Ok(())
+  }
+
+  fn expand_nodes(
+    &self,
+    nodes: Vec<NodeRef>,
+    parent: NodeRef,
+    depth: usize,
+  ) -> Result<Vec<NodeRef>> {
+    let mut expanded = Vec::with_capacity(nodes.len());
+    for node_ref in nodes {
+      if entity_kind(self.ast, node_ref) != Some("instantiate") {
+        self.expand_children(node_ref, depth)?;
+        expanded.push(node_ref);
+        continue;
+      }
+      if depth >= MAX_EXPANSION_DEPTH {
+        return Err(error_at(
+          self.ast,
+          node_ref,
+          "Template instantiation is nested too deep, is a template using itself?".to_string(),
+        ));
+      }
+      let generated = self.instantiate(node_ref, parent)?;
+      expanded.extend(self.expand_nodes(generated, parent, depth + 1)?);
+    }
+    Ok(expanded)
+  }
+
+  fn instantiate(&self, node_ref: NodeRef, parent: NodeRef) -> Result<Vec<NodeRef>> {
+    let node = self.ast.get_node(node_ref).unwrap();
+    let Node::Entity(entity) = &node.node_data else {
+      unreachable!()
+    };
+    let name = match entity.refs.as_slice() {
+      [name] => name.to_string(),
+      _ => {
+        return Err(error_at(
+          self.ast,
+          node_ref,
+          "instantiate needs exactly one @template".to_string(),
+        ))
+      }
+    };
+    let template = self
+      .templates
+      .get(&name)
+      .ok_or_else(|| error_at(self.ast, node_ref, format!("Unknown template @{}", name)))?;
+
+    let mut generated = vec![];
+    for child in entity.children.borrow().iter().copied() {
+      let args = match &self.ast.get_node(child).unwrap().node_data {
+        Node::Property(prop) if prop.name.as_str() == "args" => prop.children.borrow().clone(),
+        _ => {
+          return Err(error_at(
+            self.ast,
+            child,
+            "Only args properties are allowed in instantiate".to_string(),
+          ))
+        }
+      };
+      if args.len() != template.params.len() {
+        return Err(error_at(
+          self.ast,
+          child,
+          format!(
+            "Template @{} takes {} arguments, got {}",
+            name,
+            template.params.len(),
+            args.len()
+          ),
+        ));
+      }
+      let instance = Instance {
+        params: &template.params,
+        args,
+        site: child,
+      };
+      for body_ref in &template.body {
+        generated.push(self.copy_node(*body_ref, parent, Some(&instance), instance.site)?);
+      }
+    }
+    Ok(generated)
+  }
+
+  /// Deep copy of `node_ref`, with parameters substituted when an instance is given.
+  fn copy_node(
+    &self,
+    node_ref: NodeRef,
+    parent: NodeRef,
+    instance: Option<&Instance>,
+    site: NodeRef,
+  ) -> Result<NodeRef> {
+    let node = self.ast.get_node(node_ref).unwrap();
+    if let (Some(instance), Node::Identifier(id)) = (instance, &node.node_data) {
+      if let Some(index) = instance
+        .params
+        .iter()
+        .position(|param| param.as_str() == id.identifier.as_str())
+      {
+        return self.copy_node(instance.args[index], parent, None, site);
+      }
+    }
+
+    let mut node_data = node.node_data.clone();
+    let children = node_data
+      .children()
+      .into_iter()
+      .map(|child| self.copy_node(child, NodeRef(-1), instance, site))
+      .collect::<Result<Vec<_>>>()?;
+    node_data.set_children(children.clone());
+    if let Some(instance) = instance {
+      self.substitute(&mut node_data, instance)?;
+    }
+    let copy = self.ast.add_node(
+      AstNode::new(node_data, parent),
+      self.ast.get_pos_for_node(node_ref),
+    );
+    for child in children {
+      self.ast.set_parent_of_node(child, copy);
+    }
+    self.ast.set_expanded_from(copy, site);
+    Ok(copy)
+  }
+
+  fn substitute(&self, node_data: &mut Node, instance: &Instance) -> Result<()> {
+    let values = instance
+      .args
+      .iter()
+      .map(|arg| self.arg_text(*arg))
+      .collect::<Result<Vec<_>>>()?;
+    let replace = |text: &mut LexedStr| {
+      if let Some(new_text) = substitute_text(text.as_str(), instance.params, &values) {
+        *text = LexedStr::from(new_text.as_str());
+      }
+    };
+    let replace_option = |text: &mut Option<LexedStr>| {
+      if let Some(text) = text {
+        replace(text)
+      }
+    };
+    match node_data {
+      Node::Entity(entity) => {
+        entity.terms.iter_mut().for_each(replace);
+        replace_option(&mut entity.label);
+        entity.refs.iter_mut().for_each(replace);
+        replace_option(&mut entity.ident);
+      }
+      Node::Property(prop) => replace(&mut prop.name),
+      Node::Identifier(id) => replace(&mut id.identifier),
+      Node::String(string) => {
+        // the text is quoted again from the new value, arguments can hold quotes
+        if let Some(value) = substitute_text(string.value.as_str(), instance.params, &values) {
+          string.text = LexedStr::from(escape_string(&value).as_str());
+          string.value = LexedStr::from(value.as_str());
+          string.quote_kind = QuoteKind::DoubleQuote;
+        }
+      }
+      Node::VPath(vpath) => {
+        replace_option(&mut vpath.table);
+        replace_option(&mut vpath.variable);
+        replace_option(&mut vpath.function);
+      }
+      Node::Reference(reference) => replace(&mut reference.ident),
+      Node::Function(func) => replace(&mut func.name),
+      Node::TableAlias(alias) => {
+        replace(&mut alias.table);
+        replace(&mut alias.alias);
+      }
+      Node::Title(title) => replace(&mut title.title),
+      Node::Const(c) => replace(&mut c.name),
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn arg_text(&self, arg: NodeRef) -> Result<String> {
+    let node = self.ast.get_node(arg).unwrap();
+    Ok(match &node.node_data {
+      Node::Identifier(id) => id.identifier.to_string(),
+      Node::String(string) => string.value.to_string(),
+      _ => self.ast.node_to_cdl(arg)?.trim().to_string(),
+    })
+  }
+}
+
+/// Replaces `$parameters` in `text`, the longest matching parameter wins so `$a_$b` works when
+/// both `$a` and `$a_` could match. Returns `None` when nothing was replaced.
+fn substitute_text(text: &str, params: &[String], values: &[String]) -> Option<String> {
+  if !text.contains('$') {
+    return None;
+  }
+  let mut result = String::with_capacity(text.len());
+  let mut rest = text;
+  let mut replaced = false;
+  while let Some(index) = rest.find('$') {
+    result.push_str(&rest[..index]);
+    rest = &rest[index..];
+    let longest = params
+      .iter()
+      .enumerate()
+      .filter(|(_, param)| rest.starts_with(param.as_str()))
+      .max_by_key(|(_, param)| param.len());
+    match longest {
+      Some((param_index, param)) => {
+        result.push_str(&values[param_index]);
+        rest = &rest[param.len()..];
+        replaced = true;
+      }
+      None => {
+        result.push('$');
+        rest = &rest[1..];
+      }
+    }
+  }
+  result.push_str(rest);
+  replaced.then_some(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use parser::parse_text;
+
+  use super::*;
+
+  const SCRIPT: &str = r#"template #filterFor {
+  params: $dataset, $variable, $label
+  filter multiselect #fromQuestionFilter_$dataset_$variable {
+    optionsFrom: $dataset:$variable
+    label: $label
+    description: "Filter on $label"
+  }
+}
+layoutArea toolbar {
+  instantiate @filterFor {
+    args: NP, ITLOB, "Line of Business"
+    args: SA, ITPLAN, "Plan"
+  }
+}
+"#;
+
+  #[test]
+  fn can_expand_templates() {
+    let ast = parse_text(SCRIPT).unwrap();
+    expand_templates(&ast).unwrap();
+    assert_eq!(
+      r#"layoutArea toolbar {
+  filter multiselect #fromQuestionFilter_NP_ITLOB {
+    optionsFrom: NP:ITLOB
+    label: "Line of Business"
+    description: "Filter on Line of Business"
+  }
+  filter multiselect #fromQuestionFilter_SA_ITPLAN {
+    optionsFrom: SA:ITPLAN
+    label: "Plan"
+    description: "Filter on Plan"
+  }
+}
+"#,
+      ast.to_cdl().unwrap()
+    );
+  }
+
+  #[test]
+  fn generated_nodes_map_back_to_the_template() {
+    let ast = parse_text(SCRIPT).unwrap();
+    expand_templates(&ast).unwrap();
+    let toolbar = ast
+      .get_node(ast.script_entity)
+      .unwrap()
+      .node_data
+      .children()[0];
+    let second = ast.get_node(toolbar).unwrap().node_data.children()[1];
+    let location = ast.get_location_for_node(second).unwrap();
+    assert_eq!(3, location.start_line);
+    let site = ast.get_expanded_from(second).unwrap();
+    assert_eq!(12, ast.get_location_for_node(site).unwrap().start_line);
+  }
+
+  #[test]
+  fn processing_expands_templates() {
+    let ast = parse_text(SCRIPT).unwrap();
+    let processed = crate::NodeProcessor::new(ast).process().unwrap();
+    let cdl = processed.to_cdl().unwrap();
+    assert_eq!(2, cdl.matches("optionsFrom").count());
+    assert!(!cdl.contains("template"));
+  }
+
+  #[test]
+  fn quotes_in_arguments_are_escaped_in_strings() {
+    let ast = parse_text(&SCRIPT.replace("\"Plan\"", r#""The \"Plan\"""#)).unwrap();
+    expand_templates(&ast).unwrap();
+    let cdl = ast.to_cdl().unwrap();
+    assert!(cdl.contains(r#"description: "Filter on The \"Plan\"""#));
+    assert_eq!(cdl, parse_text(&cdl).unwrap().to_cdl().unwrap());
+  }
+
+  #[test]
+  fn wrong_number_of_arguments_gives_error() {
+    let ast =
+      parse_text(&SCRIPT.replace("args: SA, ITPLAN, \"Plan\"", "args: SA, ITPLAN")).unwrap();
+    let error = expand_templates(&ast).unwrap_err();
+    assert_eq!(
+      "[12:5]: Template @filterFor takes 3 arguments, got 2",
+      error.to_string()
+    );
+  }
+
+  #[test]
+  fn unknown_template_gives_error() {
+    let ast = parse_text("instantiate @missing {\n  args: a\n}\n").unwrap();
+    let error = expand_templates(&ast).unwrap_err();
+    assert_eq!("[1:1]: Unknown template @missing", error.to_string());
+  }
+
+  #[test]
+  fn recursive_template_gives_error() {
+    let ast = parse_text(
+      "template #loop {\n  params: $a\n  instantiate @loop {\n    args: $a\n  }\n}\ninstantiate @loop {\n  args: x\n}\n",
+    )
+    .unwrap();
+    let error = expand_templates(&ast).unwrap_err();
+    assert!(error.to_string().contains("nested too deep"));
+  }
+
+  #[test]
+  fn longest_parameter_wins() {
+    let params = vec!["$a".to_string(), "$ab".to_string()];
+    let values = vec!["1".to_string(), "2".to_string()];
+    assert_eq!(
+      Some("x2_1$c".to_string()),
+      substitute_text("x$ab_$a$c", &params, &values)
+    );
+    assert_eq!(None, substitute_text("plain", &params, &values));
+  }
+}
@@ -1,5 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use parser::parse_text;
+use lexer::lex_borrowed;
+use parser::{parse_text, parse_tokens};
 
 fn criterion_benchmark(c: &mut Criterion) {
   let file = include_str!("../../../test_script/test.cdl");
@@ -7,6 +8,10 @@ fn criterion_benchmark(c: &mut Criterion) {
   c.bench_function("parse large file (30k lines, 780kb)", |b| {
     b.iter(|| parse_text(black_box(file)))
   });
+  // includes lexing and the conversion to owned tokens, to compare with parse_text end to end
+  c.bench_function("parse large file from borrowed tokens", |b| {
+    b.iter(|| parse_tokens(file, lex_borrowed(black_box(file)).unwrap()))
+  });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,50 @@
+use anyhow::{bail, Result};
+use ast::{AstConstNode, AstNode, Node, NodeRef};
+use lexer::TokenKind;
+
+use crate::{parse_expr::parse_expression, parser::Parser};
+
+use super::Parsable;
+
+impl Parsable for AstConstNode {
+  fn can_parse(parser: &Parser) -> bool {
+    let const_token = parser.get_current_token();
+    let name_token = parser.get_next_token(1);
+    let equal_token = parser.get_next_token(2);
+    let (Ok(const_token), Ok(name_token), Ok(equal_token)) = (const_token, name_token, equal_token)
+    else {
+      return false;
+    };
+    const_token.kind == TokenKind::Identifier
+      && const_token.text == Some("const".into())
+      && name_token.kind == TokenKind::Identifier
+      && equal_token.kind == TokenKind::Equal
+  }
+
+  fn parse(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
+    let (node_ref, start_pos) = {
+      let const_token = parser.get_current_token()?;
+      let name_token = parser.get_next_token(1)?;
+      let ast_node = AstConstNode::new(name_token.text.as_ref().unwrap().clone());
+      let node_ref = parser.add_node(
+        AstNode::new(Node::Const(ast_node), parent),
+        const_token.pos.start..usize::MAX,
+      );
+      (node_ref, const_token.pos.start)
+    };
+    parser.eat_tokens(3)?;
+    let value = parse_expression(parser, node_ref)?;
+    parser.add_child_to_node(node_ref, value);
+    let end_pos = parser.get_pos_for_node(value).end;
+    let next_token = parser.get_current_token()?;
+    match next_token.kind {
+      TokenKind::EOL => {
+        parser.eat_token()?;
+      }
+      TokenKind::BraceClose => {}
+      _ => bail!("Tried parsing const, did not find EOL when expected"),
+    }
+    parser.update_location_on_node(node_ref, start_pos, end_pos);
+    Ok(node_ref)
+  }
+}
@@ -3,6 +3,7 @@ use crate::parser::Parser;
 use super::Parsable;
 use anyhow::anyhow;
 use anyhow::Result;
+use ast::AstConstNode;
 use ast::AstEntityNode;
 use ast::AstNode;
 use ast::AstPropertyNode;
@@ -17,6 +18,7 @@ use log::trace;
 struct EntityHeaderInfo {
   terms: Vec<LexedStr>,
   start_loc: usize,
+  end_loc: usize,
   label: Option<LexedStr>,
   refs: Vec<LexedStr>,
   ident: Option<LexedStr>,
@@ -25,9 +27,7 @@ struct EntityHeaderInfo {
 
 impl Parsable for AstEntityNode {
   fn can_parse(parser: &Parser) -> bool {
-    let next_token = parser.get_current_token();
-    if next_token.is_ok() {
-      let next_token = next_token.unwrap();
+    if let Ok(next_token) = parser.get_current_token() {
       if next_token.kind == TokenKind::Identifier {
         return true;
       }
@@ -37,59 +37,78 @@ impl Parsable for AstEntityNode {
 
   #[tracing::instrument(name = "parse_entity", skip(parser, parent))]
   fn parse(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
-    let header = parse_entity_header(parser)?;
-    let entity = AstEntityNode {
-      children: vec![].into(),
-      terms: header.terms,
-      label: header.label,
-      refs: header.refs,
-      ident: header.ident,
-      entity_number: header.entity_number,
-    };
-    let ast_node = AstNode::new(Node::Entity(entity), parent);
-    let current_entity_ref = parser.add_node(ast_node, header.start_loc..usize::MAX);
-    trace!("current_entity_ref {:?}", current_entity_ref);
-    let next_token = parser.get_current_token()?;
-    if next_token.kind == TokenKind::EOL {
-      return Ok(current_entity_ref);
+    parser.enter()?;
+    let node_ref = parse_entity(parser, parent)?;
+    parser.leave();
+    Ok(node_ref)
+  }
+}
+
+fn parse_entity(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
+  let header = parse_entity_header(parser)?;
+  let is_config = header.terms.first().map(|t| t.as_str()) == Some("config");
+  let entity = AstEntityNode {
+    children: vec![].into(),
+    terms: header.terms,
+    label: header.label,
+    refs: header.refs,
+    ident: header.ident,
+    entity_number: header.entity_number,
+  };
+  let ast_node = AstNode::new(Node::Entity(entity), parent);
+  let current_entity_ref = parser.add_node(ast_node, header.start_loc..usize::MAX);
+  trace!("current_entity_ref {:?}", current_entity_ref);
+  let next_token = parser.get_current_token()?;
+  if next_token.kind == TokenKind::EOL {
+    parser.update_location_on_node(current_entity_ref, header.start_loc, header.end_loc);
+    return Ok(current_entity_ref);
+  }
+  parser.eat_token_of_type(TokenKind::BraceOpen)?;
+  loop {
+    parser.eat_eol_and_comments();
+    if AstPropertyNode::can_parse(parser) {
+      let child_node_ref = AstPropertyNode::parse(parser, current_entity_ref)?;
+      parser.add_child_to_node(current_entity_ref, child_node_ref);
+      continue;
     }
-    parser.eat_token_of_type(TokenKind::BraceOpen)?;
-    loop {
-      parser.eat_eol_and_comments();
-      if AstPropertyNode::can_parse(parser) {
-        let child_node_ref = AstPropertyNode::parse(parser, current_entity_ref)?;
-        parser.add_child_to_node(current_entity_ref, child_node_ref);
-        continue;
-      }
-      if AstTableAliasNode::can_parse(parser) {
-        // if !is_config_hub {
-        //   return Err(anyhow!("Table Alias not allowed outside config hub"));
-        // }
-        let child_node_ref = AstTableAliasNode::parse(parser, current_entity_ref)?;
-        parser.add_child_to_node(current_entity_ref, child_node_ref);
-        continue;
-      }
-      if AstEntityNode::can_parse(parser) {
-        let child_node_ref = AstEntityNode::parse(parser, current_entity_ref)?;
-        parser.add_child_to_node(current_entity_ref, child_node_ref);
-        continue;
+    if AstTableAliasNode::can_parse(parser) {
+      // if !is_config_hub {
+      //   return Err(anyhow!("Table Alias not allowed outside config hub"));
+      // }
+      let child_node_ref = AstTableAliasNode::parse(parser, current_entity_ref)?;
+      parser.add_child_to_node(current_entity_ref, child_node_ref);
+      continue;
+    }
+    if AstConstNode::can_parse(parser) {
+      if !is_config {
+        let pos = parser.get_current_token()?.pos.clone();
+        return Err(parser.error_at(
+          pos,
+          "const is only allowed at the top level and in config blocks",
+        ));
       }
+      let child_node_ref = AstConstNode::parse(parser, current_entity_ref)?;
+      parser.add_child_to_node(current_entity_ref, child_node_ref);
+      continue;
+    }
+    if AstEntityNode::can_parse(parser) {
+      let child_node_ref = AstEntityNode::parse(parser, current_entity_ref)?;
+      parser.add_child_to_node(current_entity_ref, child_node_ref);
+      continue;
+    }
 
-      let curr_token = parser.get_current_token()?;
-      if curr_token.kind == TokenKind::BraceClose {
-        parser.eat_token()?;
-        parser.update_location_on_node(current_entity_ref, header.start_loc, curr_token.pos.end);
-        return Ok(current_entity_ref);
-      }
-      return Err(anyhow!("Unexpected error while parsing entity"));
+    let curr_token = parser.get_current_token()?;
+    if curr_token.kind == TokenKind::BraceClose {
+      parser.eat_token()?;
+      parser.update_location_on_node(current_entity_ref, header.start_loc, curr_token.pos.end);
+      return Ok(current_entity_ref);
     }
+    return Err(anyhow!("Unexpected error while parsing entity"));
   }
 }
 
 pub fn can_parse_anonymous_entity(parser: &Parser) -> bool {
-  let next_token = parser.get_current_token();
-  if next_token.is_ok() {
-    let next_token = next_token.unwrap();
+  if let Ok(next_token) = parser.get_current_token() {
     if next_token.kind == TokenKind::BraceOpen {
       return true;
     }
@@ -98,6 +117,13 @@ pub fn can_parse_anonymous_entity(parser: &Parser) -> bool {
 }
 
 pub fn parse_anonymous_entity(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
+  parser.enter()?;
+  let node_ref = parse_anonymous_entity_body(parser, parent)?;
+  parser.leave();
+  Ok(node_ref)
+}
+
+fn parse_anonymous_entity_body(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
   let open_brace_token_pos = {
     let open_brace_token = parser.get_current_token()?;
     open_brace_token.pos.start
@@ -147,6 +173,7 @@ pub fn parse_anonymous_entity(parser: &mut Parser, parent: NodeRef) -> Result<No
 fn parse_entity_header(parser: &mut Parser) -> Result<EntityHeaderInfo> {
   let terms = parser.get_tokens_of_kind(TokenKind::Identifier);
   let start_loc = terms[0].pos.start;
+  let mut end_loc = terms[terms.len() - 1].pos.end;
   let terms = terms
     .iter()
     .map(|t| t.text.as_ref().unwrap().clone())
@@ -155,7 +182,7 @@ fn parse_entity_header(parser: &mut Parser) -> Result<EntityHeaderInfo> {
 
   let label_token = parser.get_tokens_of_kind(TokenKind::String);
   let label = if !label_token.is_empty() {
-    parser.eat_token()?;
+    end_loc = parser.eat_token()?.end;
     label_token[0].text.clone()
   } else {
     None
@@ -167,28 +194,33 @@ fn parse_entity_header(parser: &mut Parser) -> Result<EntityHeaderInfo> {
     if parser.is_next_token_of_type(TokenKind::Reference) {
       let ref_token = parser.get_current_token()?;
       ref_tokens.push(ref_token.text.clone().unwrap());
+      end_loc = ref_token.pos.end;
       let _ = parser.eat_token();
       continue;
     }
     if parser.is_next_token_of_type(TokenKind::Hash) {
-      let ident_token = parser.get_next_token(1);
-      if ident_token.is_ok() {
-        parser.eat_tokens(2)?;
-        ident = ident_token.unwrap().text.clone()
+      if let Ok(ident_token) = parser.get_next_token(1) {
+        end_loc = parser.eat_tokens(2)?.end;
+        ident = ident_token.text.clone()
       } else {
         ident = None
       }
-        continue;
+      continue;
+    }
+    if parser.is_next_token_of_type(TokenKind::Color) {
+      // idents like #abcdef are lexed as colors
+      let ident_token = parser.get_current_token()?;
+      ident = ident_token.text.clone();
+      end_loc = parser.eat_token()?.end;
+      continue;
     }
     break;
   }
-  
+
   let entity_number = {
-    let next_token = parser.get_current_token();
-    if next_token.is_ok() {
-      let next_token = next_token.unwrap();
+    if let Ok(next_token) = parser.get_current_token() {
       if let TokenKind::Number(entity_number) = next_token.kind {
-        parser.eat_token()?;
+        end_loc = parser.eat_token()?.end;
         Some(entity_number)
       } else {
         None
@@ -201,8 +233,9 @@ fn parse_entity_header(parser: &mut Parser) -> Result<EntityHeaderInfo> {
   Ok(EntityHeaderInfo {
     terms,
     start_loc,
+    end_loc,
     label,
-    refs : ref_tokens,
+    refs: ref_tokens,
     ident,
     entity_number,
   })
@@ -1,6 +1,6 @@
 use super::Parsable;
 use crate::parser::Parser;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use ast::{AstNode, AstNumberNode, Node, NodeRef};
 use lexer::TokenKind;
 
@@ -11,25 +11,23 @@ impl Parsable for AstNumberNode {
       return false;
     }
     let curr_token = curr_token.unwrap();
-    if let TokenKind::Number(_num) = curr_token.kind {
-      return true;
-    }
-    false
+    matches!(
+      curr_token.kind,
+      TokenKind::Number(_) | TokenKind::Percentage(_)
+    )
   }
 
   fn parse(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
     let number_token = parser.get_current_token()?;
-    let mut value = match number_token.kind {
+    let raw = number_token.text.clone();
+    let value = match number_token.kind {
       TokenKind::Number(num) => num,
+      TokenKind::Percentage(num) => num,
       _ => return Err(anyhow!("Did not find number when trying to parse a number")),
     };
+    check_percentage_is_separated(parser)?;
     parser.eat_token()?;
-    let maybe_percent_token = parser.get_current_token()?;
-    if maybe_percent_token.kind == TokenKind::Percent {
-      value /= 100.0;
-      parser.eat_token()?;
-    }
-    let ast_node = AstNumberNode { value };
+    let ast_node = AstNumberNode { value, raw };
     let node_ref = parser.add_node(
       AstNode::new(Node::Number(ast_node), parent),
       number_token.pos.clone(),
@@ -37,3 +35,25 @@ impl Parsable for AstNumberNode {
     Ok(node_ref)
   }
 }
+
+/// A `%` right after a number makes it a percentage, so `10%3` would be the percentage `10%`
+/// directly followed by `3`. That is rejected, the remainder is written `10 % 3`.
+pub(crate) fn check_percentage_is_separated(parser: &Parser) -> Result<()> {
+  let token = parser.get_current_token()?;
+  if !matches!(token.kind, TokenKind::Percentage(_)) {
+    return Ok(());
+  }
+  if let Ok(next) = parser.get_next_token(1) {
+    let is_operand = matches!(
+      next.kind,
+      TokenKind::Number(_)
+        | TokenKind::Percentage(_)
+        | TokenKind::Identifier
+        | TokenKind::ParenOpen
+    );
+    if is_operand && next.pos.start == token.pos.end {
+      bail!("Expected a space after a percentage, the remainder operator is written `a % b`");
+    }
+  }
+  Ok(())
+}
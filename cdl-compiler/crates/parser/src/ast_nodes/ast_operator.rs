@@ -1,46 +1,25 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 
-use ast::{AstNode, AstOperatorNode, Node, NodeRef, Operator};
+use ast::{
+  AstNode, AstNumberNode, AstOperatorNode, AstUnaryOperatorNode, Node, NodeRef, Operator,
+  UnaryOperator,
+};
 use lexer::TokenKind;
 
-use crate::{parse_expr::parse_expression, parser::Parser};
+use crate::{
+  ast_nodes::ast_number::check_percentage_is_separated,
+  parse_expr::{parse_expression_bp, parse_infix, skip_line_breaks},
+  parser::Parser,
+};
 
-pub fn can_parse_term(parser: &mut Parser) -> bool {
-  let curr_token = parser.get_current_token();
-  if curr_token.is_err() {
-    return false;
-  }
-  let curr_token = curr_token.unwrap();
-  matches!(
-    curr_token.kind,
-    TokenKind::Div
-      | TokenKind::Mul
-      | TokenKind::Equal
-      | TokenKind::NotEqual
-      | TokenKind::LessThan
-      | TokenKind::LessThanOrEqual
-      | TokenKind::MoreThan
-      | TokenKind::MoreThanOrEqual
-      | TokenKind::And
-      | TokenKind::Or
-  )
-}
-
-pub fn can_parse_factor(parser: &mut Parser) -> bool {
-  let curr_token = parser.get_current_token();
-  if curr_token.is_err() {
-    return false;
-  }
-  let curr_token = curr_token.unwrap();
-  matches!(curr_token.kind, TokenKind::Plus | TokenKind::Minus)
-}
-
-pub fn parse_operator_term(parser: &mut Parser, parent: NodeRef, left: NodeRef) -> Result<NodeRef> {
-  let operator_token = parser.get_current_token()?;
-  parser.eat_token()?;
-  let operator = match operator_token.kind {
-    TokenKind::Div => Operator::Div,
+pub fn peek_binary_operator(parser: &Parser) -> Option<Operator> {
+  let curr_token = parser.get_current_token().ok()?;
+  let operator = match curr_token.kind {
+    TokenKind::Plus => Operator::Plus,
+    TokenKind::Minus => Operator::Minus,
     TokenKind::Mul => Operator::Mul,
+    TokenKind::Div => Operator::Div,
+    TokenKind::Percent => Operator::Mod,
     TokenKind::Equal => Operator::Equal,
     TokenKind::NotEqual => Operator::NotEqual,
     TokenKind::LessThan => Operator::LessThan,
@@ -49,42 +28,146 @@ pub fn parse_operator_term(parser: &mut Parser, parent: NodeRef, left: NodeRef)
     TokenKind::MoreThanOrEqual => Operator::MoreThanOrEqual,
     TokenKind::And => Operator::And,
     TokenKind::Or => Operator::Or,
-    _ => return Err(anyhow!("Unknown token when parsing operator")),
+    _ if is_negative_number(parser) => Operator::Minus,
+    _ => return None,
   };
+  Some(operator)
+}
+
+/// `a -1` is lexed as `a` followed by the number `-1`, in operator position it is a subtraction.
+fn is_negative_number(parser: &Parser) -> bool {
+  match parser.get_current_token() {
+    Ok(token) => {
+      matches!(token.kind, TokenKind::Number(_) | TokenKind::Percentage(_))
+        && token
+          .text
+          .as_ref()
+          .map(|text| text.as_str().starts_with('-'))
+          .unwrap_or(false)
+    }
+    Err(_) => false,
+  }
+}
+
+pub fn parse_binary_operator(
+  parser: &mut Parser,
+  parent: NodeRef,
+  left: NodeRef,
+  operator: Operator,
+) -> Result<NodeRef> {
+  let left_pos = parser.get_pos_for_node(left);
+  if operator == Operator::Mod && parser.get_current_token()?.pos.start == left_pos.end {
+    bail!("Expected a space before `%`, a `%` right after a value is a percentage");
+  }
   let operator_node = AstOperatorNode::new(operator, left, NodeRef(0));
   let operator_node_ref = parser.add_node(
     AstNode::new(Node::Operator(operator_node), parent),
-    operator_token.pos.start..usize::MAX,
+    left_pos.start..usize::MAX,
   );
-  let right_node = parse_expression(parser, operator_node_ref)?;
+  parser.set_parent_of_node(left, operator_node_ref);
+  let right_node = if is_negative_number(parser) {
+    let number_ref = parse_negated_number(parser, operator_node_ref)?;
+    parse_infix(parser, operator_node_ref, number_ref, operator.precedence())?
+  } else {
+    parser.eat_token()?;
+    skip_line_breaks(parser)?;
+    parse_expression_bp(parser, operator_node_ref, operator.precedence())?
+  };
   parser.add_child_to_node(operator_node_ref, right_node);
-  let left_pos = parser.get_pos_for_node(left);
   let right_pos = parser.get_pos_for_node(right_node);
   parser.update_location_on_node(operator_node_ref, left_pos.start, right_pos.end);
   Ok(operator_node_ref)
 }
 
-pub fn parse_operator_factor(
-  parser: &mut Parser,
-  parent: NodeRef,
-  left: NodeRef,
-) -> Result<NodeRef> {
-  let operator_token = parser.get_current_token()?;
+fn parse_negated_number(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
+  let number_token = parser.get_current_token()?;
+  let value = match number_token.kind {
+    TokenKind::Number(num) => -num,
+    TokenKind::Percentage(num) => -num,
+    _ => return Err(anyhow!("Did not find number when trying to parse a number")),
+  };
+  check_percentage_is_separated(parser)?;
+  let raw = number_token
+    .text
+    .as_ref()
+    .map(|text| text.as_str()[1..].into());
+  let ast_node = AstNumberNode { value, raw };
+  let node_ref = parser.add_node(
+    AstNode::new(Node::Number(ast_node), parent),
+    number_token.pos.start + 1..number_token.pos.end,
+  );
   parser.eat_token()?;
-  let operator = match operator_token.kind {
-    TokenKind::Plus => Operator::Plus,
-    TokenKind::Minus => Operator::Minus,
-    _ => return Err(anyhow!("Unknown token when parsing operator")),
+  Ok(node_ref)
+}
+
+pub fn can_parse_unary_operator(parser: &Parser) -> bool {
+  parser.is_next_token_of_type(TokenKind::Minus) || is_prefix_not(parser)
+}
+
+/// `not` is lexed as an identifier so it can still name properties and values. It is the unary
+/// operator only in prefix position, when an operand follows it.
+fn is_prefix_not(parser: &Parser) -> bool {
+  let is_not = match parser.get_current_token() {
+    Ok(token) => {
+      token.kind == TokenKind::Identifier
+        && token
+          .text
+          .as_ref()
+          .map(|text| text.as_str().eq_ignore_ascii_case("not"))
+          .unwrap_or(false)
+    }
+    Err(_) => false,
   };
-  let operator_node = AstOperatorNode::new(operator, left, NodeRef(0));
+  is_not
+    && match parser.get_next_token(1) {
+      Ok(next) => {
+        !matches!(
+          next.kind,
+          TokenKind::EOL
+            | TokenKind::Comma
+            | TokenKind::ParenClose
+            | TokenKind::BraceClose
+            | TokenKind::BracketClose
+            | TokenKind::LineComment
+        ) && !is_binary_operator(&next.kind)
+      }
+      Err(_) => false,
+    }
+}
+
+fn is_binary_operator(kind: &TokenKind) -> bool {
+  matches!(
+    kind,
+    TokenKind::Plus
+      | TokenKind::Mul
+      | TokenKind::Div
+      | TokenKind::Percent
+      | TokenKind::Equal
+      | TokenKind::NotEqual
+      | TokenKind::LessThan
+      | TokenKind::LessThanOrEqual
+      | TokenKind::MoreThan
+      | TokenKind::MoreThanOrEqual
+      | TokenKind::And
+      | TokenKind::Or
+  )
+}
+
+pub fn parse_unary_operator(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
+  let operator = match parser.get_current_token()?.kind {
+    TokenKind::Minus => UnaryOperator::Minus,
+    TokenKind::Identifier if is_prefix_not(parser) => UnaryOperator::Not,
+    _ => return Err(anyhow!("Unknown token when parsing unary operator")),
+  };
+  let start = parser.eat_token()?.start;
+  let operator_node = AstUnaryOperatorNode::new(operator, NodeRef(0));
   let operator_node_ref = parser.add_node(
-    AstNode::new(Node::Operator(operator_node), parent),
-    operator_token.pos.start..usize::MAX,
+    AstNode::new(Node::UnaryOperator(operator_node), parent),
+    start..usize::MAX,
   );
-  let right_node = parse_expression(parser, operator_node_ref)?;
-  parser.add_child_to_node(operator_node_ref, right_node);
-  let left_pos = parser.get_pos_for_node(left);
-  let right_pos = parser.get_pos_for_node(right_node);
-  parser.update_location_on_node(operator_node_ref, left_pos.start, right_pos.end);
+  let expr = parse_expression_bp(parser, operator_node_ref, operator.precedence())?;
+  parser.add_child_to_node(operator_node_ref, expr);
+  let expr_pos = parser.get_pos_for_node(expr);
+  parser.update_location_on_node(operator_node_ref, start, expr_pos.end);
   Ok(operator_node_ref)
 }
@@ -32,22 +32,26 @@ impl Parsable for AstPropertyNode {
       );
       (node_ref, name_token.pos.start)
     };
-    parser.eat_tokens(2)?;
+    let colon_end = parser.eat_tokens(2)?.end;
     let children = parse_list(parser, node_ref)?;
     let next_token = parser.get_current_token()?;
 
     let last_token_end = if next_token.kind == TokenKind::BraceClose {
-      &next_token.pos
+      // the closing brace belongs to the parent entity
+      match children.last() {
+        Some(last_child) => parser.get_pos_for_node(*last_child).end,
+        None => colon_end,
+      }
     } else if next_token.kind == TokenKind::EOL {
       parser.eat_token()?;
-      &next_token.pos
+      next_token.pos.end
     } else {
       bail!("Tried parsing property, did not find EOL when expected");
     };
     // let last_token_end = parser
     //   .eat_token_of_type(TokenKind::EOL)
     //   .expect("Tried parsing property, did not find EOL when expected");
-    parser.update_location_on_node(node_ref, start_pos, last_token_end.end);
+    parser.update_location_on_node(node_ref, start_pos, last_token_end);
     children
       .iter()
       .for_each(|c| parser.add_child_to_node(node_ref, *c));
@@ -1,6 +1,6 @@
 use anyhow::bail;
 use anyhow::Result;
-use ast::{AstEntityNode, AstNode, AstScriptNode, AstTitleNode, Node, NodeRef};
+use ast::{AstConstNode, AstEntityNode, AstNode, AstScriptNode, AstTitleNode, Node, NodeRef};
 
 use crate::parser::Parser;
 
@@ -12,18 +12,28 @@ impl Parsable for AstScriptNode {
   }
 
   fn parse(parser: &mut Parser, _: NodeRef) -> Result<NodeRef> {
-    let root_node = AstScriptNode { children: vec![].into() };
+    let root_node = AstScriptNode {
+      children: vec![].into(),
+    };
     let root_node_ref = parser.add_node(
       AstNode::new(Node::Script(root_node), NodeRef(-1)),
       0..usize::MAX,
     );
-    while parser.is_tokens_left() {
+    loop {
       parser.eat_eol_and_comments();
+      if parser.get_current_token().is_err() {
+        break;
+      }
       if AstTitleNode::can_parse(parser) {
         let node_ref = AstTitleNode::parse(parser, root_node_ref)?;
         parser.add_child_to_node(root_node_ref, node_ref);
         continue;
       }
+      if AstConstNode::can_parse(parser) {
+        let node_ref = AstConstNode::parse(parser, root_node_ref)?;
+        parser.add_child_to_node(root_node_ref, node_ref);
+        continue;
+      }
       if AstEntityNode::can_parse(parser) {
         let node_ref = AstEntityNode::parse(parser, root_node_ref)?;
         parser.add_child_to_node(root_node_ref, node_ref);
@@ -35,6 +45,7 @@ impl Parsable for AstScriptNode {
         token.kind
       );
     }
+    parser.update_location_on_node(root_node_ref, 0, parser.text_len());
     Ok(root_node_ref)
   }
 }
@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use ast::{AstNode, AstStringNode, Node, NodeRef, QuoteKind};
-use lexer::TokenKind;
+use lexer::{unescape_string, TokenKind};
 
 use crate::parser::Parser;
 
@@ -24,12 +24,21 @@ impl Parsable for AstStringNode {
     let string_token = parser.get_current_token()?;
     let text = string_token.text.clone().unwrap();
     //parser.trace("Parsing String");
-    let quote_kind = if text.0.starts_with('\'') {
+    let quote_kind = if text.0.starts_with('r') {
+      QuoteKind::Raw
+    } else if text.0.starts_with("\"\"\"") {
+      QuoteKind::TripleQuote
+    } else if text.0.starts_with('\'') {
       QuoteKind::SingleQuote
     } else {
       QuoteKind::DoubleQuote
     };
-    let ast_node = AstStringNode { text, quote_kind };
+    let value = unescape_string(text.as_str())?.as_str().into();
+    let ast_node = AstStringNode {
+      text,
+      value,
+      quote_kind,
+    };
     let node_ref = parser.add_node(
       AstNode::new(Node::String(ast_node), parent),
       string_token.pos.clone(),
@@ -46,6 +46,7 @@ impl Parsable for AstVPathNode {
             variable: None,
             function: third_token.text.clone(),
             is_hierarchy: false,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..parent_close_pos.end,
         )
@@ -58,6 +59,7 @@ impl Parsable for AstVPathNode {
             variable: third_token.text.clone(),
             function: None,
             is_hierarchy: false,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..third_token.pos.end,
         )
@@ -70,6 +72,7 @@ impl Parsable for AstVPathNode {
             variable: third_token.text.clone(),
             function: None,
             is_hierarchy: true,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..third_token.pos.end,
         )
@@ -82,6 +85,7 @@ impl Parsable for AstVPathNode {
             variable: None,
             function: None,
             is_hierarchy: false,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..second_token.pos.end,
         )
@@ -95,6 +99,7 @@ impl Parsable for AstVPathNode {
             variable: None,
             function: second_token.text.clone(),
             is_hierarchy: false,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..parent_close_pos.end,
         )
@@ -107,6 +112,7 @@ impl Parsable for AstVPathNode {
             variable: second_token.text.clone(),
             function: None,
             is_hierarchy: false,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..second_token.pos.end,
         )
@@ -119,6 +125,7 @@ impl Parsable for AstVPathNode {
             variable: second_token.text.clone(),
             function: None,
             is_hierarchy: true,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.start..second_token.pos.end,
         )
@@ -131,6 +138,7 @@ impl Parsable for AstVPathNode {
             variable: None,
             function: None,
             is_hierarchy: false,
+            hierarchy: NodeRef(-1).into(),
           },
           first_token.pos.clone(),
         )
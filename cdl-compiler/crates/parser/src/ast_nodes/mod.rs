@@ -1,5 +1,6 @@
 pub mod ast_boolean;
 pub mod ast_color;
+pub mod ast_const;
 pub mod ast_entity;
 pub mod ast_formula;
 pub mod ast_function;
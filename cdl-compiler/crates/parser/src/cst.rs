@@ -0,0 +1,521 @@
+use std::{
+  fmt::{self, Display},
+  ops::Range,
+};
+
+use anyhow::Result;
+use ast::{Ast, CommentPlace, Comments, Node, NodeRef};
+use lexer::{LexedStr, Token, TokenKind};
+
+use crate::parse_text;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+  Whitespace,
+  Eol,
+  LineComment,
+  MultiLineComment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+  pub kind: TriviaKind,
+  pub text: LexedStr,
+  pub pos: Range<usize>,
+}
+
+/// A significant token together with the trivia around it. Trailing trivia runs up to and
+/// including the first end of line after the token, everything after that leads the next token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+  pub kind: TokenKind,
+  pub text: LexedStr,
+  pub pos: Range<usize>,
+  pub leading_trivia: Vec<Trivia>,
+  pub trailing_trivia: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CstElement {
+  Node(CstNode),
+  Token(CstToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstNode {
+  pub node_ref: NodeRef,
+  pub pos: Range<usize>,
+  pub children: Vec<CstElement>,
+}
+
+/// Lossless syntax tree. Printing it gives back the parsed text byte for byte, every node
+/// points at the `Ast` node it was built from.
+#[derive(Debug, Clone)]
+pub struct Cst {
+  pub root: CstNode,
+  pub eof_trivia: Vec<Trivia>,
+  ast: Ast,
+}
+
+impl Cst {
+  pub(crate) fn new(text: &str, tokens: &[Token], ast: Ast, root: NodeRef) -> Cst {
+    let (tokens, eof_trivia) = attach_trivia(text, tokens);
+    let mut builder = CstBuilder {
+      ast: &ast,
+      tokens: tokens.into_iter().peekable(),
+    };
+    let mut root = builder.build_node(root, 0..text.len());
+    root
+      .children
+      .extend(builder.tokens.by_ref().map(CstElement::Token));
+    Cst {
+      root,
+      eof_trivia,
+      ast,
+    }
+  }
+
+  pub fn ast(&self) -> &Ast {
+    &self.ast
+  }
+
+  pub fn into_ast(self) -> Ast {
+    self.ast
+  }
+
+  /// Parses the current text of the tree again, for use after the tree has been edited.
+  pub fn to_ast(&self) -> Result<Ast> {
+    parse_text(&self.to_string())
+  }
+
+  pub fn tokens(&self) -> Vec<&CstToken> {
+    let mut tokens = vec![];
+    self.root.collect_tokens(&mut tokens);
+    tokens
+  }
+
+  /// The comments in the text, attached to the statements they belong to, for printing with
+  /// `Ast::to_cdl_with_comments`. Comments inside a statement move to the line before it.
+  pub fn comments(&self) -> Comments {
+    let mut comments = Comments::new();
+    let root = Statement {
+      node_ref: self.root.node_ref,
+      before: self.root.node_ref,
+      is_entity: false,
+      last: None,
+    };
+    self.root.collect_comments(&self.ast, &root, &mut comments);
+    for trivia in self.eof_trivia.iter().filter(|t| t.is_comment()) {
+      comments.add(
+        root.node_ref,
+        CommentPlace::BeforeClose,
+        trivia.comment_text(),
+      );
+    }
+    comments
+  }
+}
+
+/// The statement comments inside it are attached to.
+#[derive(Clone, Copy)]
+struct Statement {
+  node_ref: NodeRef,
+  /// Where comments before the statement go, entities used as values print theirs before the
+  /// property they are in.
+  before: NodeRef,
+  is_entity: bool,
+  last: Option<usize>,
+}
+
+impl Statement {
+  fn place_leading(&self, token: &CstToken) -> (NodeRef, CommentPlace) {
+    if self.is_entity && token.kind == TokenKind::BraceClose && self.is_last(token) {
+      (self.node_ref, CommentPlace::BeforeClose)
+    } else {
+      (self.before, CommentPlace::Before)
+    }
+  }
+
+  fn place_trailing(&self, token: &CstToken) -> (NodeRef, CommentPlace) {
+    match self.is_entity {
+      true if token.kind == TokenKind::BraceOpen => (self.node_ref, CommentPlace::EndOfLine),
+      true if self.is_last(token) => (self.node_ref, CommentPlace::AfterClose),
+      false if self.is_last(token) => (self.node_ref, CommentPlace::EndOfLine),
+      _ => (self.before, CommentPlace::Before),
+    }
+  }
+
+  fn is_last(&self, token: &CstToken) -> bool {
+    self.last == Some(token.pos.start)
+  }
+}
+
+impl CstNode {
+  fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a CstToken>) {
+    for child in &self.children {
+      match child {
+        CstElement::Node(node) => node.collect_tokens(tokens),
+        CstElement::Token(token) => tokens.push(token),
+      }
+    }
+  }
+
+  fn collect_comments(&self, ast: &Ast, outer: &Statement, comments: &mut Comments) {
+    let node_data = ast
+      .get_node(self.node_ref)
+      .map(|node| node.node_data.clone());
+    let statement = match node_data {
+      Some(
+        Node::Title(_) | Node::Entity(_) | Node::Property(_) | Node::TableAlias(_) | Node::Const(_),
+      ) => {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        let is_entity = matches!(node_data, Some(Node::Entity(_)));
+        let is_value = matches!(
+          ast
+            .get_node(outer.node_ref)
+            .map(|node| node.node_data.clone()),
+          Some(Node::Property(_))
+        );
+        Statement {
+          node_ref: self.node_ref,
+          before: if is_value {
+            outer.before
+          } else {
+            self.node_ref
+          },
+          is_entity,
+          last: tokens.last().map(|token| token.pos.start),
+        }
+      }
+      _ => *outer,
+    };
+    for child in &self.children {
+      match child {
+        CstElement::Node(node) => node.collect_comments(ast, &statement, comments),
+        CstElement::Token(token) => {
+          let leading = statement.place_leading(token);
+          for trivia in token.leading_trivia.iter().filter(|t| t.is_comment()) {
+            comments.add(leading.0, leading.1, trivia.comment_text());
+          }
+          let trailing = statement.place_trailing(token);
+          for trivia in token.trailing_trivia.iter().filter(|t| t.is_comment()) {
+            comments.add(trailing.0, trailing.1, trivia.comment_text());
+          }
+        }
+      }
+    }
+  }
+}
+
+impl Trivia {
+  pub fn is_comment(&self) -> bool {
+    matches!(
+      self.kind,
+      TriviaKind::LineComment | TriviaKind::MultiLineComment
+    )
+  }
+
+  fn comment_text(&self) -> String {
+    self.text.as_str().trim_end().to_string()
+  }
+}
+
+impl Display for Trivia {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.text)
+  }
+}
+
+impl Display for CstToken {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for trivia in &self.leading_trivia {
+      write!(f, "{}", trivia)?;
+    }
+    write!(f, "{}", self.text)?;
+    for trivia in &self.trailing_trivia {
+      write!(f, "{}", trivia)?;
+    }
+    Ok(())
+  }
+}
+
+impl Display for CstNode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for child in &self.children {
+      match child {
+        CstElement::Node(node) => write!(f, "{}", node)?,
+        CstElement::Token(token) => write!(f, "{}", token)?,
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Display for Cst {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.root)?;
+    for trivia in &self.eof_trivia {
+      write!(f, "{}", trivia)?;
+    }
+    Ok(())
+  }
+}
+
+fn trivia_kind(kind: &TokenKind) -> Option<TriviaKind> {
+  match kind {
+    TokenKind::EOL => Some(TriviaKind::Eol),
+    TokenKind::LineComment => Some(TriviaKind::LineComment),
+    TokenKind::MultiLineComment => Some(TriviaKind::MultiLineComment),
+    _ => None,
+  }
+}
+
+fn attach_trivia(text: &str, tokens: &[Token]) -> (Vec<CstToken>, Vec<Trivia>) {
+  let mut attacher = TriviaAttacher::default();
+  let mut last_end = 0;
+  for token in tokens {
+    if last_end < token.pos.start {
+      attacher.push_trivia(whitespace(text, last_end..token.pos.start));
+    }
+    last_end = token.pos.end;
+    let token_text = LexedStr::from(&text[token.pos.clone()]);
+    match trivia_kind(&token.kind) {
+      Some(kind) => attacher.push_trivia(Trivia {
+        kind,
+        text: token_text,
+        pos: token.pos.clone(),
+      }),
+      None => attacher.push_token(token.kind.clone(), token_text, token.pos.clone()),
+    }
+  }
+  if last_end < text.len() {
+    attacher.push_trivia(whitespace(text, last_end..text.len()));
+  }
+  (attacher.tokens, attacher.pending)
+}
+
+#[derive(Default)]
+struct TriviaAttacher {
+  tokens: Vec<CstToken>,
+  pending: Vec<Trivia>,
+  in_trailing: bool,
+}
+
+impl TriviaAttacher {
+  fn push_trivia(&mut self, trivia: Trivia) {
+    let is_eol = trivia.kind == TriviaKind::Eol;
+    match self.tokens.last_mut() {
+      Some(last) if self.in_trailing => last.trailing_trivia.push(trivia),
+      _ => self.pending.push(trivia),
+    }
+    if is_eol {
+      self.in_trailing = false;
+    }
+  }
+
+  fn push_token(&mut self, kind: TokenKind, text: LexedStr, pos: Range<usize>) {
+    self.tokens.push(CstToken {
+      kind,
+      text,
+      pos,
+      leading_trivia: std::mem::take(&mut self.pending),
+      trailing_trivia: vec![],
+    });
+    self.in_trailing = true;
+  }
+}
+
+fn whitespace(text: &str, pos: Range<usize>) -> Trivia {
+  Trivia {
+    kind: TriviaKind::Whitespace,
+    text: LexedStr::from(&text[pos.clone()]),
+    pos,
+  }
+}
+
+struct CstBuilder<'a, I: Iterator<Item = CstToken>> {
+  ast: &'a Ast,
+  tokens: std::iter::Peekable<I>,
+}
+
+impl<'a, I: Iterator<Item = CstToken>> CstBuilder<'a, I> {
+  fn build_node(&mut self, node_ref: NodeRef, bounds: Range<usize>) -> CstNode {
+    let pos = clamp(self.ast.get_pos_for_node(node_ref), &bounds);
+    let mut child_refs = self
+      .ast
+      .get_node(node_ref)
+      .map(|node| node.node_data.children())
+      .unwrap_or_default();
+    child_refs.sort_by_key(|child| self.ast.get_pos_for_node(*child).start);
+
+    let mut children = vec![];
+    for child_ref in child_refs {
+      let child_pos = clamp(self.ast.get_pos_for_node(child_ref), &pos);
+      self.take_tokens_before(child_pos.start, &mut children);
+      children.push(CstElement::Node(self.build_node(child_ref, child_pos)));
+    }
+    self.take_tokens_before(pos.end, &mut children);
+    CstNode {
+      node_ref,
+      pos,
+      children,
+    }
+  }
+
+  fn take_tokens_before(&mut self, end: usize, children: &mut Vec<CstElement>) {
+    while let Some(token) = self.tokens.next_if(|token| token.pos.start < end) {
+      children.push(CstElement::Token(token));
+    }
+  }
+}
+
+fn clamp(pos: Range<usize>, bounds: &Range<usize>) -> Range<usize> {
+  let start = pos.start.clamp(bounds.start, bounds.end);
+  let end = pos.end.clamp(start, bounds.end);
+  start..end
+}
+
+#[cfg(test)]
+mod tests {
+  use ast::Node;
+
+  use crate::parse_cst;
+
+  use super::*;
+
+  #[test]
+  fn round_trips_test_scripts() {
+    let files = [
+      include_str!("../../../test_script/test.cdl"),
+      include_str!("../../../test_script/workforce.cdl"),
+      include_str!("../../../test_script/canvas_example.cdl"),
+      include_str!("../../../test_script/large.cdl"),
+    ];
+    for file in files {
+      let cst = parse_cst(file).unwrap();
+      assert_eq!(file, cst.to_string());
+    }
+  }
+
+  #[test]
+  fn keeps_comments_and_whitespace_as_trivia() {
+    let text =
+      "// header\nwidget kpi {\n  label: \"foo\" // trailing\n\n  /* lead */ size: small\n}\n\n";
+    let cst = parse_cst(text).unwrap();
+    assert_eq!(text, cst.to_string());
+
+    let tokens = cst.tokens();
+    let widget = tokens[0];
+    assert_eq!("widget", widget.text.as_str());
+    assert_eq!(TriviaKind::LineComment, widget.leading_trivia[0].kind);
+
+    let foo = tokens
+      .iter()
+      .find(|t| t.text.as_str() == "\"foo\"")
+      .unwrap();
+    let trailing = foo
+      .trailing_trivia
+      .iter()
+      .map(|t| t.kind)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        TriviaKind::Whitespace,
+        TriviaKind::LineComment,
+        TriviaKind::Eol
+      ],
+      trailing
+    );
+
+    let size = tokens.iter().find(|t| t.text.as_str() == "size").unwrap();
+    assert_eq!(
+      "\n  /* lead */ ",
+      size
+        .leading_trivia
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<String>()
+    );
+    assert_eq!(1, cst.eof_trivia.len());
+  }
+
+  #[test]
+  fn nodes_point_to_the_ast() {
+    let cst = parse_cst("widget kpi {\n  value: (1 + 2) * 3\n}\n").unwrap();
+    let entity = match &cst.root.children[0] {
+      CstElement::Node(node) => node,
+      _ => panic!("expected entity node"),
+    };
+    assert!(matches!(
+      cst.ast().get_node(entity.node_ref).unwrap().node_data,
+      Node::Entity(_)
+    ));
+    let property = entity
+      .children
+      .iter()
+      .find_map(|c| match c {
+        CstElement::Node(node) => Some(node),
+        _ => None,
+      })
+      .unwrap();
+    assert_eq!("  value: (1 + 2) * 3\n", property.to_string());
+    assert_eq!(
+      "widget kpi {\n  value: (1 + 2) * 3\n}\n",
+      entity.to_string()
+    );
+  }
+
+  #[test]
+  fn comments_print_with_their_statements() {
+    let text = "// header
+title \"t\"
+config hub {
+  // alias
+  table a = b.c
+}
+widget kpi { // open
+  /* lead */ label: \"foo\" // label
+  size: widget, {
+    // nested
+    size: small
+  } // value
+  // last
+} // close
+// end
+";
+    let cst = parse_cst(text).unwrap();
+    let cdl = cst
+      .ast()
+      .to_cdl_with_comments(ast::DEFAULT_WIDTH, &cst.comments())
+      .unwrap();
+    assert_eq!(
+      "// header
+title \"t\"
+config hub {
+  // alias
+  table a = b.c
+}
+widget kpi { // open
+  /* lead */
+  label: \"foo\" // label
+  size: widget {
+    // nested
+    size: small
+  } // value
+  // last
+} // close
+// end
+",
+      cdl
+    );
+  }
+
+  #[test]
+  fn can_derive_ast_again() {
+    let text = "widget kpi {\n  label: \"foo\" // comment\n}\n";
+    let cst = parse_cst(text).unwrap();
+    let ast = cst.to_ast().unwrap();
+    assert_eq!(cst.ast().to_cdl().unwrap(), ast.to_cdl().unwrap());
+  }
+}
@@ -1,27 +1,75 @@
 mod ast_nodes;
+mod cst;
+mod options;
 mod parse_expr;
 mod parser;
 mod token_stream;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use ast::Ast;
-use lexer::lex;
+use lexer::{into_owned_tokens, lex_with_max_tokens, BorrowedToken, Token};
 use parser::Parser;
 use token_stream::TokenStream;
 
+pub use cst::*;
+pub use options::*;
+pub use parser::ParseError;
+
 #[tracing::instrument(name = "parsing", skip(text))]
 pub fn parse_text(text: &str) -> Result<Ast> {
-  let tokens = lex(text)?;
-  let mut parser = Parser::new(text, TokenStream::new(tokens));
+  parse_text_with_options(text, &ParseOptions::default())
+}
+
+/// Like [`parse_text`], failing with [`LimitExceeded`] when the input goes over one of the limits.
+#[tracing::instrument(name = "parsing_with_options", skip(text))]
+pub fn parse_text_with_options(text: &str, options: &ParseOptions) -> Result<Ast> {
+  let tokens = lex_with_options(text, options)?;
+  let mut parser = Parser::with_options(text, TokenStream::new(tokens), options.clone());
+  parser.parse()?;
+
+  Ok(parser.ast)
+}
+
+fn lex_with_options(text: &str, options: &ParseOptions) -> Result<Vec<Token>> {
+  if text.len() > options.max_size {
+    bail!(LimitExceeded {
+      limit: Limit::Size,
+      max: options.max_size,
+    });
+  }
+  lex_with_max_tokens(text, options.max_tokens)
+}
+
+/// Parses tokens from [`lexer::lex_borrowed`]. The tokens are first converted with
+/// [`lexer::into_owned_tokens`], so token text is still copied, but only once per distinct string
+/// where [`parse_text`] copies it for every token.
+#[tracing::instrument(name = "parsing_tokens", skip(text, tokens))]
+pub fn parse_tokens(text: &str, tokens: Vec<BorrowedToken<'_>>) -> Result<Ast> {
+  let mut parser = Parser::new(text, TokenStream::new(into_owned_tokens(tokens)));
   parser.parse()?;
 
   Ok(parser.ast)
 }
 
+#[tracing::instrument(name = "parsing_cst", skip(text))]
+pub fn parse_cst(text: &str) -> Result<Cst> {
+  parse_cst_with_options(text, &ParseOptions::default())
+}
+
+/// Like [`parse_cst`], with the limits of [`parse_text_with_options`].
+#[tracing::instrument(name = "parsing_cst_with_options", skip(text))]
+pub fn parse_cst_with_options(text: &str, options: &ParseOptions) -> Result<Cst> {
+  let tokens = lex_with_options(text, options)?;
+  let mut parser = Parser::with_options(text, TokenStream::new(tokens.clone()), options.clone());
+  let root = parser.parse()?;
+
+  Ok(Cst::new(text, &tokens, parser.ast, root))
+}
+
 #[cfg(test)]
 mod tests {
 
-  use ast::{Node, NodeRef};
+  use ast::{AstOperatorNode, Node, NodeRef, Operator};
 
   use super::*;
 
@@ -76,6 +124,59 @@ mod tests {
     println!("{}", cdl);
   }
 
+  #[test]
+  fn to_cdl_prints_what_it_parsed() {
+    let ast = parse!(
+      r#"config hub {
+  table alias1 = dataset.table
+}
+maintype subtype "label" @ref1 @ref2 #id 3245 {
+  prop: ident
+  prop: "string"
+  prop: 1234
+  prop: true
+  prop: #aabbcc
+  prop: @foo.bar
+  prop: table:variable
+  prop: :^variable
+  prop: func()
+  prop: func(foo, bar)
+  prop: 1 + 2 * 3
+  prop: first, second
+  prop: widget {
+    size: small
+  }
+}
+"#
+    );
+    let cdl = ast.to_cdl().unwrap();
+    assert_eq!(
+      r#"config hub {
+  table alias1 = dataset.table
+}
+maintype subtype "label" @ref1 @ref2 #id 3245 {
+  prop: ident
+  prop: "string"
+  prop: 1234
+  prop: true
+  prop: #aabbcc
+  prop: @foo.bar
+  prop: table:variable
+  prop: :^variable
+  prop: func()
+  prop: func(foo, bar)
+  prop: 1 + 2 * 3
+  prop: first, second
+  prop: widget {
+    size: small
+  }
+}
+"#,
+      cdl
+    );
+    assert_eq!(cdl, parse_text(&cdl).unwrap().to_cdl().unwrap());
+  }
+
   #[test]
   fn can_parse_title() {
     parse!("title \"dashboard title\"\n");
@@ -153,6 +254,49 @@ mod tests {
     }
   }
 
+  #[test]
+  fn string_values_are_unescaped() {
+    let ast = parse!(
+      r#"maintype {
+        prop: "a \"b\"\n\u{f8}"
+    }
+    "#
+    );
+    if let Node::String(node) = node_data!(ast, 3) {
+      assert_eq!(r#""a \"b\"\n\u{f8}""#, node.text.to_string());
+      assert_eq!("a \"b\"\nø", node.value.to_string());
+    } else {
+      panic!("expected string node");
+    }
+  }
+
+  #[test]
+  fn keeps_raw_text_of_literals() {
+    let ast = parse!(
+      r#"maintype #abcdef12 {
+        a: 1.50
+        b: 25%
+        c: #11223344
+        d: r"C:\temp"
+    }
+    "#
+    );
+    if let Node::Entity(node) = node_data!(ast, 1) {
+      assert_eq!("abcdef12", node.ident.as_ref().unwrap().to_string());
+    }
+    if let Node::Number(node) = node_data!(ast, 5) {
+      assert_eq!(0.25, node.value);
+      assert_eq!("25%", node.raw.as_ref().unwrap().to_string());
+    } else {
+      panic!("expected number node");
+    }
+    let cdl = ast.to_cdl().unwrap();
+    assert!(cdl.contains("a: 1.50\n"));
+    assert!(cdl.contains("b: 25%\n"));
+    assert!(cdl.contains("c: #11223344\n"));
+    assert!(cdl.contains(r#"d: r"C:\temp""#));
+  }
+
   #[test]
   fn can_parse_property_number() {
     let ast = parse!(
@@ -305,7 +449,10 @@ mod tests {
     );
     if let Node::Function(node) = node_data!(ast, 3) {
       assert_eq!("func", node.name.to_string());
-      assert_eq!(vec![NodeRef(4), NodeRef(5), NodeRef(6)], node.children.borrow().clone());
+      assert_eq!(
+        vec![NodeRef(4), NodeRef(5), NodeRef(6)],
+        node.children.borrow().clone()
+      );
     }
   }
 
@@ -318,7 +465,10 @@ mod tests {
     "#
     );
     if let Node::Property(node) = node_data!(ast, 3) {
-      assert_eq!(vec![NodeRef(4), NodeRef(5), NodeRef(6)], node.children.borrow().clone());
+      assert_eq!(
+        vec![NodeRef(4), NodeRef(5), NodeRef(6)],
+        node.children.borrow().clone()
+      );
     }
   }
   #[test]
@@ -396,6 +546,16 @@ mod tests {
     assert!(ast.is_ok());
   }
 
+  #[test]
+  fn can_parse_borrowed_tokens() {
+    let file = include_str!("../../../test_script/test.cdl");
+    let ast = parse_tokens(file, lexer::lex_borrowed(file).unwrap()).unwrap();
+    assert_eq!(
+      parse_text(file).unwrap().to_cdl().unwrap(),
+      ast.to_cdl().unwrap()
+    );
+  }
+
   #[test]
   fn can_parse_large_expr() {
     parse!(
@@ -405,4 +565,255 @@ mod tests {
   "#
     );
   }
+
+  #[test]
+  fn parse_error_reports_line_and_column() {
+    let err = parse_text("widget kpi {\n  label: \"foo\"\n  : bar\n}").unwrap_err();
+    assert_eq!("Error while parsing at 3:3", err.to_string());
+  }
+
+  #[test]
+  fn node_locations_have_line_and_column() {
+    let ast = parse!("widget kpi {\n  label: \"æøå\"\n}");
+    let location = ast.get_location_for_node(NodeRef(3)).unwrap();
+    assert_eq!(2, location.start_line);
+    assert_eq!(10, location.start_pos);
+    assert_eq!(15, location.end_pos);
+    let json = serde_json::to_value(&ast).unwrap();
+    assert_eq!(2, json["locations"][3]["start_line"]);
+    assert_eq!(10, json["locations"][3]["start_pos"]);
+  }
+
+  fn expression_to_cdl(expr: &str) -> String {
+    let ast = parse_text(&format!("widget kpi {{\n  value: {}\n}}\n", expr)).unwrap();
+    let cdl = ast.to_cdl().unwrap();
+    let line = cdl.lines().nth(1).unwrap();
+    line.trim_start_matches("  value: ").to_string()
+  }
+
+  #[test]
+  fn multiplication_binds_tighter_than_addition() {
+    let ast = parse!("widget kpi {\n  value: 1 + 2 * 3\n}\n");
+    if let Node::Operator(op) = node_data!(ast, 4) {
+      assert_eq!(Operator::Plus, op.operator);
+      assert!(matches!(
+        ast.get_node(op.right.get()).unwrap().node_data,
+        Node::Operator(AstOperatorNode {
+          operator: Operator::Mul,
+          ..
+        })
+      ));
+      assert_eq!(vec![NodeRef(4)], ast.get_parent(op.left.get()));
+    } else {
+      panic!("expected operator node");
+    }
+  }
+
+  #[test]
+  fn operators_are_left_associative() {
+    let ast = parse!("widget kpi {\n  value: 1 - 2 - 3\n}\n");
+    let value = ast.get_node(NodeRef(2)).unwrap().node_data.children()[0];
+    if let Node::Operator(op) = &ast.get_node(value).unwrap().node_data {
+      assert!(matches!(
+        ast.get_node(op.left.get()).unwrap().node_data,
+        Node::Operator(_)
+      ));
+    } else {
+      panic!("expected operator node");
+    }
+  }
+
+  #[test]
+  fn prints_minimal_parentheses() {
+    assert_eq!("1 + 2 * 3", expression_to_cdl("1 + (2 * 3)"));
+    assert_eq!("(1 + 2) * 3", expression_to_cdl("(1 + 2) * 3"));
+    assert_eq!("1 - (2 - 3)", expression_to_cdl("1 - (2 - 3)"));
+    assert_eq!("1 - 2 - 3", expression_to_cdl("(1 - 2) - 3"));
+    assert_eq!(
+      "a OR b AND c = 1",
+      expression_to_cdl("a or (b and (c = 1))")
+    );
+    assert_eq!("(a OR b) AND c", expression_to_cdl("(a or b) and c"));
+    assert_eq!("10 % 3 + 1", expression_to_cdl("10 % 3 + 1"));
+  }
+
+  #[test]
+  fn wraps_long_expressions() {
+    let text = "widget kpi {\n  value: iif(selected(q1:, code1), \"Detractor\", iif(selected(q1:, code2), \"Passive\", \"Promoter\"))\n  filter: :region = 1 AND :year = 2 AND NOT (:a = 3 OR :b = 4)\n  size: small, medium\n}\nconst total = count(q1:) + count(q2:) + count(q3:)\n";
+    let ast = parse_text(text).unwrap();
+    let wrapped = ast.to_cdl_with_width(40).unwrap();
+    assert_eq!(
+      r#"widget kpi {
+  value: iif(
+    selected(q1:, code1),
+    "Detractor",
+    iif(
+      selected(q1:, code2),
+      "Passive",
+      "Promoter"
+    )
+  )
+  filter: :region = 1 AND
+    :year = 2 AND
+    NOT (:a = 3 OR :b = 4)
+  size: small, medium
+}
+const total = count(q1:) +
+  count(q2:) +
+  count(q3:)
+"#,
+      wrapped
+    );
+    assert_eq!(text, ast.to_cdl_with_width(200).unwrap());
+    let reparsed = parse_text(&wrapped).unwrap();
+    assert_eq!(text, reparsed.to_cdl().unwrap());
+    assert_eq!(wrapped, parse_cst(&wrapped).unwrap().to_string());
+  }
+
+  #[test]
+  fn wrapped_test_script_parses_the_same() {
+    let file = include_str!("../../../test_script/test.cdl");
+    let ast = parse_text(file).unwrap();
+    let wrapped = ast.to_cdl_with_width(ast::DEFAULT_WIDTH).unwrap();
+    assert!(wrapped.lines().count() > ast.to_cdl().unwrap().lines().count());
+    assert_eq!(
+      ast.to_cdl().unwrap(),
+      parse_text(&wrapped).unwrap().to_cdl().unwrap()
+    );
+  }
+
+  #[test]
+  fn can_parse_unary_operators() {
+    assert_eq!("-a * b", expression_to_cdl("-a * b"));
+    assert_eq!("-(a + b)", expression_to_cdl("-(a + b)"));
+    assert_eq!("NOT a = 1 OR b", expression_to_cdl("not (a = 1) or b"));
+    assert_eq!("NOT (a OR b)", expression_to_cdl("NOT(a or b)"));
+    assert_eq!("a - 1", expression_to_cdl("a -1"));
+  }
+
+  #[test]
+  fn not_is_an_identifier_outside_prefix_position() {
+    let ast = parse!("widget kpi {\n  not: 1\n  value: not, NOT x\n}\n");
+    if let Node::Property(prop) = node_data!(ast, 2) {
+      assert_eq!("not", prop.name.to_string());
+    } else {
+      panic!("expected property node");
+    }
+    assert_eq!(
+      "widget kpi {\n  not: 1\n  value: not, NOT x\n}\n",
+      ast.to_cdl().unwrap()
+    );
+  }
+
+  #[test]
+  fn percent_after_a_space_is_the_remainder() {
+    let ast = parse!("widget kpi {\n  value: a % b\n}\n");
+    if let Node::Operator(op) = node_data!(ast, 4) {
+      assert_eq!(Operator::Mod, op.operator);
+    } else {
+      panic!("expected operator node");
+    }
+    assert_eq!("10 % 3", expression_to_cdl("10 % 3"));
+    assert_eq!("25%", expression_to_cdl("25%"));
+  }
+
+  #[test]
+  fn percent_without_a_space_is_not_the_remainder() {
+    assert!(parse_text("widget kpi {\n  value: 10%3\n}\n").is_err());
+    assert!(parse_text("widget kpi {\n  value: a%b\n}\n").is_err());
+  }
+
+  #[test]
+  fn can_parse_const() {
+    let text = "const threshold = 10 * 2\nconfig hub {\n  const color = #ff0000\n  hub: 1\n}\n";
+    let ast = parse_text(text).unwrap();
+    assert!(matches!(
+      ast.get_node(NodeRef(1)).unwrap().node_data,
+      Node::Const(_)
+    ));
+    assert_eq!(text, ast.to_cdl().unwrap());
+    assert_eq!(text, parse_cst(text).unwrap().to_string());
+  }
+
+  #[test]
+  fn const_only_allowed_in_config() {
+    let error = parse_text("widget kpi {\n  const a = 1\n}\n").unwrap_err();
+    let parse_error = error.downcast_ref::<ParseError>().unwrap();
+    assert_eq!(2, parse_error.location.start_line);
+    assert_eq!(3, parse_error.location.start_pos);
+    assert_eq!(
+      "const is only allowed at the top level and in config blocks",
+      error.root_cause().to_string()
+    );
+  }
+
+  fn limit_of(error: &anyhow::Error) -> Option<Limit> {
+    error.downcast_ref::<LimitExceeded>().map(|e| e.limit)
+  }
+
+  #[test]
+  fn deep_nesting_fails_without_overflowing() {
+    let depth = 10_000;
+    let text = format!(
+      "widget kpi {{\n  value: {}1{}\n}}\n",
+      "(".repeat(depth),
+      ")".repeat(depth)
+    );
+    let error = parse_text(&text).unwrap_err();
+    assert_eq!(Some(Limit::Depth), limit_of(&error));
+    assert!(error.downcast_ref::<ParseError>().is_some());
+
+    let text = format!("{}{}", "a {\n".repeat(depth), "}\n".repeat(depth));
+    let error = parse_text(&text).unwrap_err();
+    assert_eq!(Some(Limit::Depth), limit_of(&error));
+
+    let text = format!("widget kpi {{\n  value: {}1\n}}\n", "NOT ".repeat(depth));
+    assert_eq!(
+      Some(Limit::Depth),
+      limit_of(&parse_text(&text).unwrap_err())
+    );
+  }
+
+  #[test]
+  fn nesting_up_to_the_limit_parses() {
+    let options = ParseOptions::default();
+    // the entity and the property value take two levels
+    let depth = options.max_depth - 2;
+    let text = format!(
+      "widget kpi {{\n  value: {}1{}\n}}\n",
+      "(".repeat(depth),
+      ")".repeat(depth)
+    );
+    assert!(parse_text_with_options(&text, &options).is_ok());
+  }
+
+  #[test]
+  fn checks_size_token_and_node_limits() {
+    let text = "widget kpi {\n  value: 1, 2, 3, 4\n}\n";
+    let limited =
+      |options: ParseOptions| limit_of(&parse_text_with_options(text, &options).unwrap_err());
+    let options = ParseOptions::default();
+    assert!(parse_text_with_options(text, &options).is_ok());
+    assert_eq!(
+      Some(Limit::Size),
+      limited(ParseOptions {
+        max_size: 10,
+        ..options.clone()
+      })
+    );
+    assert_eq!(
+      Some(Limit::Tokens),
+      limited(ParseOptions {
+        max_tokens: 10,
+        ..options.clone()
+      })
+    );
+    assert_eq!(
+      Some(Limit::Nodes),
+      limited(ParseOptions {
+        max_nodes: 4,
+        ..options
+      })
+    );
+  }
 }
@@ -0,0 +1,38 @@
+pub use lexer::{Limit, LimitExceeded};
+
+/// Limits for parsing untrusted input. Going over a limit fails the parse with a
+/// [`LimitExceeded`] error instead of running out of stack or memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+  /// Max nesting of entities and expressions.
+  pub max_depth: usize,
+  pub max_tokens: usize,
+  /// Max size of the input in bytes.
+  pub max_size: usize,
+  pub max_nodes: usize,
+}
+
+impl Default for ParseOptions {
+  /// Only the nesting is limited, deep enough for any real script while keeping the parser well
+  /// inside the stack of a default thread.
+  fn default() -> Self {
+    ParseOptions {
+      max_depth: 128,
+      max_tokens: usize::MAX,
+      max_size: usize::MAX,
+      max_nodes: usize::MAX,
+    }
+  }
+}
+
+impl ParseOptions {
+  /// Limits suited for input from the network.
+  pub fn untrusted() -> ParseOptions {
+    ParseOptions {
+      max_depth: 64,
+      max_tokens: 2_000_000,
+      max_size: 16 * 1024 * 1024,
+      max_nodes: 2_000_000,
+    }
+  }
+}
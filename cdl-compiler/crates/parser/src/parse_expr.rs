@@ -1,7 +1,9 @@
 use crate::{
   ast_nodes::{
     ast_entity::{can_parse_anonymous_entity, parse_anonymous_entity},
-    ast_operator::{can_parse_factor, can_parse_term, parse_operator_factor, parse_operator_term},
+    ast_operator::{
+      can_parse_unary_operator, parse_binary_operator, parse_unary_operator, peek_binary_operator,
+    },
     Parsable,
   },
   parser::Parser,
@@ -24,11 +26,23 @@ pub fn parse_arg_list(parser: &mut Parser, parent: NodeRef) -> Result<Vec<NodeRe
       TokenKind::Comma => {
         let _ = parser.eat_token()?;
       }
+      TokenKind::EOL => {
+        let _ = parser.eat_token()?;
+      }
       _ => node_refs.push(parse_expression(parser, parent)?),
     }
   }
 }
 
+/// Line breaks are allowed inside parentheses and after binary operators, where the expression
+/// can't have ended.
+pub fn skip_line_breaks(parser: &mut Parser) -> Result<()> {
+  while parser.is_next_token_of_type(TokenKind::EOL) {
+    parser.eat_token()?;
+  }
+  Ok(())
+}
+
 pub fn parse_bracket_arg_list(parser: &mut Parser, _parent: NodeRef) -> Result<Vec<NodeRef>> {
   let node_refs = vec![];
   loop {
@@ -80,23 +94,39 @@ pub fn parse_list(parser: &mut Parser, parent: NodeRef) -> Result<Vec<NodeRef>>
 }
 
 pub fn parse_expression(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
-  let term_node_ref = parse_term(parser, parent)?;
-  if can_parse_term(parser) {
-    let operator_ref = parse_operator_term(parser, parent, term_node_ref)?;
-    Ok(operator_ref)
-  } else {
-    Ok(term_node_ref)
-  }
+  parse_expression_bp(parser, parent, 0)
 }
 
-pub fn parse_term(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
-  let factor_node_ref = parse_factor(parser, parent)?;
-  if can_parse_factor(parser) {
-    let operator_ref = parse_operator_factor(parser, parent, factor_node_ref)?;
-    Ok(operator_ref)
+/// Parses an expression, only taking binary operators binding tighter than `min_precedence`.
+pub fn parse_expression_bp(
+  parser: &mut Parser,
+  parent: NodeRef,
+  min_precedence: u8,
+) -> Result<NodeRef> {
+  parser.enter()?;
+  let left = if can_parse_unary_operator(parser) {
+    parse_unary_operator(parser, parent)?
   } else {
-    Ok(factor_node_ref)
+    parse_factor(parser, parent)?
+  };
+  let node_ref = parse_infix(parser, parent, left, min_precedence)?;
+  parser.leave();
+  Ok(node_ref)
+}
+
+pub fn parse_infix(
+  parser: &mut Parser,
+  parent: NodeRef,
+  mut left: NodeRef,
+  min_precedence: u8,
+) -> Result<NodeRef> {
+  while let Some(operator) = peek_binary_operator(parser) {
+    if operator.precedence() <= min_precedence {
+      break;
+    }
+    left = parse_binary_operator(parser, parent, left, operator)?;
   }
+  Ok(left)
 }
 
 pub fn parse_factor(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
@@ -139,11 +169,12 @@ pub fn parse_factor(parser: &mut Parser, parent: NodeRef) -> Result<NodeRef> {
   }
 
   if parser.is_next_token_of_type(TokenKind::ParenOpen) {
-    parser.eat_token()?;
+    let start = parser.eat_token()?;
+    skip_line_breaks(parser)?;
     let expr_node = parse_expression(parser, parent)?;
-    let location = parser.get_pos_for_node(expr_node);
+    skip_line_breaks(parser)?;
     let end = parser.eat_token_of_type(TokenKind::ParenClose)?;
-    parser.update_location_on_node(expr_node, location.start, end.end);
+    parser.update_location_on_node(expr_node, start.start, end.end);
     return Ok(expr_node);
   }
   //dbg!(parser.get_current_token());
@@ -1,44 +1,93 @@
-use std::ops::Range;
+use std::{cell::Cell, fmt, ops::Range, rc::Rc};
 
 use ast::{Ast, AstNode, AstScriptNode, NodeRef};
-use lexer::{get_location_from_position, Token, TokenKind};
+use lexer::{LineIndex, Location, Token, TokenKind};
+use serde::Serialize;
+
+use crate::{
+  ast_nodes::Parsable,
+  options::{Limit, LimitExceeded, ParseOptions},
+  token_stream::TokenStream,
+};
+use anyhow::Result;
+
+/// Context added to every parse error, pointing at the token the parser stopped at.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParseError {
+  pub pos: Range<usize>,
+  pub location: Location,
+}
 
-use crate::{ast_nodes::Parsable, token_stream::TokenStream};
-use anyhow::{Context, Result};
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Error while parsing at {}:{}",
+      self.location.start_line, self.location.start_pos
+    )
+  }
+}
 
 #[derive(Debug)]
 pub struct Parser {
-  text: String,
+  line_index: Rc<LineIndex>,
   tokens: TokenStream,
+  options: ParseOptions,
+  depth: Cell<usize>,
   pub ast: Ast,
 }
 
 impl Parser {
   pub fn new(text: &str, tokens: TokenStream) -> Parser {
+    Parser::with_options(text, tokens, ParseOptions::default())
+  }
+
+  pub fn with_options(text: &str, tokens: TokenStream, options: ParseOptions) -> Parser {
+    let line_index = Rc::new(LineIndex::new(text));
+    let mut ast = Ast::new();
+    ast.set_line_index(line_index.clone());
     Parser {
       tokens,
-      text: text.to_string(),
-      ast: Ast::new(),
+      options,
+      depth: Cell::new(0),
+      line_index,
+      ast,
     }
   }
   pub fn parse(&mut self) -> Result<NodeRef> {
-    AstScriptNode::parse(self, NodeRef(-1)).context(self.get_top_level_error_message())
-  }
-
-  fn get_top_level_error_message(&self) -> String {
-    let token = self.get_current_token();
-    if token.is_err() {
-      "Unknown error".to_string()
-    } else {
-      let token = token.unwrap();
-      let location = get_location_from_position(&self.text, &token.pos);
-      format!(
-        "Error while parsing at {}:{}",
-        location.start_line, location.start_pos
-      )
+    AstScriptNode::parse(self, NodeRef(-1)).map_err(|error| {
+      if error.downcast_ref::<ParseError>().is_some() {
+        error
+      } else {
+        error.context(self.get_top_level_error())
+      }
+    })
+  }
+
+  fn get_top_level_error(&self) -> ParseError {
+    let pos = match self.get_current_token() {
+      Ok(token) => token.pos.clone(),
+      Err(_) => self.text_len()..self.text_len(),
+    };
+    self.parse_error_at(pos)
+  }
+
+  fn parse_error_at(&self, pos: Range<usize>) -> ParseError {
+    ParseError {
+      location: self.line_index.location(&pos),
+      pos,
     }
   }
 
+  /// An error located at `pos` rather than at the token the parser stopped at.
+  pub(crate) fn error_at(&self, pos: Range<usize>, message: &str) -> anyhow::Error {
+    anyhow::anyhow!(message.to_string()).context(self.parse_error_at(pos))
+  }
+
+  pub(crate) fn text_len(&self) -> usize {
+    self.line_index.len()
+  }
+
   pub(crate) fn get_current_token(&self) -> Result<&Token> {
     self.tokens.get_current_token()
   }
@@ -63,6 +112,36 @@ impl Parser {
     self.tokens.is_next_token_of_type(kind)
   }
 
+  /// Called when starting on a nested entity or expression, must be paired with
+  /// [`Parser::leave`]. Also checks the node limit, as every node is added below one of these.
+  pub(crate) fn enter(&self) -> Result<()> {
+    let depth = self.depth.get() + 1;
+    if depth > self.options.max_depth {
+      return Err(
+        LimitExceeded {
+          limit: Limit::Depth,
+          max: self.options.max_depth,
+        }
+        .into(),
+      );
+    }
+    if self.ast.nodes.borrow().len() > self.options.max_nodes {
+      return Err(
+        LimitExceeded {
+          limit: Limit::Nodes,
+          max: self.options.max_nodes,
+        }
+        .into(),
+      );
+    }
+    self.depth.set(depth);
+    Ok(())
+  }
+
+  pub(crate) fn leave(&self) {
+    self.depth.set(self.depth.get() - 1);
+  }
+
   pub(crate) fn add_node(&self, n: AstNode, location: Range<usize>) -> NodeRef {
     self.ast.add_node(n, location)
   }
@@ -71,17 +150,16 @@ impl Parser {
     self.tokens.get_tokens_of_kind(kind)
   }
 
-  pub(crate) fn add_child_to_node(&self, parent: NodeRef, child: NodeRef) {
-    self.ast.add_child_to_node(parent, child);
+  pub(crate) fn set_parent_of_node(&self, node_ref: NodeRef, parent: NodeRef) {
+    self.ast.set_parent_of_node(node_ref, parent);
   }
 
-  pub(crate) fn is_tokens_left(&self) -> bool {
-    self.tokens.is_tokens_left()
+  pub(crate) fn add_child_to_node(&self, parent: NodeRef, child: NodeRef) {
+    self.ast.add_child_to_node(parent, child);
   }
 
   pub(crate) fn eat_eol_and_comments(&mut self) {
-    while self.is_tokens_left() {
-      let curr_token = self.get_current_token().unwrap();
+    while let Ok(curr_token) = self.get_current_token() {
       if curr_token.kind == TokenKind::EOL
         || curr_token.kind == TokenKind::LineComment
         || curr_token.kind == TokenKind::MultiLineComment
@@ -17,7 +17,7 @@ impl TokenStream {
     }
   }
   pub fn get_current_token(&self) -> Result<&Token> {
-    return self.get_nth_token(0);
+    self.get_nth_token(0)
   }
 
   pub fn get_nth_token(&self, num: usize) -> Result<&Token> {
@@ -62,13 +62,9 @@ impl TokenStream {
     let mut num_tokens = 0;
     loop {
       let curr_token = self.get_nth_token(num_tokens);
-      if curr_token.is_ok() {
-        let curr_token = curr_token.unwrap();
-        if curr_token.kind == kind {
-          num_tokens += 1;
-        } else {
-          break;
-        }
+      match curr_token {
+        Ok(curr_token) if curr_token.kind == kind => num_tokens += 1,
+        _ => break,
       }
     }
     if num_tokens > 0 {
@@ -79,14 +75,6 @@ impl TokenStream {
     &[]
   }
 
-  pub fn is_tokens_left(&self) -> bool {
-    let curr = self.curr_token.borrow();
-    if *curr + 1 < self.tokens.len() {
-      return true;
-    }
-    false
-  }
-
   pub fn is_next_token_of_type(&self, kind: TokenKind) -> bool {
     let curr_token = self.get_current_token();
     if curr_token.is_err() {
@@ -195,6 +183,14 @@ mod tests {
     assert_eq!(0..10, pos);
   }
 
+  #[test]
+  fn get_tokens_of_kind_stops_at_eof() {
+    let stream = TokenStream::new(create_tokens());
+    assert_eq!(2, stream.get_tokens_of_kind(TokenKind::Identifier).len());
+    let _ = stream.eat_tokens(2);
+    assert!(stream.get_tokens_of_kind(TokenKind::Identifier).is_empty());
+  }
+
   #[test]
   fn is_next_token_of_type() {
     let stream = TokenStream::new(create_tokens());
@@ -0,0 +1,50 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+pub use api::*;
+
+fn to_js<T: Serialize + ?Sized>(value: &T) -> Result<JsValue, JsValue> {
+  serde_wasm_bindgen::to_value(value).map_err(Into::into)
+}
+
+fn to_js_result<T: Serialize>(result: Result<T, Vec<Diagnostic>>) -> Result<JsValue, JsValue> {
+  match result {
+    Ok(value) => to_js(&value),
+    Err(diagnostics) => Err(to_js(&diagnostics)?),
+  }
+}
+
+#[wasm_bindgen(js_name = parse)]
+pub fn parse_js(text: &str) -> Result<JsValue, JsValue> {
+  to_js_result(api::parse(text))
+}
+
+#[wasm_bindgen(js_name = diagnostics)]
+pub fn diagnostics_js(text: &str) -> Result<JsValue, JsValue> {
+  to_js(&api::diagnostics(text))
+}
+
+#[wasm_bindgen(js_name = format)]
+pub fn format_js(text: &str) -> Result<JsValue, JsValue> {
+  to_js_result(api::format(text))
+}
+
+#[wasm_bindgen(js_name = select)]
+pub fn select_js(text: &str, property: &str) -> Result<JsValue, JsValue> {
+  to_js_result(api::select(text, property))
+}
+
+#[wasm_bindgen(js_name = toJson)]
+pub fn to_json_js(text: &str) -> Result<JsValue, JsValue> {
+  to_js_result(api::to_json(text))
+}
+
+#[wasm_bindgen(js_name = toModel)]
+pub fn to_model_js(text: &str, link_references: bool) -> Result<JsValue, JsValue> {
+  to_js_result(api::to_model(text, link_references))
+}
+
+#[wasm_bindgen(js_name = toTypescript)]
+pub fn to_typescript_js(text: &str) -> Result<JsValue, JsValue> {
+  to_js_result(api::to_typescript(text))
+}